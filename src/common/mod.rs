@@ -1,5 +1,7 @@
 pub mod bytes;
 pub mod dialer;
 pub mod hex;
+pub mod http;
 pub mod reader;
+pub mod secret;
 pub mod utils;
@@ -0,0 +1,67 @@
+use std::fmt;
+
+use zeroize::Zeroize;
+
+/// Holds a password, key or hash, zeroes it on drop and never prints it.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Secret<T: Zeroize>(T);
+
+pub type SecretString = Secret<String>;
+pub type SecretBytes<const N: usize> = Secret<[u8; N]>;
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    /// Borrow the secret, keep the borrow as short as possible.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<const N: usize> Secret<[u8; N]> {
+    /// Take the first `N` bytes of `bytes`, e.g. of a digest returned as a `Vec`.
+    pub fn copy_from_slice(bytes: &[u8]) -> Self {
+        let mut value = [0u8; N];
+        value.copy_from_slice(&bytes[..N]);
+        Secret(value)
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[test]
+fn test_secret_debug_is_redacted() {
+    let password = SecretString::from("hunter2");
+    let key = SecretBytes::<4>::new([1, 2, 3, 4]);
+    assert_eq!(password.expose(), "hunter2");
+    assert_eq!(format!("{:?}", password), "Secret([REDACTED])");
+    assert_eq!(
+        format!("{:?} {:?}", key, Some(&key)),
+        "Secret([REDACTED]) Some(Secret([REDACTED]))"
+    );
+    assert_eq!(SecretBytes::<2>::copy_from_slice(&[5, 6, 7]).expose(), &[5, 6]);
+}
@@ -0,0 +1,696 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::LevelFilter;
+use toml::{Table, Value};
+
+use crate::common::secret::SecretString;
+use crate::credentials::{self, PasswordSource};
+use crate::drcom::wired::dialer::{LoginAccount, HOSTNAME_MAX_LEN, SERVICE_PACK_MAX_LEN};
+use crate::drcom::wired::retransmit::{RetransmitPolicy, Step, StepPolicy};
+use crate::drcom::{PASSWORD_MAX_LEN, USERNAME_MAX_LEN};
+use crate::hooks::{HookEvent, Hooks};
+use crate::logging::LogFormat;
+use crate::probe::{ProbeTarget, Prober};
+use crate::socket::{self, SocketOptions};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IOError(io::Error),
+    ParseError(toml::de::Error),
+    UnknownKey(String),
+    InvalidValue { key: String, reason: String },
+    WorldReadable(PathBuf),
+}
+
+type ConfigResult<T> = Result<T, ConfigError>;
+
+/// Settings read from `drcom.toml`, every field is optional so that command
+/// line flags can fill in or override whatever the file leaves out.
+///
+/// ```toml
+/// server = "10.100.61.3:61440"
+/// username = "2019000000"
+/// interface = "eth0"
+///
+/// [account]
+/// mac_address = "b8:88:e3:05:16:80"
+/// client_version = 0x0a
+///
+/// [retransmit]
+/// timeout = 3
+/// login = "5:1"
+///
+/// [probe]
+/// url = "http://connect.rom.miui.com/generate_204"
+/// interval = 60
+///
+/// [hooks]
+/// online = "systemctl restart wg-quick@wg0"
+/// offline = ["resolvectl flush-caches", "notify-send 'drcom offline'"]
+/// ```
+#[derive(Debug, Default)]
+pub struct Config {
+    pub server:             Option<String>,
+    pub username:           Option<String>,
+    pub password:           Option<PasswordSource>,
+    pub host_ip:            Option<Ipv4Addr>,
+    pub interface:          Option<String>,
+    /// Where `/sys`, `/proc` and `/etc` are read from when looking up `interface`.
+    pub system_root:        Option<PathBuf>,
+    pub bind_address:       Option<SocketAddrV4>,
+    pub bind_device:        Option<String>,
+    /// Whether to bind an ephemeral port when `bind_address` is taken.
+    pub ephemeral_port:     Option<bool>,
+    /// Whether to set SO_REUSEADDR, see `SocketOptions::reuse_address`.
+    pub reuse_address:      Option<bool>,
+    pub heartbeat_interval: Option<Duration>,
+    pub log_level:          Option<LevelFilter>,
+    pub log_format:         Option<LogFormat>,
+    pub control_socket:     Option<PathBuf>,
+    pub metrics_listen:     Option<SocketAddr>,
+    pub retransmit:         RetransmitConfig,
+    pub probe:              ProbeConfig,
+    pub hooks:              HookConfig,
+    pub account:            AccountConfig,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RetransmitConfig {
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    pub steps:   Vec<(Step, StepPolicy)>,
+}
+
+/// Connectivity check next to the heartbeat, see `Prober`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProbeConfig {
+    pub target:   Option<ProbeTarget>,
+    pub interval: Option<Duration>,
+    pub timeout:  Option<Duration>,
+    pub failures: Option<u32>,
+}
+
+/// Commands run on supervisor transitions, see `Hooks`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HookConfig {
+    pub timeout:  Option<Duration>,
+    pub commands: Vec<(HookEvent, String)>,
+}
+
+macro_rules! account_config {
+    (
+        $( $field:ident: $ty:ty => $parse:expr ),*
+    ) => {
+        /// Overrides for the `LoginAccount` knobs that have to match the official client.
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct AccountConfig {
+            pub ipaddresses: Option<Vec<Ipv4Addr>>,
+            $( pub $field: Option<$ty>, )*
+        }
+
+        impl AccountConfig {
+            /// Set `field` from `value`, `key` is the name reported on error.
+            fn set(&mut self, key: &str, field: &str, value: &Value) -> ConfigResult<()> {
+                match field {
+                    "ipaddresses" => self.ipaddresses = Some(ipv4_list(key, value, 4)?),
+                    $( stringify!($field) => self.$field = Some($parse(key, value)?), )*
+                    _ => return Err(ConfigError::UnknownKey(key.to_string())),
+                }
+                Ok(())
+            }
+
+            pub fn apply(&self, account: &mut LoginAccount) {
+                if let Some(ref value) = self.ipaddresses {
+                    account.ipaddresses(value);
+                }
+                $(
+                    if let Some(ref value) = self.$field {
+                        account.$field(value.clone());
+                    }
+                )*
+            }
+        }
+    }
+}
+
+account_config!(
+    adapter_count: u8 => integer,
+    mac_address: [u8; 6] => mac_address,
+    dog_flag: u8 => integer,
+    client_version: u8 => integer,
+    dog_version: u8 => integer,
+    control_check_status: u8 => integer,
+    ror_version: bool => boolean,
+    hostname: String => bounded_string::<HOSTNAME_MAX_LEN>,
+    service_pack: String => bounded_string::<SERVICE_PACK_MAX_LEN>,
+    dns_server: Ipv4Addr => ipv4,
+    dhcp_server: Ipv4Addr => ipv4,
+    backup_dns_server: Ipv4Addr => ipv4,
+    wins_ips: [Ipv4Addr; 2] => ipv4_pair,
+    major_version: u32 => integer,
+    minor_version: u32 => integer,
+    build_number: u32 => integer,
+    platform_id: u32 => integer,
+    auto_logout: bool => boolean,
+    broadcast_mode: bool => boolean,
+    random: u16 => integer,
+    auth_extra_option: u16 => integer
+);
+
+impl Config {
+    /// Read a config file, one holding a literal `password` must not be world-readable.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> ConfigResult<Self> {
+        let mut file = fs::File::open(&path).map_err(ConfigError::IOError)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).map_err(ConfigError::IOError)?;
+        let config = Self::from_toml(&content)?;
+
+        let metadata = file.metadata().map_err(ConfigError::IOError)?;
+        if matches!(config.password, Some(PasswordSource::Literal(_)))
+            && credentials::is_world_readable(&metadata)
+        {
+            return Err(ConfigError::WorldReadable(path.as_ref().to_path_buf()));
+        }
+        Ok(config)
+    }
+
+    pub fn from_toml(content: &str) -> ConfigResult<Self> {
+        let table = content.parse::<Table>().map_err(ConfigError::ParseError)?;
+
+        let mut config = Config::default();
+        for (key, value) in table.iter() {
+            match (key.as_str(), value) {
+                (
+                    section @ ("account" | "retransmit" | "probe" | "hooks"),
+                    Value::Table(fields),
+                ) => {
+                    for (field, value) in fields.iter() {
+                        config.set(&format!("{}.{}", section, field), value)?;
+                    }
+                }
+                _ => config.set(key, value)?,
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn socket_options(&self) -> SocketOptions {
+        let mut options = SocketOptions::new();
+        if let Some(address) = self.bind_address {
+            options.address(address);
+        }
+        options
+            .device(self.bind_device.clone())
+            .ephemeral_fallback(self.ephemeral_port.unwrap_or(false))
+            .reuse_address(self.reuse_address.unwrap_or(true));
+        options
+    }
+
+    /// Apply a command line `key=value` override, e.g. `account.mac_address=b8:88:e3:05:16:80`.
+    ///
+    /// The value is read as TOML when possible and as a bare string otherwise.
+    /// Keys of the `[account]` section may leave out the `account.` prefix.
+    pub fn set_override(&mut self, assignment: &str) -> ConfigResult<()> {
+        let (key, raw) = assignment
+            .split_once('=')
+            .ok_or_else(|| invalid(assignment, "expected <key>=<value>"))?;
+        let (key, raw) = (key.trim(), raw.trim());
+        let value = format!("value = {}", raw)
+            .parse::<Table>()
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| Value::String(raw.to_string()));
+
+        match self.set(key, &value) {
+            Err(ConfigError::UnknownKey(_)) if !key.contains('.') => {
+                self.set(&format!("account.{}", key), &value)
+            }
+            result => result,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> ConfigResult<()> {
+        match key.split_once('.') {
+            Some(("account", field)) => self.account.set(key, field, value),
+            Some(("retransmit", field)) => self.retransmit.set(key, field, value),
+            Some(("probe", field)) => self.probe.set(key, field, value),
+            Some(("hooks", field)) => self.hooks.set(key, field, value),
+            Some(_) => Err(ConfigError::UnknownKey(key.to_string())),
+            None => {
+                match key {
+                    "server" => self.server = Some(string(key, value)?),
+                    "username" => {
+                        self.username = Some(bounded_string::<USERNAME_MAX_LEN>(key, value)?)
+                    }
+                    "password" => {
+                        let password = bounded_string::<PASSWORD_MAX_LEN>(key, value)?;
+                        self.password = Some(PasswordSource::Literal(SecretString::new(password)))
+                    }
+                    "password_file" => {
+                        self.password = Some(PasswordSource::File(string(key, value)?.into()))
+                    }
+                    "password_env" => self.password = Some(PasswordSource::Env(string(key, value)?)),
+                    "password_credential" => {
+                        self.password = Some(PasswordSource::Credential(string(key, value)?))
+                    }
+                    "host_ip" => self.host_ip = Some(ipv4(key, value)?),
+                    "interface" => self.interface = Some(string(key, value)?),
+                    "system_root" => self.system_root = Some(string(key, value)?.into()),
+                    "bind_address" => self.bind_address = Some(bind_address(key, value)?),
+                    "bind_device" => self.bind_device = Some(string(key, value)?),
+                    "ephemeral_port" => self.ephemeral_port = Some(boolean(key, value)?),
+                    "reuse_address" => self.reuse_address = Some(boolean(key, value)?),
+                    "heartbeat_interval" => self.heartbeat_interval = Some(seconds(key, value)?),
+                    "log_level" => self.log_level = Some(parsed(key, value)?),
+                    "log_format" => self.log_format = Some(parsed(key, value)?),
+                    "control_socket" => self.control_socket = Some(string(key, value)?.into()),
+                    "metrics_listen" => self.metrics_listen = Some(parsed(key, value)?),
+                    _ => return Err(ConfigError::UnknownKey(key.to_string())),
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl RetransmitConfig {
+    fn set(&mut self, key: &str, field: &str, value: &Value) -> ConfigResult<()> {
+        match field {
+            "timeout" => self.timeout = Some(seconds(key, value)?),
+            "retries" => self.retries = Some(integer(key, value)?),
+            _ => {
+                let step = field
+                    .parse::<Step>()
+                    .map_err(|_| ConfigError::UnknownKey(key.to_string()))?;
+                let policy = string(key, value)?
+                    .parse::<StepPolicy>()
+                    .map_err(|e| invalid(key, e))?;
+                self.steps.retain(|(s, _)| *s != step);
+                self.steps.push((step, policy));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self) -> RetransmitPolicy {
+        let default = StepPolicy::default();
+        let mut policy = RetransmitPolicy::uniform(
+            self.timeout.unwrap_or(default.timeout),
+            self.retries.unwrap_or(default.retries),
+        );
+        for (step, step_policy) in self.steps.iter() {
+            policy.set_step(*step, *step_policy);
+        }
+        policy
+    }
+}
+
+impl ProbeConfig {
+    fn set(&mut self, key: &str, field: &str, value: &Value) -> ConfigResult<()> {
+        match field {
+            "url" => {
+                let target = ProbeTarget::http(&string(key, value)?).map_err(|e| invalid(key, e))?;
+                self.target = Some(target)
+            }
+            "tcp" => {
+                let target = ProbeTarget::tcp(&string(key, value)?).map_err(|e| invalid(key, e))?;
+                self.target = Some(target)
+            }
+            "interval" => self.interval = Some(seconds(key, value)?),
+            "timeout" => self.timeout = Some(seconds(key, value)?),
+            "failures" => self.failures = Some(integer(key, value)?),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// `None` unless a target is configured.
+    pub fn prober(&self) -> Option<Prober> {
+        let mut prober = Prober::new(self.target.clone()?);
+        if let Some(interval) = self.interval {
+            prober.interval(interval);
+        }
+        if let Some(timeout) = self.timeout {
+            prober.timeout(timeout);
+        }
+        if let Some(failures) = self.failures {
+            prober.max_failures(failures);
+        }
+        Some(prober)
+    }
+}
+
+impl HookConfig {
+    /// A single command or an array of them, appended to the ones already set.
+    fn set(&mut self, key: &str, field: &str, value: &Value) -> ConfigResult<()> {
+        if field == "timeout" {
+            self.timeout = Some(seconds(key, value)?);
+            return Ok(());
+        }
+        let event = field
+            .parse::<HookEvent>()
+            .map_err(|_| ConfigError::UnknownKey(key.to_string()))?;
+        let commands = match value {
+            Value::Array(items) => items
+                .iter()
+                .map(|item| string(key, item))
+                .collect::<ConfigResult<Vec<_>>>()?,
+            _ => vec![string(key, value)?],
+        };
+        self.commands
+            .extend(commands.into_iter().map(|command| (event, command)));
+        Ok(())
+    }
+
+    pub fn apply(&self, hooks: &mut Hooks) {
+        hooks.commands(self.commands.clone());
+        if let Some(timeout) = self.timeout {
+            hooks.timeout(timeout);
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::IOError(e) => write!(f, "cannot read config: {}", e),
+            ConfigError::ParseError(e) => write!(f, "malformed config: {}", e),
+            ConfigError::UnknownKey(key) => write!(f, "unknown config key `{}`", key),
+            ConfigError::InvalidValue { key, reason } => {
+                write!(f, "invalid value for `{}`: {}", key, reason)
+            }
+            ConfigError::WorldReadable(path) => write!(
+                f,
+                "refusing to read a password from world-readable {}, chmod o-r it first",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid<R: fmt::Display>(key: &str, reason: R) -> ConfigError {
+    ConfigError::InvalidValue {
+        key:    key.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn string(key: &str, value: &Value) -> ConfigResult<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        _ => Err(invalid(key, "expected a string")),
+    }
+}
+
+fn parsed<T: FromStr>(key: &str, value: &Value) -> ConfigResult<T>
+where
+    T::Err: fmt::Display,
+{
+    string(key, value)?.parse().map_err(|e| invalid(key, e))
+}
+
+fn bounded_string<const MAX_LEN: usize>(key: &str, value: &Value) -> ConfigResult<String> {
+    let s = string(key, value)?;
+    if s.len() > MAX_LEN {
+        return Err(invalid(key, format!("longer than {} bytes", MAX_LEN)));
+    }
+    Ok(s)
+}
+
+/// Integers may be written natively (`10`, `0x0a`) or as strings (`"0x0a"`).
+fn integer<T: TryFrom<i64>>(key: &str, value: &Value) -> ConfigResult<T> {
+    let n = match value {
+        Value::Integer(n) => *n,
+        Value::String(s) => {
+            let s = s.trim();
+            let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => s.parse::<i64>(),
+            };
+            parsed.map_err(|_| invalid(key, format!("`{}` is not an integer", s)))?
+        }
+        _ => return Err(invalid(key, "expected an integer")),
+    };
+    T::try_from(n).map_err(|_| invalid(key, format!("{} is out of range", n)))
+}
+
+fn boolean(key: &str, value: &Value) -> ConfigResult<bool> {
+    match value {
+        Value::Boolean(b) => Ok(*b),
+        Value::String(s) => match s.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" => Ok(false),
+            _ => Err(invalid(key, format!("`{}` is not a boolean", s))),
+        },
+        _ => Err(invalid(key, "expected a boolean")),
+    }
+}
+
+fn seconds(key: &str, value: &Value) -> ConfigResult<Duration> {
+    let secs = match value {
+        Value::Integer(n) => *n as f64,
+        Value::Float(n) => *n,
+        _ => return Err(invalid(key, "expected a number of seconds")),
+    };
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| invalid(key, format!("{} is not a positive number of seconds", secs)))
+}
+
+fn bind_address(key: &str, value: &Value) -> ConfigResult<SocketAddrV4> {
+    let s = string(key, value)?;
+    socket::parse_bind_address(s.trim())
+        .map_err(|_| invalid(key, format!("`{}` is not an IPv4 address[:port]", s)))
+}
+
+fn ipv4(key: &str, value: &Value) -> ConfigResult<Ipv4Addr> {
+    let s = string(key, value)?;
+    s.trim()
+        .parse()
+        .map_err(|_| invalid(key, format!("`{}` is not an IPv4 address", s)))
+}
+
+/// A single address or an array of at most `max` addresses.
+fn ipv4_list(key: &str, value: &Value, max: usize) -> ConfigResult<Vec<Ipv4Addr>> {
+    let addresses = match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| ipv4(key, item))
+            .collect::<ConfigResult<Vec<_>>>()?,
+        _ => vec![ipv4(key, value)?],
+    };
+    if addresses.len() > max {
+        return Err(invalid(key, format!("at most {} addresses are allowed", max)));
+    }
+    Ok(addresses)
+}
+
+fn ipv4_pair(key: &str, value: &Value) -> ConfigResult<[Ipv4Addr; 2]> {
+    let mut pair = [Ipv4Addr::UNSPECIFIED; 2];
+    for (i, ip) in ipv4_list(key, value, 2)?.into_iter().enumerate() {
+        pair[i] = ip;
+    }
+    Ok(pair)
+}
+
+/// Accepts `b8:88:e3:05:16:80`, `b8-88-e3-05-16-80`, `b888.e305.1680` and `b888e3051680`.
+fn mac_address(key: &str, value: &Value) -> ConfigResult<[u8; 6]> {
+    let s = string(key, value)?;
+    let digits: String = s
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    let malformed = || invalid(key, format!("`{}` is not a MAC address", s));
+    // from_str_radix takes a leading `+` as well
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(malformed());
+    }
+
+    let mut mac = [0u8; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| malformed())?;
+    }
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            server = "10.100.61.3:61440"
+            username = "usernameusername"
+            password_file = "/etc/drcom/password"
+            interface = "eth1"
+            system_root = "/srv/fixture"
+            bind_address = "10.0.0.2"
+            bind_device = "eth1"
+            ephemeral_port = true
+            reuse_address = false
+            heartbeat_interval = 12.5
+            log_level = "debug"
+            log_format = "json"
+            control_socket = "/run/drcom/control.sock"
+            metrics_listen = "127.0.0.1:9100"
+
+            [account]
+            mac_address = "b8:88:e3:05:16:80"
+            client_version = 0x0f
+            dog_version = "0x01"
+            ror_version = true
+            ipaddresses = ["10.30.22.17", "10.30.22.18"]
+            wins_ips = "1.1.1.1"
+            auth_extra_option = 2
+
+            [retransmit]
+            timeout = 2
+            login = "5:1"
+
+            [probe]
+            tcp = "1.1.1.1:53"
+            interval = 30
+
+            [hooks]
+            timeout = 10
+            online = "systemctl restart wg-quick@wg0"
+            login_failed = ["logger -t drcom failed", "true"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.as_deref(), Some("10.100.61.3:61440"));
+        assert_eq!(
+            config.password,
+            Some(PasswordSource::File("/etc/drcom/password".into()))
+        );
+        assert_eq!(config.interface.as_deref(), Some("eth1"));
+        assert_eq!(config.system_root, Some(PathBuf::from("/srv/fixture")));
+        let mut options = SocketOptions::new();
+        options
+            .address(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), socket::DEFAULT_PORT))
+            .device(Some(String::from("eth1")))
+            .ephemeral_fallback(true)
+            .reuse_address(false);
+        assert_eq!(config.socket_options(), options);
+        assert_eq!(config.heartbeat_interval, Some(Duration::from_millis(12500)));
+        assert_eq!(config.log_level, Some(LevelFilter::Debug));
+        assert_eq!(config.log_format, Some(LogFormat::Json));
+        assert_eq!(config.control_socket, Some(PathBuf::from("/run/drcom/control.sock")));
+        assert_eq!(config.metrics_listen, Some(SocketAddr::from(([127, 0, 0, 1], 9100))));
+        assert_eq!(
+            config.account.mac_address,
+            Some([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80])
+        );
+        assert_eq!(config.account.client_version, Some(0x0f));
+        assert_eq!(config.account.dog_version, Some(0x01));
+        assert_eq!(config.account.ror_version, Some(true));
+        assert_eq!(
+            config.account.wins_ips,
+            Some([Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::UNSPECIFIED])
+        );
+        assert_eq!(config.account.hostname, None);
+
+        let policy = config.retransmit.policy();
+        assert_eq!(policy.challenge, StepPolicy::new(Duration::from_secs(2), 2));
+        assert_eq!(policy.login, StepPolicy::new(Duration::from_secs(5), 1));
+
+        assert_eq!(config.probe.target, Some(ProbeTarget::Tcp(String::from("1.1.1.1:53"))));
+        assert_eq!(config.probe.interval, Some(Duration::from_secs(30)));
+        assert!(config.probe.prober().is_some());
+        assert!(ProbeConfig::default().prober().is_none());
+
+        assert_eq!(config.hooks.timeout, Some(Duration::from_secs(10)));
+        assert_eq!(
+            config.hooks.commands,
+            vec![
+                (HookEvent::LoginFailed, String::from("logger -t drcom failed")),
+                (HookEvent::LoginFailed, String::from("true")),
+                (HookEvent::Online, String::from("systemctl restart wg-quick@wg0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_errors_name_the_key() {
+        let cases = [
+            ("[account]\nmac_address = \"b8:88:e3:05:16\"", "account.mac_address"),
+            ("[account]\nmac_address = \"+8:88:e3:05:16:80\"", "account.mac_address"),
+            ("[account]\ndog_flag = 256", "account.dog_flag"),
+            ("[account]\nhostname = \"a-very-long-hostname-over-32-bytes\"", "account.hostname"),
+            ("[account]\nipaddresses = [\"1.1.1.1\", \"2.2.2\"]", "account.ipaddresses"),
+            ("[retransmit]\nlogin = \"5\"", "retransmit.login"),
+            ("heartbeat_interval = 0", "heartbeat_interval"),
+            ("log_format = \"yaml\"", "log_format"),
+            ("[probe]\nurl = \"https://www.gstatic.com/generate_204\"", "probe.url"),
+            ("[hooks]\nonline = 1", "hooks.online"),
+        ];
+        for (content, expected) in cases {
+            match Config::from_toml(content) {
+                Err(ConfigError::InvalidValue { key, .. }) => assert_eq!(key, expected),
+                other => panic!("{}: {:?}", expected, other),
+            }
+        }
+        assert!(matches!(
+            Config::from_toml("[account]\nmac = \"b8:88:e3:05:16:80\""),
+            Err(ConfigError::UnknownKey(ref key)) if key == "account.mac"
+        ));
+    }
+
+    #[test]
+    fn test_config_file_with_password() {
+        let path = env::temp_dir().join(format!("drcom-rs-config-{}.toml", process::id()));
+        fs::write(&path, "username = \"a\"\npassword = \"secret\"\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(Config::from_file(&path), Err(ConfigError::WorldReadable(_))));
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(Config::from_file(&path).unwrap().password.is_some());
+
+        // without a password the config may be readable by anyone
+        fs::write(&path, "username = \"a\"\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(Config::from_file(&path).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_config_set_override() {
+        let mut config = Config::from_toml("[account]\ndog_flag = 3").unwrap();
+        config.set_override("dog_flag=0x01").unwrap();
+        config.set_override("mac_address=b8-88-e3-05-16-80").unwrap();
+        config.set_override("account.hostname = DESKTOP").unwrap();
+        config.set_override("server=10.100.61.3:61440").unwrap();
+        config.set_override("retransmit.keep_alive1=1.5:4").unwrap();
+
+        assert_eq!(config.account.dog_flag, Some(1));
+        assert_eq!(
+            config.account.mac_address,
+            Some([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80])
+        );
+        assert_eq!(config.account.hostname.as_deref(), Some("DESKTOP"));
+        assert_eq!(config.server.as_deref(), Some("10.100.61.3:61440"));
+        assert_eq!(
+            config.retransmit.policy().keep_alive1,
+            StepPolicy::new(Duration::from_millis(1500), 4)
+        );
+        assert!(matches!(
+            config.set_override("nonsense=1"),
+            Err(ConfigError::UnknownKey(ref key)) if key == "account.nonsense"
+        ));
+    }
+}
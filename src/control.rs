@@ -0,0 +1,393 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+/// How long either side waits on a connection before giving up on it.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line taken, nothing valid comes close.
+const MAX_REQUEST_LEN: usize = 4096;
+const SOCKET_NAME: &str = "drcom-rs.sock";
+
+/// Requests accepted on the control socket, one JSON object per line such as
+/// `{"command": "status"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Status,
+    Logout,
+    Relogin,
+    Reload,
+}
+
+#[derive(Debug)]
+pub enum ControlError {
+    IOError(io::Error),
+    MalformedMessage(String),
+    UnknownCommand(String),
+    /// A command arrived while a logout is still under way.
+    Busy,
+    /// The daemon answered with `"ok": false`.
+    Rejected(String),
+}
+
+type ControlResult<T> = Result<T, ControlError>;
+
+/// Listening end of the control socket, removed from the filesystem on drop.
+#[derive(Debug)]
+pub struct ControlServer {
+    listener: UnixListener,
+    path:     PathBuf,
+    /// Clients still sending their request.
+    pending:  Vec<ControlConnection>,
+}
+
+/// An accepted client, read without blocking so that a slow one cannot hold up
+/// the heartbeat loop.
+#[derive(Debug)]
+pub struct ControlConnection {
+    stream:   UnixStream,
+    buffer:   Vec<u8>,
+    accepted: Instant,
+}
+
+/// A client along with its parsed request, waiting for its response.
+pub type ControlRequest = (ControlConnection, ControlResult<Command>);
+
+impl Command {
+    pub const ALL: [Command; 4] = [
+        Command::Status,
+        Command::Logout,
+        Command::Relogin,
+        Command::Reload,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Command::Status => "status",
+            Command::Logout => "logout",
+            Command::Relogin => "relogin",
+            Command::Reload => "reload",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "command": self.name() })
+    }
+
+    pub fn from_json(line: &str) -> ControlResult<Self> {
+        let request: Value = serde_json::from_str(line)
+            .map_err(|e| ControlError::MalformedMessage(e.to_string()))?;
+        request
+            .get("command")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ControlError::MalformedMessage(String::from("missing `command`")))?
+            .parse()
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Command {
+    type Err = ControlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Command::ALL
+            .iter()
+            .find(|command| command.name() == s)
+            .copied()
+            .ok_or_else(|| ControlError::UnknownCommand(s.to_string()))
+    }
+}
+
+/// Where the control socket lives unless configured otherwise: the systemd
+/// runtime directory, then `$XDG_RUNTIME_DIR`, then `/run` for root.
+pub fn default_path() -> PathBuf {
+    for variable in ["RUNTIME_DIRECTORY", "XDG_RUNTIME_DIR"] {
+        if let Some(directory) = env::var_os(variable).filter(|d| !d.is_empty()) {
+            return Path::new(&directory).join(SOCKET_NAME);
+        }
+    }
+    match unsafe { libc::geteuid() } {
+        0 => Path::new("/run").join(SOCKET_NAME),
+        uid => env::temp_dir().join(format!("drcom-rs-{}.sock", uid)),
+    }
+}
+
+impl ControlServer {
+    /// Listen on `path`, taking over a socket file left behind by a process
+    /// that is no longer there.
+    pub fn bind<P: AsRef<Path>>(path: P) -> ControlResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        if UnixStream::connect(&path).is_ok() {
+            return Err(ControlError::IOError(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another process", path.display()),
+            )));
+        }
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(ControlError::IOError(e)),
+            _ => {}
+        }
+
+        // only the owner and its group may log us out, the socket must not exist with
+        // looser permissions even for a moment
+        let umask = unsafe { libc::umask(0o117) };
+        let listener = UnixListener::bind(&path);
+        unsafe { libc::umask(umask) };
+        let server = ControlServer {
+            listener: listener.map_err(ControlError::IOError)?,
+            path,
+            pending: vec![],
+        };
+        fs::set_permissions(&server.path, fs::Permissions::from_mode(0o660))
+            .map_err(ControlError::IOError)?;
+        server.listener.set_nonblocking(true).map_err(ControlError::IOError)?;
+        Ok(server)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The next client whose request arrived in full or failed, never blocks.
+    /// Clients still sending are kept until `IO_TIMEOUT` runs out.
+    pub fn next_request(&mut self) -> ControlResult<Option<ControlRequest>> {
+        loop {
+            for i in 0..self.pending.len() {
+                if let Some(command) = self.pending[i].poll_command() {
+                    return Ok(Some((self.pending.remove(i), command)));
+                }
+            }
+            match self.accept()? {
+                Some(connection) => self.pending.push(connection),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn accept(&self) -> ControlResult<Option<ControlConnection>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(true).map_err(ControlError::IOError)?;
+                Ok(Some(ControlConnection {
+                    stream,
+                    buffer: vec![],
+                    accepted: Instant::now(),
+                }))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(ControlError::IOError(e)),
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl ControlConnection {
+    /// The request once its line is complete, `None` while more is to come.
+    fn poll_command(&mut self) -> Option<ControlResult<Command>> {
+        let mut chunk = [0u8; 512];
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                return Some(Command::from_json(&String::from_utf8_lossy(&self.buffer[..end])));
+            }
+            if self.buffer.len() > MAX_REQUEST_LEN {
+                return Some(Err(ControlError::MalformedMessage(String::from(
+                    "request too long",
+                ))));
+            }
+            match self.stream.read(&mut chunk) {
+                // a client may shut down its end instead of ending the line
+                Ok(0) if !self.buffer.is_empty() => {
+                    return Some(Command::from_json(&String::from_utf8_lossy(&self.buffer)))
+                }
+                Ok(0) => {
+                    return Some(Err(ControlError::MalformedMessage(String::from(
+                        "connection closed",
+                    ))))
+                }
+                Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.accepted.elapsed() < IO_TIMEOUT {
+                        return None;
+                    }
+                    return Some(Err(ControlError::IOError(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "timed out waiting for the request",
+                    ))));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(ControlError::IOError(e))),
+            }
+        }
+    }
+
+    /// Answer with `{"ok": true, ...}` plus whatever fields `body` holds.
+    pub fn respond_ok(mut self, body: Value) -> ControlResult<()> {
+        let mut response = json!({ "ok": true });
+        if let (Some(response), Value::Object(fields)) = (response.as_object_mut(), body) {
+            response.extend(fields);
+        }
+        // a response fits in the socket buffer, writing does not block
+        write_line(&mut self.stream, &response)
+    }
+
+    pub fn respond_error(mut self, error: &ControlError) -> ControlResult<()> {
+        let response = json!({ "ok": false, "error": error.to_string() });
+        write_line(&mut self.stream, &response)
+    }
+}
+
+/// Send `command` to the daemon listening on `path` and return its response.
+pub fn request<P: AsRef<Path>>(path: P, command: Command) -> ControlResult<Value> {
+    let stream = UnixStream::connect(path).map_err(ControlError::IOError)?;
+    stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .map_err(ControlError::IOError)?;
+    let mut reader = BufReader::new(stream);
+    write_line(reader.get_mut(), &command.to_json())?;
+
+    let response: Value = serde_json::from_str(&read_line(&mut reader)?)
+        .map_err(|e| ControlError::MalformedMessage(e.to_string()))?;
+    match response.get("ok").and_then(Value::as_bool) {
+        Some(true) => Ok(response),
+        Some(false) => Err(ControlError::Rejected(
+            response
+                .get("error")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error")
+                .to_string(),
+        )),
+        None => Err(ControlError::MalformedMessage(String::from("missing `ok`"))),
+    }
+}
+
+fn read_line(reader: &mut BufReader<UnixStream>) -> ControlResult<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line).map_err(ControlError::IOError)? {
+        0 => Err(ControlError::MalformedMessage(String::from("connection closed"))),
+        _ => Ok(line),
+    }
+}
+
+fn write_line(stream: &mut UnixStream, message: &Value) -> ControlResult<()> {
+    writeln!(stream, "{}", message).map_err(ControlError::IOError)
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlError::IOError(e) => write!(f, "{}", e),
+            ControlError::MalformedMessage(reason) => write!(f, "malformed message: {}", reason),
+            ControlError::UnknownCommand(command) => {
+                let names: Vec<&str> = Command::ALL.iter().map(Command::name).collect();
+                write!(
+                    f,
+                    "unknown command `{}`, expected one of {}",
+                    command,
+                    names.join(", ")
+                )
+            }
+            ControlError::Busy => write!(f, "busy logging out, try again once it is done"),
+            ControlError::Rejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use std::thread;
+
+    #[test]
+    fn test_command_json() {
+        for command in Command::ALL {
+            assert_eq!(Command::from_json(&command.to_json().to_string()).unwrap(), command);
+        }
+        assert!(matches!(
+            Command::from_json(r#"{"command": "reboot"}"#),
+            Err(ControlError::UnknownCommand(_))
+        ));
+        assert!(matches!(
+            Command::from_json("status"),
+            Err(ControlError::MalformedMessage(_))
+        ));
+    }
+
+    #[test]
+    fn test_control_round_trip() {
+        let path = env::temp_dir().join(format!("drcom-rs-control-{}.sock", process::id()));
+        let mut server = ControlServer::bind(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        assert!(server.next_request().unwrap().is_none());
+        // a live socket is not taken over, the probe shows up as an empty connection
+        assert!(ControlServer::bind(&path).is_err());
+        let (_, probe) = server.next_request().unwrap().unwrap();
+        assert!(matches!(probe, Err(ControlError::MalformedMessage(_))));
+
+        // a client that has not finished its request does not hold up the others
+        let mut slow = UnixStream::connect(&path).unwrap();
+        slow.write_all(br#"{"command": "#).unwrap();
+        assert!(server.next_request().unwrap().is_none());
+
+        let client = {
+            let path = path.clone();
+            thread::spawn(move || {
+                (
+                    request(&path, Command::Status).unwrap(),
+                    request(&path, Command::Logout),
+                )
+            })
+        };
+        for respond in [true, false] {
+            let (connection, command) = loop {
+                if let Some(request) = server.next_request().unwrap() {
+                    break request;
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+            match (command.unwrap(), respond) {
+                (Command::Status, true) => {
+                    connection.respond_ok(json!({ "state": "online" })).unwrap()
+                }
+                (Command::Logout, false) => connection
+                    .respond_error(&ControlError::Rejected(String::from("not now")))
+                    .unwrap(),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        let (status, logout) = client.join().unwrap();
+        assert_eq!(status, json!({ "ok": true, "state": "online" }));
+        assert!(matches!(logout, Err(ControlError::Rejected(ref e)) if e == "not now"));
+
+        slow.write_all(b"\"relogin\"}\n").unwrap();
+        let (connection, command) = server.next_request().unwrap().unwrap();
+        assert_eq!(command.unwrap(), Command::Relogin);
+        connection.respond_ok(json!({})).unwrap();
+        let mut response = String::new();
+        BufReader::new(slow).read_line(&mut response).unwrap();
+        assert_eq!(response, "{\"ok\":true}\n");
+
+        drop(server);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,285 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use crate::common::secret::SecretString;
+
+/// Environment variable read when no other password source is given.
+pub const PASSWORD_ENV: &str = "DRCOM_PASSWORD";
+/// Name of the systemd credential (`LoadCredential=password:...`) read by default.
+pub const DEFAULT_CREDENTIAL: &str = "password";
+const CREDENTIALS_DIRECTORY_ENV: &str = "CREDENTIALS_DIRECTORY";
+
+/// Where to take the login password from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordSource {
+    /// Given on the command line or in the config, visible to other users.
+    Literal(SecretString),
+    /// First line of a file that must not be world-readable.
+    File(PathBuf),
+    /// Value of an environment variable.
+    Env(String),
+    /// First line of stdin, or a prompt without echo if stdin is a TTY.
+    Stdin,
+    /// A systemd credential from `$CREDENTIALS_DIRECTORY`.
+    Credential(String),
+}
+
+#[derive(Debug)]
+pub enum CredentialError {
+    IOError(PathBuf, io::Error),
+    StdinError(io::Error),
+    WorldReadable(PathBuf),
+    EnvNotSet(String),
+    NoCredentialsDirectory,
+    Empty(PasswordSource),
+    NoSource,
+}
+
+type CredentialResult<T> = Result<T, CredentialError>;
+
+impl PasswordSource {
+    pub fn read(&self) -> CredentialResult<SecretString> {
+        self.read_with(|name| env::var_os(name))
+    }
+
+    /// Like `read`, looking environment variables up through `var`.
+    pub fn read_with<F>(&self, var: F) -> CredentialResult<SecretString>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let password = match self {
+            PasswordSource::Literal(password) => password.clone(),
+            PasswordSource::File(path) => read_protected_file(path)?,
+            PasswordSource::Env(name) => var(name)
+                .and_then(|value| value.into_string().ok())
+                .map(SecretString::new)
+                .ok_or_else(|| CredentialError::EnvNotSet(name.clone()))?,
+            PasswordSource::Stdin => read_stdin()?,
+            PasswordSource::Credential(name) => {
+                let directory =
+                    var(CREDENTIALS_DIRECTORY_ENV).ok_or(CredentialError::NoCredentialsDirectory)?;
+                let path = Path::new(&directory).join(name);
+                read_first_line(&path)?
+            }
+        };
+
+        if password.expose().is_empty() {
+            return Err(CredentialError::Empty(self.clone()));
+        }
+        Ok(password)
+    }
+
+    /// Pick a source when none was given explicitly: a systemd credential named
+    /// `password`, then `$DRCOM_PASSWORD`, then a prompt if stdin is a TTY.
+    pub fn discover() -> CredentialResult<Self> {
+        Self::discover_with(|name| env::var_os(name))
+    }
+
+    /// Like `discover`, looking environment variables up through `var`.
+    pub fn discover_with<F>(var: F) -> CredentialResult<Self>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        if let Some(directory) = var(CREDENTIALS_DIRECTORY_ENV) {
+            if Path::new(&directory).join(DEFAULT_CREDENTIAL).is_file() {
+                return Ok(PasswordSource::Credential(DEFAULT_CREDENTIAL.to_string()));
+            }
+        }
+        if var(PASSWORD_ENV).is_some() {
+            return Ok(PasswordSource::Env(PASSWORD_ENV.to_string()));
+        }
+        if io::stdin().is_terminal() {
+            return Ok(PasswordSource::Stdin);
+        }
+        Err(CredentialError::NoSource)
+    }
+}
+
+impl fmt::Display for PasswordSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PasswordSource::Literal(_) => write!(f, "--password"),
+            PasswordSource::File(path) => write!(f, "file {}", path.display()),
+            PasswordSource::Env(name) => write!(f, "${}", name),
+            PasswordSource::Stdin => write!(f, "stdin"),
+            PasswordSource::Credential(name) => write!(f, "credential {}", name),
+        }
+    }
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CredentialError::IOError(path, e) => {
+                write!(f, "cannot read password from {}: {}", path.display(), e)
+            }
+            CredentialError::StdinError(e) => write!(f, "cannot read password from stdin: {}", e),
+            CredentialError::WorldReadable(path) => write!(
+                f,
+                "refusing to read password from world-readable {}, chmod o-r it first",
+                path.display()
+            ),
+            CredentialError::EnvNotSet(name) => write!(f, "${} is not set", name),
+            CredentialError::NoCredentialsDirectory => write!(
+                f,
+                "${} is not set, is this a systemd service with LoadCredential=?",
+                CREDENTIALS_DIRECTORY_ENV
+            ),
+            CredentialError::Empty(source) => write!(f, "empty password from {}", source),
+            CredentialError::NoSource => write!(
+                f,
+                "no password given, use --password-file, --password-env, --password-stdin or ${}",
+                PASSWORD_ENV
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+/// Only the first line counts, so a trailing newline from `echo` does no harm.
+fn first_line(content: SecretString) -> SecretString {
+    SecretString::from(content.expose().lines().next().unwrap_or_default())
+}
+
+fn read_first_line(path: &Path) -> CredentialResult<SecretString> {
+    fs::read_to_string(path)
+        .map(|content| first_line(SecretString::new(content)))
+        .map_err(|e| CredentialError::IOError(path.to_path_buf(), e))
+}
+
+fn read_protected_file(path: &Path) -> CredentialResult<SecretString> {
+    let metadata =
+        fs::metadata(path).map_err(|e| CredentialError::IOError(path.to_path_buf(), e))?;
+    if is_world_readable(&metadata) {
+        return Err(CredentialError::WorldReadable(path.to_path_buf()));
+    }
+    read_first_line(path)
+}
+
+#[cfg(unix)]
+pub(crate) fn is_world_readable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o004 != 0
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_world_readable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+fn read_stdin() -> CredentialResult<SecretString> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password("Password: ")
+            .map(SecretString::new)
+            .map_err(CredentialError::StdinError);
+    }
+    let mut buffer = String::new();
+    let result = io::stdin().lock().read_line(&mut buffer);
+    // wrap the buffer before bailing out so that it is zeroed either way
+    let line = SecretString::new(buffer);
+    result.map_err(CredentialError::StdinError)?;
+    Ok(first_line(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    fn password_file(name: &str, content: &str, mode: u32) -> PathBuf {
+        let path = env::temp_dir().join(format!("drcom-rs-{}-{}", name, process::id()));
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_password_file() {
+        let path = password_file("private", "secret\nignored\n", 0o600);
+        assert_eq!(PasswordSource::File(path.clone()).read().unwrap().expose(), "secret");
+        fs::remove_file(path).unwrap();
+
+        let path = password_file("public", "secret\n", 0o644);
+        assert!(matches!(
+            PasswordSource::File(path.clone()).read(),
+            Err(CredentialError::WorldReadable(_))
+        ));
+        fs::remove_file(path).unwrap();
+
+        let path = password_file("empty", "\n", 0o600);
+        assert!(matches!(
+            PasswordSource::File(path.clone()).read(),
+            Err(CredentialError::Empty(_))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    /// An environment holding only `vars`, so tests never touch the real one.
+    fn environment(vars: &[(&str, &Path)]) -> impl Fn(&str) -> Option<OsString> {
+        let vars: Vec<(String, OsString)> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_os_str().to_os_string()))
+            .collect();
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    #[test]
+    fn test_password_env() {
+        let source = PasswordSource::Env(PASSWORD_ENV.to_string());
+        assert!(matches!(
+            source.read_with(environment(&[])),
+            Err(CredentialError::EnvNotSet(_))
+        ));
+        let var = environment(&[(PASSWORD_ENV, Path::new("secret"))]);
+        assert_eq!(source.read_with(var).unwrap().expose(), "secret");
+    }
+
+    #[test]
+    fn test_password_credential() {
+        let path = password_file("credential", "secret", 0o400);
+        let var = environment(&[(CREDENTIALS_DIRECTORY_ENV, path.parent().unwrap())]);
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        assert_eq!(PasswordSource::Credential(name).read_with(var).unwrap().expose(), "secret");
+        assert!(matches!(
+            PasswordSource::Credential(DEFAULT_CREDENTIAL.to_string()).read_with(environment(&[])),
+            Err(CredentialError::NoCredentialsDirectory)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_password_discover() {
+        let directory = env::temp_dir().join(format!("drcom-rs-credentials-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join(DEFAULT_CREDENTIAL), "secret").unwrap();
+        let var = environment(&[
+            (CREDENTIALS_DIRECTORY_ENV, &directory),
+            (PASSWORD_ENV, Path::new("secret")),
+        ]);
+        assert_eq!(
+            PasswordSource::discover_with(var).unwrap(),
+            PasswordSource::Credential(DEFAULT_CREDENTIAL.to_string())
+        );
+        fs::remove_dir_all(&directory).unwrap();
+
+        // the credentials directory has no password in it any more
+        let var = environment(&[
+            (CREDENTIALS_DIRECTORY_ENV, &directory),
+            (PASSWORD_ENV, Path::new("secret")),
+        ]);
+        assert_eq!(
+            PasswordSource::discover_with(var).unwrap(),
+            PasswordSource::Env(PASSWORD_ENV.to_string())
+        );
+    }
+}
@@ -81,7 +81,7 @@ impl HasherBuilder {
     }
 }
 
-fn hash_bytes(bytes: &[u8], type_: HasherType) -> Vec<u8> {
+pub fn hash_bytes(bytes: &[u8], type_: HasherType) -> Vec<u8> {
     let mut hasher = HasherBuilder::build(type_);
     hasher.update(bytes);
     hasher.finish()
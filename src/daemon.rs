@@ -0,0 +1,246 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+#[derive(Debug)]
+pub enum DaemonError {
+    IOError(io::Error),
+    PidfileError(PathBuf, io::Error),
+    AlreadyRunning { pid: Option<i32>, pidfile: PathBuf },
+}
+
+type DaemonResult<T> = Result<T, DaemonError>;
+
+/// Flags raised by SIGTERM/SIGINT and SIGHUP, checked by the main loop.
+#[derive(Debug)]
+pub struct Signals {
+    shutdown: Arc<AtomicBool>,
+    reload:   Arc<AtomicBool>,
+}
+
+/// Holds our pid in a file locked for as long as it lives, removing it on drop.
+#[derive(Debug)]
+pub struct Pidfile {
+    path: PathBuf,
+    file: File,
+}
+
+impl Signals {
+    pub fn register() -> DaemonResult<Self> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reload = Arc::new(AtomicBool::new(false));
+        for signal in [SIGTERM, SIGINT] {
+            signal_hook::flag::register(signal, Arc::clone(&shutdown))
+                .map_err(DaemonError::IOError)?;
+        }
+        signal_hook::flag::register(SIGHUP, Arc::clone(&reload)).map_err(DaemonError::IOError)?;
+        Ok(Signals { shutdown, reload })
+    }
+
+    /// Whether SIGTERM or SIGINT arrived since the last call.
+    pub fn take_shutdown(&self) -> bool {
+        self.shutdown.swap(false, Ordering::SeqCst)
+    }
+
+    /// Whether SIGHUP arrived since the last call.
+    pub fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Pidfile {
+    /// Lock `path` and write our pid to it, refusing if another process holds the lock.
+    ///
+    /// The lock belongs to the open file, so it survives `daemonize`, which only
+    /// needs a `write_pid` afterwards to record the final pid.
+    pub fn create<P: AsRef<Path>>(path: P) -> DaemonResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let pidfile_error = |e| DaemonError::PidfileError(path.clone(), e);
+
+        loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                // the running instance's pid stays until we hold the lock
+                .truncate(false)
+                .mode(0o644)
+                .open(&path)
+                .map_err(pidfile_error)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    return Err(pidfile_error(e));
+                }
+                let pid = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|content| content.trim().parse().ok());
+                return Err(DaemonError::AlreadyRunning { pid, pidfile: path });
+            }
+
+            // the previous owner removes the file before unlocking it, try again if we
+            // locked what it removed
+            let locked = file.metadata().map_err(pidfile_error)?;
+            match fs::metadata(&path) {
+                Ok(current) if (current.dev(), current.ino()) == (locked.dev(), locked.ino()) => {
+                    let pidfile = Pidfile { path, file };
+                    pidfile.write_pid()?;
+                    return Ok(pidfile);
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(pidfile_error(e)),
+            }
+        }
+    }
+
+    /// Replace the content with our pid, which changes when daemonizing.
+    pub fn write_pid(&self) -> DaemonResult<()> {
+        let content = format!("{}\n", process::id());
+        self.file
+            .set_len(0)
+            .and_then(|_| self.file.write_all_at(content.as_bytes(), 0))
+            .map_err(|e| DaemonError::PidfileError(self.path.clone(), e))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn fork() -> DaemonResult<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(DaemonError::IOError(io::Error::last_os_error())),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Detach from the terminal with the classic double fork, for systems
+/// without a service manager to do it for us.
+///
+/// stdout and stderr go to `log_file` if given and to `/dev/null` otherwise.
+/// The working directory becomes `/`, so relative paths must be resolved first.
+pub fn daemonize(log_file: Option<&Path>) -> DaemonResult<()> {
+    // open everything before forking so that errors still reach the terminal
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(DaemonError::IOError)?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o640)
+            .open(path)
+            .map_err(DaemonError::IOError)?,
+        None => null.try_clone().map_err(DaemonError::IOError)?,
+    };
+
+    fork()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(DaemonError::IOError(io::Error::last_os_error()));
+    }
+    // the session leader exits so that we can never reacquire a terminal
+    fork()?;
+
+    unsafe { libc::umask(0o027) };
+    std::env::set_current_dir("/").map_err(DaemonError::IOError)?;
+    for (file, fd) in [
+        (&null, libc::STDIN_FILENO),
+        (&output, libc::STDOUT_FILENO),
+        (&output, libc::STDERR_FILENO),
+    ] {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(DaemonError::IOError(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DaemonError::IOError(e) => write!(f, "{}", e),
+            DaemonError::PidfileError(path, e) => {
+                write!(f, "cannot write pidfile {}: {}", path.display(), e)
+            }
+            DaemonError::AlreadyRunning {
+                pid: Some(pid),
+                pidfile,
+            } => write!(
+                f,
+                "already running as pid {} according to {}",
+                pid,
+                pidfile.display()
+            ),
+            DaemonError::AlreadyRunning { pid: None, pidfile } => {
+                write!(f, "already running according to {}", pidfile.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_pidfile() {
+        let path = env::temp_dir().join(format!("drcom-rs-{}.pid", process::id()));
+        {
+            let pidfile = Pidfile::create(&path).unwrap();
+            assert_eq!(
+                fs::read_to_string(pidfile.path()).unwrap(),
+                format!("{}\n", process::id())
+            );
+        }
+        assert!(!path.exists());
+
+        // the lock is per open file, so a second one conflicts even in this process
+        let pidfile = Pidfile::create(&path).unwrap();
+        match Pidfile::create(&path) {
+            Err(DaemonError::AlreadyRunning { pid, .. }) => {
+                assert_eq!(pid, Some(process::id() as i32))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        drop(pidfile);
+        assert!(!path.exists());
+
+        // a stale pidfile nobody holds a lock on is taken over
+        fs::write(&path, "1\n").unwrap();
+        let pidfile = Pidfile::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", process::id()));
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_signals() {
+        let signals = Signals::register().unwrap();
+        assert!(!signals.take_reload());
+        signal_hook::low_level::raise(SIGHUP).unwrap();
+        assert!(signals.take_reload());
+        assert!(!signals.take_reload());
+        assert!(!signals.take_shutdown());
+    }
+}
@@ -0,0 +1,398 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use crate::drcom::wired::dialer::{LoginAccount, HOSTNAME_MAX_LEN};
+
+/// Where sysfs, procfs and `/etc` are found on a running system.
+pub const DEFAULT_SYSTEM_ROOT: &str = "/";
+
+const RTF_GATEWAY: u16 = 0x0002;
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    IOError(PathBuf, io::Error),
+    UnknownInterface(String),
+    Malformed(PathBuf),
+}
+
+type DiscoveryResult<T> = Result<T, DiscoveryError>;
+
+/// What the official client reports about the machine, read from one interface.
+///
+/// Files are looked up below a root directory so that a fixture tree can stand
+/// in for `/sys`, `/proc` and `/etc`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name:        String,
+    /// `None` for interfaces without a hardware address, e.g. tun devices.
+    pub mac_address: Option<[u8; 6]>,
+    /// Local addresses in the subnets routed through the interface, at most 4.
+    pub ipaddresses: Vec<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub dhcp_server: Option<Ipv4Addr>,
+    pub hostname:    Option<String>,
+}
+
+impl InterfaceInfo {
+    pub fn discover<P: AsRef<Path>>(root: P, name: &str) -> DiscoveryResult<Self> {
+        let root = root.as_ref();
+        let device = root.join("sys/class/net").join(name);
+        if name.is_empty() || name.contains('/') || !device.is_dir() {
+            return Err(DiscoveryError::UnknownInterface(name.to_string()));
+        }
+
+        let mac_address = match read_optional(&device.join("address"))? {
+            Some(content) => parse_mac_address(content.trim()).filter(|mac| *mac != [0u8; 6]),
+            None => None,
+        };
+
+        let fib_trie = root.join("proc/net/fib_trie");
+        let local_addresses = read_optional(&fib_trie)?
+            .map(|content| parse_local_addresses(&content))
+            .unwrap_or_default();
+        let route = root.join("proc/net/route");
+        let subnets = match read_optional(&route)? {
+            Some(content) => parse_subnets(&content, name)
+                .ok_or(DiscoveryError::Malformed(route))?,
+            None => Vec::new(),
+        };
+        let ipaddresses = local_addresses
+            .into_iter()
+            .filter(|address| {
+                let address = u32::from(*address);
+                subnets.iter().any(|(network, mask)| address & mask == *network)
+            })
+            .take(4)
+            .collect();
+
+        let dhcp_server = match read_optional(&device.join("ifindex"))? {
+            Some(index) => read_optional(&root.join("run/systemd/netif/leases").join(index.trim()))?
+                .and_then(|lease| networkd_server_address(&lease)),
+            None => None,
+        };
+        let dhcp_server = match dhcp_server {
+            Some(server) => Some(server),
+            None => read_optional(&root.join(format!("var/lib/dhcp/dhclient.{}.leases", name)))?
+                .and_then(|leases| dhclient_server_identifier(&leases)),
+        };
+
+        let hostname = read_optional(&root.join("proc/sys/kernel/hostname"))?
+            .map(|content| truncate(content.trim(), HOSTNAME_MAX_LEN).to_string())
+            .filter(|hostname| !hostname.is_empty());
+
+        Ok(InterfaceInfo {
+            name: name.to_string(),
+            mac_address,
+            ipaddresses,
+            dns_servers: discover_dns_servers(root)?,
+            dhcp_server,
+            hostname,
+        })
+    }
+
+    /// Fill in whatever was discovered, leaving the rest of `account` alone.
+    pub fn apply(&self, account: &mut LoginAccount) {
+        if let Some(mac_address) = self.mac_address {
+            account.mac_address(mac_address);
+        }
+        if !self.ipaddresses.is_empty() {
+            account
+                .ipaddresses(&self.ipaddresses)
+                .adapter_count(self.ipaddresses.len() as u8);
+        }
+        if let Some(dns_server) = self.dns_servers.first() {
+            account.dns_server(*dns_server);
+        }
+        if let Some(backup_dns_server) = self.dns_servers.get(1) {
+            account.backup_dns_server(*backup_dns_server);
+        }
+        if let Some(dhcp_server) = self.dhcp_server {
+            account.dhcp_server(dhcp_server);
+        }
+        if let Some(ref hostname) = self.hostname {
+            account.hostname(hostname.clone());
+        }
+    }
+}
+
+/// Contents of `path`, `None` when it does not exist.
+fn read_optional(path: &Path) -> DiscoveryResult<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(DiscoveryError::IOError(path.to_path_buf(), e)),
+    }
+}
+
+fn parse_mac_address(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut octets = s.split(':');
+    for byte in mac.iter_mut() {
+        *byte = u8::from_str_radix(octets.next()?, 16).ok()?;
+    }
+    octets.next().is_none().then_some(mac)
+}
+
+/// Host addresses from `/proc/net/fib_trie`, each leaf is followed by its routes:
+///
+/// ```text
+///            |-- 192.0.2.2
+///               /32 host LOCAL
+/// ```
+fn parse_local_addresses(content: &str) -> Vec<Ipv4Addr> {
+    let mut addresses = Vec::new();
+    let mut leaf = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(address) = line.strip_prefix("|-- ") {
+            leaf = address.parse::<Ipv4Addr>().ok();
+        } else if line.starts_with("/32 host LOCAL") {
+            match leaf {
+                Some(address) if !address.is_loopback() && !addresses.contains(&address) => {
+                    addresses.push(address)
+                }
+                _ => {}
+            }
+        }
+    }
+    addresses
+}
+
+/// Directly connected `(network, mask)` pairs of `interface` from `/proc/net/route`,
+/// `None` if a line of it cannot be read.
+fn parse_subnets(content: &str, interface: &str) -> Option<Vec<(u32, u32)>> {
+    let mut subnets = Vec::new();
+    for line in content.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0] != interface {
+            continue;
+        }
+        let (destination, flags, mask) = (fields.get(1)?, fields.get(3)?, fields.get(7)?);
+        let flags = u16::from_str_radix(flags, 16).ok()?;
+        let mask = route_address(mask)?;
+        if flags & RTF_GATEWAY != 0 || mask == 0 {
+            continue;
+        }
+        subnets.push((route_address(destination)?, mask));
+    }
+    Some(subnets)
+}
+
+/// The kernel prints addresses in network byte order as a native integer.
+fn route_address(hex: &str) -> Option<u32> {
+    let raw = u32::from_str_radix(hex, 16).ok()?;
+    Some(u32::from(Ipv4Addr::from(raw.to_ne_bytes())))
+}
+
+/// IPv4 name servers from resolv.conf, looking past the systemd-resolved stub.
+fn discover_dns_servers(root: &Path) -> DiscoveryResult<Vec<Ipv4Addr>> {
+    for path in ["etc/resolv.conf", "run/systemd/resolve/resolv.conf"] {
+        let servers = match read_optional(&root.join(path))? {
+            Some(content) => parse_nameservers(&content),
+            None => continue,
+        };
+        let stubbed = servers.iter().any(Ipv4Addr::is_loopback);
+        let servers: Vec<_> = servers.into_iter().filter(|s| !s.is_loopback()).collect();
+        if !servers.is_empty() || !stubbed {
+            return Ok(servers);
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn parse_nameservers(content: &str) -> Vec<Ipv4Addr> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => words.next()?.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `SERVER_ADDRESS=` of a systemd-networkd lease file.
+fn networkd_server_address(lease: &str) -> Option<Ipv4Addr> {
+    lease
+        .lines()
+        .find_map(|line| line.strip_prefix("SERVER_ADDRESS="))
+        .and_then(|address| address.trim().parse().ok())
+}
+
+/// `option dhcp-server-identifier` of the latest lease in a dhclient leases file.
+fn dhclient_server_identifier(leases: &str) -> Option<Ipv4Addr> {
+    leases
+        .lines()
+        .rev()
+        .filter_map(|line| line.trim().strip_prefix("option dhcp-server-identifier "))
+        .find_map(|address| address.trim_end_matches(';').trim().parse().ok())
+}
+
+fn truncate(s: &str, max_len: usize) -> &str {
+    let mut end = s.len().min(max_len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscoveryError::IOError(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            DiscoveryError::UnknownInterface(name) => write!(f, "no interface named `{}`", name),
+            DiscoveryError::Malformed(path) => write!(f, "cannot parse {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const FIB_TRIE: &str = "\
+Main:
+  +-- 0.0.0.0/0 3 0 5
+     |-- 0.0.0.0
+        /0 universe UNICAST
+     +-- 127.0.0.0/8 2 0 2
+        +-- 127.0.0.0/31 1 0 0
+           |-- 127.0.0.0
+              /8 host LOCAL
+           |-- 127.0.0.1
+              /32 host LOCAL
+     +-- 192.0.2.0/24 2 0 2
+        +-- 192.0.2.0/30 2 0 2
+           |-- 192.0.2.0
+              /24 link UNICAST
+           |-- 192.0.2.2
+              /32 host LOCAL
+           |-- 192.0.2.3
+              /32 host LOCAL
+        |-- 192.0.2.255
+           /32 link BROADCAST
+     |-- 198.51.100.7
+        /32 host LOCAL
+Local:
+     +-- 192.0.2.0/24 2 0 2
+           |-- 192.0.2.2
+              /32 host LOCAL
+";
+
+    const ROUTE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t010200C0\t0003\t0\t0\t0\t00000000\t0\t0\t0
+eth0\t000200C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+wlan0\t006433C6\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+";
+
+    fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("drcom-rs-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, content) in files.iter() {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_interface_discover() {
+        let root = fixture("discover", &[
+            ("sys/class/net/eth0/address", "b8:88:e3:05:16:80\n"),
+            ("sys/class/net/eth0/ifindex", "2\n"),
+            ("proc/net/fib_trie", FIB_TRIE),
+            ("proc/net/route", ROUTE),
+            ("proc/sys/kernel/hostname", "dorm-desktop-with-a-very-long-hostname\n"),
+            ("etc/resolv.conf", "# stub\nnameserver 127.0.0.53\noptions edns0\n"),
+            (
+                "run/systemd/resolve/resolv.conf",
+                "nameserver 10.10.0.21\nnameserver 2001:db8::53\nnameserver 10.10.0.22\n",
+            ),
+            ("run/systemd/netif/leases/2", "ADDRESS=192.0.2.2\nSERVER_ADDRESS=192.0.2.1\n"),
+        ]);
+        let info = InterfaceInfo::discover(&root, "eth0").unwrap();
+        assert_eq!(
+            info,
+            InterfaceInfo {
+                name:        String::from("eth0"),
+                mac_address: Some([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80]),
+                ipaddresses: vec![Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(192, 0, 2, 3)],
+                dns_servers: vec![Ipv4Addr::new(10, 10, 0, 21), Ipv4Addr::new(10, 10, 0, 22)],
+                dhcp_server: Some(Ipv4Addr::new(192, 0, 2, 1)),
+                hostname:    Some(String::from("dorm-desktop-with-a-very-long-ho")),
+            }
+        );
+
+        let mut account = LoginAccount::new("usernameusername", "password", [0u8; 4]);
+        info.apply(&mut account);
+        let mut expected = LoginAccount::new("usernameusername", "password", [0u8; 4]);
+        expected
+            .mac_address([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80])
+            .ipaddresses(&[Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(192, 0, 2, 3)])
+            .adapter_count(2)
+            .dns_server(Ipv4Addr::new(10, 10, 0, 21))
+            .backup_dns_server(Ipv4Addr::new(10, 10, 0, 22))
+            .dhcp_server(Ipv4Addr::new(192, 0, 2, 1))
+            .hostname(String::from("dorm-desktop-with-a-very-long-ho"));
+        assert_eq!(format!("{:?}", account), format!("{:?}", expected));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_interface_discover_partial() {
+        let root = fixture("discover-partial", &[
+            ("sys/class/net/tun0/address", "00:00:00:00:00:00\n"),
+            ("var/lib/dhcp/dhclient.tun0.leases", concat!(
+                "lease {\n  option dhcp-server-identifier 10.0.0.1;\n}\n",
+                "lease {\n  option dhcp-server-identifier 10.0.0.254;\n}\n"
+            )),
+        ]);
+        let info = InterfaceInfo::discover(&root, "tun0").unwrap();
+        assert_eq!(
+            info,
+            InterfaceInfo {
+                name: String::from("tun0"),
+                dhcp_server: Some(Ipv4Addr::new(10, 0, 0, 254)),
+                ..Default::default()
+            }
+        );
+
+        let mut account = LoginAccount::new("usernameusername", "password", [0u8; 4]);
+        InterfaceInfo::default().apply(&mut account);
+        assert_eq!(
+            format!("{:?}", account),
+            format!("{:?}", LoginAccount::new("usernameusername", "password", [0u8; 4]))
+        );
+
+        assert!(matches!(
+            InterfaceInfo::discover(&root, "eth0"),
+            Err(DiscoveryError::UnknownInterface(_))
+        ));
+        assert!(matches!(
+            InterfaceInfo::discover(&root, "../tun0"),
+            Err(DiscoveryError::UnknownInterface(_))
+        ));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_parse_subnets() {
+        assert_eq!(
+            parse_subnets(ROUTE, "eth0"),
+            Some(vec![(u32::from(Ipv4Addr::new(192, 0, 2, 0)), 0xffffff00)])
+        );
+        assert_eq!(parse_subnets(ROUTE, "eth1"), Some(vec![]));
+        assert_eq!(parse_subnets("Iface\neth0\tzz\n", "eth0"), None);
+    }
+}
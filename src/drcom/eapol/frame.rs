@@ -0,0 +1,336 @@
+use std::net::Ipv4Addr;
+use std::{io, result};
+
+use byteorder::{ByteOrder, NetworkEndian};
+
+use crate::common::bytes::BytesAbleNum;
+use crate::common::reader::{ReadBytesError, ReaderHelper};
+use crate::crypto::hash::{HasherBuilder, HasherType};
+
+/// Ethertype of 802.1X port access entity frames.
+pub const ETH_P_PAE: u16 = 0x888e;
+/// Multicast address EAPOL-Start is sent to before the authenticator is known.
+pub const PAE_GROUP_ADDRESS: [u8; 6] = [0x01, 0x80, 0xc2, 0x00, 0x00, 0x03];
+
+const EAPOL_VERSION: u8 = 1;
+const MD5_VALUE_SIZE: u8 = 16;
+
+#[derive(Debug)]
+pub enum FrameError {
+    EthertypeMismatch(u16),
+    LengthMismatch(usize, usize),
+    PacketReadError(ReadBytesError),
+}
+
+type FrameResult<T> = result::Result<T, FrameError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EAPOLType {
+    EAPPacket,
+    Start,
+    Logoff,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EAPCode {
+    Request,
+    Response,
+    Success,
+    Failure,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EAPType {
+    Identity,
+    Notification,
+    MD5Challenge,
+    Unknown(u8),
+}
+
+/// Dr.COM appends the client address after the identity of every response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrCOMTrailer {
+    pub eap_type: EAPType,
+    pub host_ip:  Ipv4Addr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EAPPacket {
+    pub code:       EAPCode,
+    pub identifier: u8,
+    /// Only present in requests and responses.
+    pub eap_type:   Option<EAPType>,
+    pub data:       Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EAPOLFrame {
+    pub destination: [u8; 6],
+    pub source:      [u8; 6],
+    pub eapol_type:  EAPOLType,
+    pub eap_packet:  Option<EAPPacket>,
+}
+
+macro_rules! impl_u8_enum {
+    (
+        $ty:ident { $( $variant:ident = $value:expr ),* }
+    ) => {
+        impl $ty {
+            pub fn as_u8(&self) -> u8 {
+                match *self {
+                    $( $ty::$variant => $value, )*
+                    $ty::Unknown(v) => v,
+                }
+            }
+
+            pub fn from_u8(value: u8) -> Self {
+                match value {
+                    $( $value => $ty::$variant, )*
+                    v => $ty::Unknown(v),
+                }
+            }
+        }
+    }
+}
+
+impl_u8_enum!(EAPOLType {
+    EAPPacket = 0,
+    Start = 1,
+    Logoff = 2
+});
+
+impl_u8_enum!(EAPCode {
+    Request = 1,
+    Response = 2,
+    Success = 3,
+    Failure = 4
+});
+
+impl_u8_enum!(EAPType {
+    Identity = 1,
+    Notification = 2,
+    MD5Challenge = 4
+});
+
+impl DrCOMTrailer {
+    fn tag(&self) -> u8 {
+        match self.eap_type {
+            EAPType::MD5Challenge => 0x2a,
+            _ => 0x00,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut result = vec![0x00, 0x44, 0x61, self.tag(), 0x00];
+        result.extend_from_slice(&self.host_ip.octets());
+        result
+    }
+}
+
+impl EAPPacket {
+    #[inline]
+    fn header_length() -> usize {
+        // code + identifier + length
+        1 + 1 + 2
+    }
+
+    pub fn identity_response(identifier: u8, identity: &str, trailer: &DrCOMTrailer) -> Self {
+        let mut data = identity.as_bytes().to_vec();
+        data.extend(trailer.as_bytes());
+        EAPPacket {
+            code: EAPCode::Response,
+            identifier,
+            eap_type: Some(EAPType::Identity),
+            data,
+        }
+    }
+
+    pub fn md5_challenge_response(
+        identifier: u8,
+        identity: &str,
+        password: &str,
+        challenge: &[u8],
+        trailer: &DrCOMTrailer,
+    ) -> Self {
+        let mut md5 = HasherBuilder::build(HasherType::MD5);
+        md5.update(&[identifier]);
+        md5.update(password.as_bytes());
+        md5.update(challenge);
+
+        let mut data = vec![MD5_VALUE_SIZE];
+        data.extend(md5.finish());
+        data.extend_from_slice(identity.as_bytes());
+        data.extend(trailer.as_bytes());
+        EAPPacket {
+            code: EAPCode::Response,
+            identifier,
+            eap_type: Some(EAPType::MD5Challenge),
+            data,
+        }
+    }
+
+    /// Challenge value carried by an MD5-Challenge request.
+    pub fn md5_challenge(&self) -> Option<&[u8]> {
+        match self.eap_type {
+            Some(EAPType::MD5Challenge) => {
+                let value_size = *self.data.first()? as usize;
+                self.data.get(1..1 + value_size)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn packet_length(&self) -> usize {
+        Self::header_length() + self.eap_type.map_or(0, |_| 1) + self.data.len()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.packet_length());
+        result.push(self.code.as_u8());
+        result.push(self.identifier);
+        result.extend((self.packet_length() as u16).as_bytes_be());
+        if let Some(eap_type) = self.eap_type {
+            result.push(eap_type.as_u8());
+        }
+        result.extend_from_slice(&self.data);
+        result
+    }
+
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> FrameResult<Self>
+    where
+        R: io::Read,
+    {
+        let header_bytes = input
+            .read_bytes(Self::header_length())
+            .map_err(FrameError::PacketReadError)?;
+        let code = EAPCode::from_u8(header_bytes[0]);
+        let identifier = header_bytes[1];
+        let length = NetworkEndian::read_u16(&header_bytes[2..4]) as usize;
+        if length < Self::header_length() {
+            return Err(FrameError::LengthMismatch(length, Self::header_length()));
+        }
+
+        let mut body_bytes = input
+            .read_bytes(length - Self::header_length())
+            .map_err(FrameError::PacketReadError)?;
+        let eap_type = match code {
+            EAPCode::Request | EAPCode::Response if !body_bytes.is_empty() => {
+                Some(EAPType::from_u8(body_bytes.remove(0)))
+            }
+            _ => None,
+        };
+
+        Ok(EAPPacket {
+            code,
+            identifier,
+            eap_type,
+            data: body_bytes,
+        })
+    }
+}
+
+impl EAPOLFrame {
+    #[inline]
+    fn ethernet_header_length() -> usize {
+        // destination + source + ethertype
+        6 + 6 + 2
+    }
+
+    #[inline]
+    fn eapol_header_length() -> usize {
+        // version + type + body length
+        1 + 1 + 2
+    }
+
+    pub fn start(source: [u8; 6]) -> Self {
+        EAPOLFrame {
+            destination: PAE_GROUP_ADDRESS,
+            source,
+            eapol_type: EAPOLType::Start,
+            eap_packet: None,
+        }
+    }
+
+    pub fn logoff(destination: [u8; 6], source: [u8; 6]) -> Self {
+        EAPOLFrame {
+            destination,
+            source,
+            eapol_type: EAPOLType::Logoff,
+            eap_packet: None,
+        }
+    }
+
+    pub fn eap(destination: [u8; 6], source: [u8; 6], eap_packet: EAPPacket) -> Self {
+        EAPOLFrame {
+            destination,
+            source,
+            eapol_type: EAPOLType::EAPPacket,
+            eap_packet: Some(eap_packet),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let body = self
+            .eap_packet
+            .as_ref()
+            .map_or_else(Vec::new, |p| p.as_bytes());
+
+        let mut result = Vec::with_capacity(
+            Self::ethernet_header_length() + Self::eapol_header_length() + body.len(),
+        );
+        result.extend_from_slice(&self.destination);
+        result.extend_from_slice(&self.source);
+        result.extend(ETH_P_PAE.as_bytes_be());
+        result.push(EAPOL_VERSION);
+        result.push(self.eapol_type.as_u8());
+        result.extend((body.len() as u16).as_bytes_be());
+        result.extend(body);
+        result
+    }
+
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> FrameResult<Self>
+    where
+        R: io::Read,
+    {
+        let ethernet_bytes = input
+            .read_bytes(Self::ethernet_header_length())
+            .map_err(FrameError::PacketReadError)?;
+        let mut destination = [0u8; 6];
+        destination.copy_from_slice(&ethernet_bytes[0..6]);
+        let mut source = [0u8; 6];
+        source.copy_from_slice(&ethernet_bytes[6..12]);
+        let ethertype = NetworkEndian::read_u16(&ethernet_bytes[12..14]);
+        if ethertype != ETH_P_PAE {
+            return Err(FrameError::EthertypeMismatch(ethertype));
+        }
+
+        let eapol_bytes = input
+            .read_bytes(Self::eapol_header_length())
+            .map_err(FrameError::PacketReadError)?;
+        let eapol_type = EAPOLType::from_u8(eapol_bytes[1]);
+        let body_length = NetworkEndian::read_u16(&eapol_bytes[2..4]) as usize;
+
+        let eap_packet = match eapol_type {
+            EAPOLType::EAPPacket => {
+                let packet = EAPPacket::from_bytes(input)?;
+                if packet.packet_length() != body_length {
+                    return Err(FrameError::LengthMismatch(
+                        packet.packet_length(),
+                        body_length,
+                    ));
+                }
+                Some(packet)
+            }
+            _ => None,
+        };
+
+        Ok(EAPOLFrame {
+            destination,
+            source,
+            eapol_type,
+            eap_packet,
+        })
+    }
+}
@@ -0,0 +1,194 @@
+use std::io;
+use std::net::Ipv4Addr;
+
+use crate::common::secret::SecretString;
+use crate::drcom::eapol::frame::{
+    DrCOMTrailer, EAPCode, EAPOLFrame, EAPOLType, EAPPacket, EAPType, FrameError,
+    PAE_GROUP_ADDRESS,
+};
+
+/// Frames `authenticate` handles before it gives up on the authenticator.
+const MAX_AUTHENTICATE_FRAMES: usize = 32;
+
+/// Link layer the supplicant exchanges whole ethernet frames over.
+pub trait FrameTransport {
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()>;
+    /// Must fail with a timeout rather than block forever on a silent link.
+    fn recv_frame(&mut self) -> io::Result<Vec<u8>>;
+}
+
+#[derive(Debug)]
+pub enum SupplicantError {
+    TransportError(io::Error),
+    FrameError(FrameError),
+    MalformedChallenge,
+    TooManyFrames,
+    AuthenticationFailed,
+}
+
+type SupplicantResult<T> = Result<T, SupplicantError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupplicantState {
+    Disconnected,
+    Started,
+    Identified,
+    Challenged,
+    Authenticated,
+    Failed,
+}
+
+pub struct Supplicant<T: FrameTransport> {
+    transport:     T,
+    identity:      String,
+    password:      SecretString,
+    mac_address:   [u8; 6],
+    host_ip:       Ipv4Addr,
+    authenticator: Option<[u8; 6]>,
+    state:         SupplicantState,
+}
+
+impl<T: FrameTransport> Supplicant<T> {
+    pub fn new(
+        transport: T,
+        identity: &str,
+        password: &str,
+        mac_address: [u8; 6],
+        host_ip: Ipv4Addr,
+    ) -> Self {
+        Supplicant {
+            transport,
+            identity: identity.to_string(),
+            password: SecretString::from(password),
+            mac_address,
+            host_ip,
+            authenticator: None,
+            state: SupplicantState::Disconnected,
+        }
+    }
+
+    pub fn state(&self) -> SupplicantState {
+        self.state
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    fn send(&mut self, frame: &EAPOLFrame) -> SupplicantResult<()> {
+        self.transport
+            .send_frame(&frame.as_bytes())
+            .map_err(SupplicantError::TransportError)
+    }
+
+    fn respond(&mut self, eap_packet: EAPPacket) -> SupplicantResult<()> {
+        let destination = self.authenticator.unwrap_or(PAE_GROUP_ADDRESS);
+        self.send(&EAPOLFrame::eap(destination, self.mac_address, eap_packet))
+    }
+
+    fn trailer(&self, eap_type: EAPType) -> DrCOMTrailer {
+        DrCOMTrailer {
+            eap_type,
+            host_ip: self.host_ip,
+        }
+    }
+
+    pub fn start(&mut self) -> SupplicantResult<()> {
+        self.authenticator = None;
+        self.send(&EAPOLFrame::start(self.mac_address))?;
+        self.state = SupplicantState::Started;
+        Ok(())
+    }
+
+    pub fn logoff(&mut self) -> SupplicantResult<()> {
+        let destination = self.authenticator.unwrap_or(PAE_GROUP_ADDRESS);
+        self.send(&EAPOLFrame::logoff(destination, self.mac_address))?;
+        self.state = SupplicantState::Disconnected;
+        Ok(())
+    }
+
+    /// Advance the state machine with one received frame, answering it when required.
+    pub fn handle_frame(&mut self, frame: &EAPOLFrame) -> SupplicantResult<SupplicantState> {
+        if frame.destination != self.mac_address && frame.destination != PAE_GROUP_ADDRESS {
+            return Ok(self.state);
+        }
+        let eap_packet = match (frame.eapol_type, &frame.eap_packet) {
+            (EAPOLType::EAPPacket, Some(eap_packet)) => eap_packet,
+            _ => return Ok(self.state),
+        };
+
+        match (eap_packet.code, eap_packet.eap_type) {
+            (EAPCode::Request, Some(EAPType::Identity)) => {
+                self.authenticator = Some(frame.source);
+                let response = EAPPacket::identity_response(
+                    eap_packet.identifier,
+                    &self.identity,
+                    &self.trailer(EAPType::Identity),
+                );
+                self.respond(response)?;
+                if self.state != SupplicantState::Authenticated {
+                    self.state = SupplicantState::Identified;
+                }
+            }
+            (EAPCode::Request, Some(EAPType::MD5Challenge)) => {
+                let challenge = eap_packet
+                    .md5_challenge()
+                    .filter(|challenge| !challenge.is_empty())
+                    .ok_or(SupplicantError::MalformedChallenge)?
+                    .to_vec();
+                let response = EAPPacket::md5_challenge_response(
+                    eap_packet.identifier,
+                    &self.identity,
+                    self.password.expose(),
+                    &challenge,
+                    &self.trailer(EAPType::MD5Challenge),
+                );
+                self.respond(response)?;
+                self.state = SupplicantState::Challenged;
+            }
+            (EAPCode::Request, Some(EAPType::Notification)) => {
+                self.respond(EAPPacket {
+                    code:       EAPCode::Response,
+                    identifier: eap_packet.identifier,
+                    eap_type:   Some(EAPType::Notification),
+                    data:       Vec::new(),
+                })?;
+            }
+            (EAPCode::Success, _) => {
+                self.state = SupplicantState::Authenticated;
+            }
+            (EAPCode::Failure, _) => {
+                self.state = SupplicantState::Failed;
+                return Err(SupplicantError::AuthenticationFailed);
+            }
+            _ => {}
+        }
+        Ok(self.state)
+    }
+
+    /// Receive and handle the next frame, skipping anything that is not EAPOL.
+    pub fn poll(&mut self) -> SupplicantResult<SupplicantState> {
+        let bytes = self
+            .transport
+            .recv_frame()
+            .map_err(SupplicantError::TransportError)?;
+        let mut reader = io::BufReader::new(&bytes[..]);
+        match EAPOLFrame::from_bytes(&mut reader) {
+            Ok(frame) => self.handle_frame(&frame),
+            Err(FrameError::EthertypeMismatch(_)) => Ok(self.state),
+            Err(e) => Err(SupplicantError::FrameError(e)),
+        }
+    }
+
+    /// Send EAPOL-Start and answer requests until the authenticator reports success,
+    /// giving up after `MAX_AUTHENTICATE_FRAMES` frames without it.
+    pub fn authenticate(&mut self) -> SupplicantResult<()> {
+        self.start()?;
+        for _ in 0..MAX_AUTHENTICATE_FRAMES {
+            if self.poll()? == SupplicantState::Authenticated {
+                return Ok(());
+            }
+        }
+        Err(SupplicantError::TooManyFrames)
+    }
+}
@@ -0,0 +1,199 @@
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use std::{io, result};
+
+use rand::Rng;
+use serde_json::Value;
+
+use crate::common::http::{self, percent_encode};
+use crate::common::secret::SecretString;
+
+#[derive(Debug)]
+pub enum PortalError {
+    IOError(io::Error),
+    HttpStatus(u16),
+    MalformedResponse(String),
+}
+
+type PortalResult<T> = result::Result<T, PortalError>;
+
+const DEFAULT_MKKEY: &str = "123456";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct PortalAccount {
+    username: String,
+    password: SecretString,
+    mkkey:    String,
+}
+
+#[derive(Debug)]
+pub struct PortalDialer {
+    server:  SocketAddr,
+    host:    String,
+    timeout: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PortalLoginResponse {
+    pub success:   bool,
+    pub message:   Option<String>,
+    pub client_ip: Option<Ipv4Addr>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PortalLogoutResponse {
+    pub success: bool,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PortalStatus {
+    pub online:            bool,
+    pub username:          Option<String>,
+    pub client_ip:         Option<Ipv4Addr>,
+    pub used_time_minutes: Option<u64>,
+    pub used_traffic_kb:   Option<u64>,
+    pub balance:           Option<u64>,
+}
+
+/// Extract the JSON object from a `callback({...})` JSONP reply, plain JSON is accepted too.
+pub fn parse_jsonp(body: &[u8]) -> PortalResult<Value> {
+    let text = String::from_utf8_lossy(body);
+    let text = text.trim().trim_end_matches(';');
+    let json = match (text.find('('), text.rfind(')')) {
+        (Some(start), Some(end)) if start < end && !text.starts_with('{') => &text[start + 1..end],
+        _ => text,
+    };
+    serde_json::from_str(json).map_err(|e| PortalError::MalformedResponse(e.to_string()))
+}
+
+fn field_u64(value: &Value, key: &str) -> Option<u64> {
+    match value.get(key)? {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn field_string(value: &Value, key: &str) -> Option<String> {
+    match value.get(key)? {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn field_ipv4(value: &Value, keys: &[&str]) -> Option<Ipv4Addr> {
+    keys.iter()
+        .filter_map(|k| field_string(value, k))
+        .find_map(|s| s.parse().ok())
+}
+
+fn result_code(value: &Value) -> PortalResult<u64> {
+    field_u64(value, "result")
+        .ok_or_else(|| PortalError::MalformedResponse(String::from("missing result field")))
+}
+
+impl PortalAccount {
+    pub fn new(username: &str, password: &str) -> Self {
+        PortalAccount {
+            username: username.to_string(),
+            password: SecretString::from(password),
+            mkkey:    String::from(DEFAULT_MKKEY),
+        }
+    }
+
+    pub fn mkkey(&mut self, value: String) -> &mut Self {
+        self.mkkey = value;
+        self
+    }
+
+    pub fn login_path(&self, nonce: u16) -> String {
+        format!(
+            "/drcom/login?callback=dr1003&DDDDD={}&upass={}&0MKKey={}&R1=0&R3=0&R6=0&para=00&v6ip=&v={}",
+            percent_encode(&self.username),
+            percent_encode(self.password.expose()),
+            percent_encode(&self.mkkey),
+            nonce
+        )
+    }
+}
+
+impl PortalLoginResponse {
+    pub fn from_bytes(body: &[u8]) -> PortalResult<Self> {
+        let value = parse_jsonp(body)?;
+        Ok(PortalLoginResponse {
+            success:   result_code(&value)? == 1,
+            message:   field_string(&value, "msga").or_else(|| field_string(&value, "msg")),
+            client_ip: field_ipv4(&value, &["v46ip", "v4ip", "ss5"]),
+        })
+    }
+}
+
+impl PortalLogoutResponse {
+    pub fn from_bytes(body: &[u8]) -> PortalResult<Self> {
+        let value = parse_jsonp(body)?;
+        Ok(PortalLogoutResponse {
+            success: result_code(&value)? == 1,
+            message: field_string(&value, "msga").or_else(|| field_string(&value, "msg")),
+        })
+    }
+}
+
+impl PortalStatus {
+    pub fn from_bytes(body: &[u8]) -> PortalResult<Self> {
+        let value = parse_jsonp(body)?;
+        Ok(PortalStatus {
+            online:            result_code(&value)? == 1,
+            username:          field_string(&value, "uid").or_else(|| field_string(&value, "NID")),
+            client_ip:         field_ipv4(&value, &["v4ip", "v46ip", "ss5"]),
+            used_time_minutes: field_u64(&value, "time"),
+            used_traffic_kb:   field_u64(&value, "flow"),
+            balance:           field_u64(&value, "fee"),
+        })
+    }
+}
+
+impl PortalDialer {
+    pub fn new(server: &str) -> PortalResult<Self> {
+        let addr = server
+            .to_socket_addrs()
+            .or_else(|_| (server, 80u16).to_socket_addrs())
+            .map_err(PortalError::IOError)?
+            .next()
+            .ok_or_else(|| PortalError::IOError(io::Error::from(io::ErrorKind::NotFound)))?;
+        Ok(PortalDialer {
+            server:  addr,
+            host:    server.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    pub fn timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeout = value;
+        self
+    }
+
+    fn request(&self, path: &str) -> PortalResult<Vec<u8>> {
+        let response =
+            http::get(&self.server, &self.host, path, self.timeout).map_err(PortalError::IOError)?;
+        if response.status != 200 {
+            return Err(PortalError::HttpStatus(response.status));
+        }
+        Ok(response.body)
+    }
+
+    pub fn login(&self, account: &PortalAccount) -> PortalResult<PortalLoginResponse> {
+        let nonce = rand::thread_rng().gen_range(1000..10000);
+        PortalLoginResponse::from_bytes(&self.request(&account.login_path(nonce))?)
+    }
+
+    pub fn logout(&self) -> PortalResult<PortalLogoutResponse> {
+        PortalLogoutResponse::from_bytes(&self.request("/drcom/logout?callback=dr1004")?)
+    }
+
+    pub fn check_status(&self) -> PortalResult<PortalStatus> {
+        PortalStatus::from_bytes(&self.request("/drcom/chkstatus?callback=dr1002")?)
+    }
+}
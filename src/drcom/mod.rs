@@ -4,14 +4,18 @@ use std::io;
 
 use crate::common::reader::{ReadBytesError, ReaderHelper};
 
+pub mod eapol;
+pub mod eportal;
+pub mod pppoe;
 pub mod wired;
 
 #[cfg(test)]
 mod tests;
 
-const PASSWORD_MAX_LEN: usize = 16;
-const USERNAME_MAX_LEN: usize = 16;
+pub(crate) const PASSWORD_MAX_LEN: usize = 16;
+pub(crate) const USERNAME_MAX_LEN: usize = 16;
 const PACKET_MAGIC_NUMBER: u16 = 0x0103u16;
+const LOGOUT_MAGIC_NUMBER: u16 = 0x0106u16;
 
 pub trait DrCOMFlag: Debug {
     fn as_u32(&self) -> u32;
@@ -33,7 +37,7 @@ pub trait DrCOMResponseCommon {
     fn validate_stream<R, V>(
         input: &mut io::BufReader<R>,
         validator: V,
-    ) -> Result<(), DrCOMValidateError>
+    ) -> Result<u8, DrCOMValidateError>
     where
         R: io::Read,
         V: FnOnce(u8) -> bool,
@@ -45,6 +49,6 @@ pub trait DrCOMResponseCommon {
         if !validator(code) {
             return Err(DrCOMValidateError::CodeMismatch(code));
         }
-        Ok(())
+        Ok(code)
     }
 }
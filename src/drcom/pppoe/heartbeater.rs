@@ -0,0 +1,280 @@
+use std::net::Ipv4Addr;
+use std::num::Wrapping;
+use std::{io, result};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::common::bytes::BytesAbleNum;
+use crate::common::reader::{ReadBytesError, ReaderHelper};
+use crate::crypto::hash::{HasherBuilder, HasherType};
+use crate::drcom::{DrCOMCommon, DrCOMFlag, DrCOMResponseCommon, DrCOMValidateError};
+
+#[derive(Debug)]
+pub enum HeartbeatError {
+    ValidateError(DrCOMValidateError),
+    PacketReadError(ReadBytesError),
+}
+
+type HeartbeatResult<T> = result::Result<T, HeartbeatError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CRCHasherType {
+    NoEncrypt,
+    MD5,
+    MD4,
+    SHA1,
+}
+
+#[derive(Debug)]
+pub struct ChallengeRequest {
+    sequence: u8,
+}
+
+#[derive(Debug)]
+pub struct ChallengeResponse {
+    pub sequence:       u8,
+    pub challenge_seed: [u8; 4],
+    pub source_ip:      Ipv4Addr,
+}
+
+#[derive(Debug)]
+pub struct HeartbeatRequest<'a> {
+    sequence:       u8,
+    mac_address:    [u8; 6],
+    source_ip:      Ipv4Addr,
+    flag:           &'a (dyn DrCOMFlag + 'a),
+    challenge_seed: [u8; 4],
+}
+
+#[derive(Debug)]
+pub struct HeartbeatResponse {
+    pub sequence: u8,
+}
+
+#[derive(Debug)]
+pub enum HeartbeatFlag {
+    First,
+    NotFirst,
+}
+
+const DRCOM_DIAL_EXT_PROTO_CRC_INIT: u32 = 20_000_711;
+const DRCOM_DIAL_EXT_PROTO_CRC_MULTIPLIER: u32 = 19_680_126;
+
+impl CRCHasherType {
+    pub fn from_seed(challenge_seed: &[u8; 4]) -> Self {
+        match LittleEndian::read_u32(challenge_seed) % 4 {
+            1 => CRCHasherType::MD5,
+            2 => CRCHasherType::MD4,
+            3 => CRCHasherType::SHA1,
+            _ => CRCHasherType::NoEncrypt,
+        }
+    }
+
+    /// Digest bytes picked into the 8-byte crc field, `None` for the plain checksum.
+    fn digest_picks(&self) -> Option<(HasherType, [usize; 8])> {
+        match *self {
+            CRCHasherType::NoEncrypt => None,
+            CRCHasherType::MD5 => Some((HasherType::MD5, [2, 3, 8, 9, 5, 6, 13, 14])),
+            CRCHasherType::MD4 => Some((HasherType::MD4, [1, 2, 8, 9, 4, 5, 11, 12])),
+            CRCHasherType::SHA1 => Some((HasherType::SHA1, [2, 3, 9, 10, 5, 6, 15, 16])),
+        }
+    }
+
+    /// Compute the crc field, the digests only cover `challenge_seed` while the plain
+    /// checksum covers `data`, whose crc field must hold the initial value.
+    pub fn check_sum(&self, challenge_seed: &[u8; 4], data: &[u8]) -> [u8; 8] {
+        let mut result = [0u8; 8];
+        match self.digest_picks() {
+            Some((hasher_type, picks)) => {
+                let mut hasher = HasherBuilder::build(hasher_type);
+                hasher.update(challenge_seed);
+                let digest = hasher.finish();
+                for (i, pick) in picks.iter().enumerate() {
+                    result[i] = digest[*pick];
+                }
+            }
+            None => {
+                let mut sum = Wrapping(0u32);
+                for chunk in data.chunks(4) {
+                    let mut word = [0u8; 4];
+                    word[..chunk.len()].copy_from_slice(chunk);
+                    sum ^= Wrapping(LittleEndian::read_u32(&word));
+                }
+                sum *= Wrapping(DRCOM_DIAL_EXT_PROTO_CRC_MULTIPLIER);
+                result[..4].copy_from_slice(&sum.0.as_bytes_le());
+            }
+        }
+        result
+    }
+}
+
+impl DrCOMCommon for ChallengeRequest {}
+
+impl ChallengeRequest {
+    pub fn new(sequence: u8) -> Self {
+        ChallengeRequest { sequence }
+    }
+
+    #[inline]
+    fn packet_length() -> usize {
+        // code + sequence + packet length + type + padding?
+        1 + 1 + 2 + 1 + 3
+    }
+
+    #[inline]
+    fn type_id() -> u8 {
+        1u8
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(Self::packet_length());
+        result.push(Self::code());
+        result.push(self.sequence);
+        result.extend((Self::packet_length() as u16).as_bytes_le());
+        result.push(Self::type_id());
+        // padding?
+        result.extend_from_slice(&[0u8; 3]);
+        result
+    }
+}
+
+impl DrCOMCommon for ChallengeResponse {}
+
+impl DrCOMResponseCommon for ChallengeResponse {}
+
+impl ChallengeResponse {
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> HeartbeatResult<Self>
+    where
+        R: io::Read,
+    {
+        // validate packet and consume 1 byte
+        Self::validate_stream(input, |c| c == Self::code())
+            .map_err(HeartbeatError::ValidateError)?;
+
+        let sequence = input
+            .read_bytes(1)
+            .map_err(HeartbeatError::PacketReadError)?[0];
+
+        // drain unknow bytes
+        input
+            .read_bytes(6)
+            .map_err(HeartbeatError::PacketReadError)?;
+
+        let mut challenge_seed = [0u8; 4];
+        challenge_seed.copy_from_slice(
+            &input
+                .read_bytes(4)
+                .map_err(HeartbeatError::PacketReadError)?,
+        );
+
+        let ip_bytes = input
+            .read_bytes(4)
+            .map_err(HeartbeatError::PacketReadError)?;
+        let source_ip = Ipv4Addr::new(ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]);
+
+        Ok(ChallengeResponse {
+            sequence,
+            challenge_seed,
+            source_ip,
+        })
+    }
+
+    pub fn crc_hasher_type(&self) -> CRCHasherType {
+        CRCHasherType::from_seed(&self.challenge_seed)
+    }
+}
+
+impl DrCOMFlag for HeartbeatFlag {
+    fn as_u32(&self) -> u32 {
+        match *self {
+            HeartbeatFlag::First => 0x2a00_6200,
+            HeartbeatFlag::NotFirst => 0x2a00_6300,
+        }
+    }
+}
+
+impl<'a> DrCOMCommon for HeartbeatRequest<'a> {}
+
+impl<'a> HeartbeatRequest<'a> {
+    pub fn new<F>(
+        sequence: u8,
+        mac_address: [u8; 6],
+        source_ip: Ipv4Addr,
+        flag: &'a F,
+        challenge_seed: [u8; 4],
+    ) -> Self
+    where
+        F: DrCOMFlag,
+    {
+        HeartbeatRequest {
+            sequence,
+            mac_address,
+            source_ip,
+            flag,
+            challenge_seed,
+        }
+    }
+
+    #[inline]
+    fn packet_length() -> usize {
+        // code + sequence + packet length + type + uid length + mac address + source ip
+        // + flag + challenge seed + crc + padding?
+        1 + 1 + 2 + 1 + 1 + 6 + 4 + 4 + 4 + 8 + 64
+    }
+
+    #[inline]
+    fn type_id() -> u8 {
+        3u8
+    }
+
+    #[inline]
+    fn crc_offset() -> usize {
+        24
+    }
+
+    pub fn crc_hasher_type(&self) -> CRCHasherType {
+        CRCHasherType::from_seed(&self.challenge_seed)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(Self::packet_length());
+        result.push(Self::code());
+        result.push(self.sequence);
+        result.extend((Self::packet_length() as u16).as_bytes_le());
+        result.push(Self::type_id());
+        // uid length
+        result.push(0u8);
+        result.extend_from_slice(&self.mac_address);
+        result.extend_from_slice(&self.source_ip.octets());
+        result.extend(self.flag.as_u32().as_bytes_le());
+        result.extend_from_slice(&self.challenge_seed);
+        result.extend(DRCOM_DIAL_EXT_PROTO_CRC_INIT.as_bytes_le());
+        result.extend(126u32.as_bytes_le());
+        // padding?
+        result.resize(Self::packet_length(), 0u8);
+
+        let crc = self.crc_hasher_type().check_sum(&self.challenge_seed, &result);
+        result[Self::crc_offset()..Self::crc_offset() + 8].copy_from_slice(&crc);
+        result
+    }
+}
+
+impl DrCOMCommon for HeartbeatResponse {}
+
+impl DrCOMResponseCommon for HeartbeatResponse {}
+
+impl HeartbeatResponse {
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> HeartbeatResult<Self>
+    where
+        R: io::Read,
+    {
+        // validate packet and consume 1 byte
+        Self::validate_stream(input, |c| c == Self::code())
+            .map_err(HeartbeatError::ValidateError)?;
+
+        let sequence = input
+            .read_bytes(1)
+            .map_err(HeartbeatError::PacketReadError)?[0];
+        Ok(HeartbeatResponse { sequence })
+    }
+}
@@ -1,9 +1,15 @@
 #[cfg(test)]
 mod wired_tests {
-    use crate::drcom::wired::dialer::{ChallengeRequest, ChallengeResponse, LoginAccount, LoginResponse};
+    use crate::common::secret::SecretBytes;
+    use crate::drcom::wired::dialer::{
+        ChallengeRequest, ChallengeResponse, LoginAccount, LoginError, LoginFailure,
+        LoginResponse, LogoutResponse,
+    };
     use crate::drcom::wired::heartbeater::{
         HeartbeatFlag, PhaseOneRequest, PhaseOneResponse, PhaseTwoRequest, PhaseTwoResponse,
+        ServerMessage,
     };
+    use crate::drcom::wired::packet::{DrcomPacket, PacketError};
     use std::io::BufReader;
     use std::net::Ipv4Addr;
     use std::str::FromStr;
@@ -20,7 +26,35 @@ mod wired_tests {
             let fake_response: Vec<u8> = vec![2, 3, 4, 5, 6, 7, 8, 9, 10];
             let mut buffer = BufReader::new(&fake_response as &[u8]);
             let cr = ChallengeResponse::from_bytes(&mut buffer).unwrap();
+            assert_eq!(cr.sequence, 0x0504);
             assert_eq!(cr.hash_salt, [6u8, 7u8, 8u8, 9u8]);
+            assert_eq!(cr.client_ip, None);
+            assert_eq!(cr.encrypt_version, None);
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![
+                2, 2, 0x1b, 0x3f, 0x5e, 0xa4, 0x2c, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10,
+                30, 22, 17, 0x31, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            let cr = ChallengeResponse::from_bytes(&mut buffer).unwrap();
+            assert_eq!(cr.sequence, 0x3f1b);
+            assert_eq!(cr.hash_salt, [0x5e, 0xa4, 0x2c, 0x01]);
+            assert_eq!(cr.client_ip, Some(Ipv4Addr::new(10, 30, 22, 17)));
+            assert_eq!(cr.encrypt_version, Some(0x31));
+        }
+
+        {
+            // servers that do not echo the address still send the encrypt version
+            let fake_response: Vec<u8> = vec![
+                2, 2, 0x1b, 0x3f, 0x5e, 0xa4, 0x2c, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0x31, 0, 0, 0,
+            ];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            let cr = ChallengeResponse::from_bytes(&mut buffer).unwrap();
+            assert_eq!(cr.client_ip, None);
+            assert_eq!(cr.encrypt_version, Some(0x31));
         }
 
         {
@@ -93,11 +127,40 @@ mod wired_tests {
         {
             let fake_response: Vec<u8> = vec![
                 4, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
-                23, 24, 25, 26, 27, 28, 29, 30, 31,
+                23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38,
             ];
             let mut buffer = BufReader::new(&fake_response as &[u8]);
             let cr = LoginResponse::from_bytes(&mut buffer).unwrap();
-            assert_eq!(cr.keep_alive_key, [23, 24, 25, 26, 27, 28]);
+            assert_eq!(cr.used_time_minutes, 0x0807_0605);
+            assert_eq!(cr.used_traffic_kb, 0x0c0b_0a09);
+            assert_eq!(cr.balance, 0x100f_0e0d);
+            assert_eq!(*cr.keep_alive_key.expose(), [23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38]);
+        }
+
+        {
+            let success_response: Vec<u8> = vec![
+                4, 0, 0, 5, 0, 0x9c, 0x1d, 0, 0, 0x4b, 0x6c, 0x2e, 0, 0xe8, 0x03, 0, 0, 0, 0, 0x44,
+                0xff, 0xff, 0xff, 0x44, 0x72, 0x63, 0x6f, 0x7a, 0x13, 0xa9, 0x0e, 0xc4, 0x33, 0xa6,
+                0xe2, 0x04, 0x57, 0x3c, 0x8d, 0x7b, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ];
+            let mut buffer = BufReader::new(&success_response as &[u8]);
+            let cr = LoginResponse::from_bytes(&mut buffer).unwrap();
+            assert_eq!(cr.used_time_minutes, 7580);
+            assert_eq!(cr.used_traffic_kb, 3_042_379);
+            assert_eq!(cr.balance, 1000);
+            assert_eq!(
+                *cr.keep_alive_key.expose(),
+                [
+                    0x44, 0x72, 0x63, 0x6f, 0x7a, 0x13, 0xa9, 0x0e, 0xc4, 0x33, 0xa6, 0xe2, 0x04,
+                    0x57, 0x3c, 0x8d
+                ]
+            );
+        }
+
+        {
+            let truncated_response: Vec<u8> = vec![4, 0, 0, 5, 0, 0x9c, 0x1d, 0, 0, 0x4b, 0x6c];
+            let mut buffer = BufReader::new(&truncated_response as &[u8]);
+            assert!(LoginResponse::from_bytes(&mut buffer).is_err());
         }
 
         {
@@ -159,24 +222,146 @@ mod wired_tests {
         }
     }
 
+    #[test]
+    fn test_drcom_wired_login_failure() {
+        {
+            let fake_response: Vec<u8> = vec![
+                5, 0, 0, 5, 1, 10, 30, 22, 17, 0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80, 0, 0,
+            ];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            match LoginResponse::from_bytes(&mut buffer) {
+                Err(LoginError::LoginFailure(failure)) => {
+                    assert_eq!(
+                        failure,
+                        LoginFailure::AlreadyOnline {
+                            online_ip:  Ipv4Addr::new(10, 30, 22, 17),
+                            online_mac: [0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80],
+                        }
+                    );
+                    assert_eq!(failure.error_code(), 0x01);
+                    assert!(failure.is_retryable());
+                }
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![5, 0, 0, 5, 3, 0, 0, 0];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            match LoginResponse::from_bytes(&mut buffer) {
+                Err(LoginError::LoginFailure(failure)) => {
+                    assert_eq!(failure, LoginFailure::WrongPassword);
+                    assert!(!failure.is_retryable());
+                }
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![5, 0, 0, 5, 0x15];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert_eq!(
+                LoginFailure::from_bytes(&mut buffer).unwrap(),
+                LoginFailure::ClientVersionTooOld
+            );
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![5, 0, 0, 5, 0x42];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            let failure = LoginFailure::from_bytes(&mut buffer).unwrap();
+            assert_eq!(failure, LoginFailure::Unknown(0x42));
+            assert_eq!(failure.error_code(), 0x42);
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![4, 0, 0, 5, 3];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert!(LoginFailure::from_bytes(&mut buffer).is_err());
+        }
+    }
+
+    #[test]
+    fn test_drcom_wired_logout() {
+        let mut la = LoginAccount::new("usernameusername", "password", [1, 2, 3, 4]);
+        la.mac_address([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80])
+            .adapter_count(0x1)
+            .control_check_status(0x20);
+
+        {
+            let lr = la.logout_request(
+                [1, 2, 3, 4],
+                &SecretBytes::new([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]),
+            );
+            let origin_bytes = vec![
+                6, 1, 0, 36, 205, 150, 231, 111, 164, 64, 51, 55, 174, 166, 215, 161, 33, 174,
+                163, 175, 117, 115, 101, 114, 110, 97, 109, 101, 117, 115, 101, 114, 110, 97, 109,
+                101, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 1, 117, 30, 4,
+                106, 178, 192, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+            ];
+            assert_eq!(lr.unwrap().as_bytes().unwrap(), origin_bytes);
+        }
+
+        {
+            let mut la = LoginAccount::new("user", "pass", [1, 2, 3, 4]);
+            la.mac_address([0xfa, 0xe1, 0x23, 0x45, 0x67, 0x89])
+                .adapter_count(0x1)
+                .control_check_status(0x30);
+
+            let lr = la.logout_request(
+                [0x7, 0x8, 0x9, 0x10],
+                &SecretBytes::new([16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31]),
+            );
+            let origin_bytes = vec![
+                6, 1, 0, 24, 115, 136, 129, 145, 85, 88, 84, 157, 185, 239, 179, 150, 116, 213,
+                130, 238, 117, 115, 101, 114, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 48, 1, 137, 105, 162, 212, 50, 209, 16,
+                17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+            ];
+            assert_eq!(lr.unwrap().as_bytes().unwrap(), origin_bytes);
+        }
+
+        {
+            let la = LoginAccount::new("usernameusername1", "password", [1, 2, 3, 4]);
+            assert!(la.logout_request([1, 2, 3, 4], &SecretBytes::default()).is_err());
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![4, 0, 0, 0];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert!(LogoutResponse::from_bytes(&mut buffer).is_ok());
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![5, 0, 0, 0];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert!(LogoutResponse::from_bytes(&mut buffer).is_err());
+        }
+    }
+
     #[test]
     fn test_drcom_wired_heartbeat() {
         let flag_first = HeartbeatFlag::First;
         let flag_not_first = HeartbeatFlag::NotFirst;
 
-        let phase1 = PhaseOneRequest::new([1, 2, 3, 4], "password", [5, 6, 7, 8], Some(123456789));
+        let la = LoginAccount::new("usernameusername", "password", [1, 2, 3, 4]);
+        let phase1 = PhaseOneRequest::new(
+            &la.password_md5_hash(),
+            &SecretBytes::new([5, 6, 7, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Some(123456789),
+        );
         assert_eq!(
             phase1.as_bytes(),
             vec![
                 255, 174, 175, 144, 214, 168, 238, 67, 106, 128, 153, 49, 172, 94, 102, 177, 222,
-                0, 0, 0, 5, 6, 7, 8, 212, 112, 0, 0, 0, 0,
+                0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 212, 112, 0, 0, 0, 0,
             ]
         );
 
         {
             let phase2 = PhaseTwoRequest::new(
                 1,
-                [5, 6, 7, 8],
+                &SecretBytes::new([5, 6, 7, 8]),
                 &flag_first,
                 Ipv4Addr::from_str("1.2.3.4").unwrap(),
                 Some(1),
@@ -193,7 +378,7 @@ mod wired_tests {
         {
             let phase2 = PhaseTwoRequest::new(
                 1,
-                [5, 6, 7, 8],
+                &SecretBytes::new([5, 6, 7, 8]),
                 &flag_first,
                 Ipv4Addr::from_str("1.2.3.4").unwrap(),
                 Some(3),
@@ -210,7 +395,7 @@ mod wired_tests {
         {
             let phase2 = PhaseTwoRequest::new(
                 1,
-                [5, 6, 7, 8],
+                &SecretBytes::new([5, 6, 7, 8]),
                 &flag_not_first,
                 Ipv4Addr::from_str("1.2.3.4").unwrap(),
                 Some(3),
@@ -233,7 +418,7 @@ mod wired_tests {
             let mut buffer = BufReader::new(&fake_response as &[u8]);
             let response = PhaseTwoResponse::from_bytes(&mut buffer).unwrap();
             assert_eq!(response.sequence, 1);
-            assert_eq!(response.keep_alive_key, [16, 17, 18, 19]);
+            assert_eq!(*response.keep_alive_key.expose(), [16, 17, 18, 19]);
         }
 
         {
@@ -248,4 +433,1390 @@ mod wired_tests {
             assert!(PhaseOneResponse::from_bytes(&mut buffer).is_err());
         }
     }
+
+    #[test]
+    fn test_drcom_wired_packet_decode() {
+        let mut challenge = vec![2, 2, 0x1b, 0x3f, 1, 2, 3, 4];
+        challenge.extend_from_slice(&[0u8; 12]);
+        challenge.extend_from_slice(&[10, 30, 22, 17]);
+        assert!(matches!(
+            DrcomPacket::decode(&challenge),
+            Ok(DrcomPacket::ChallengeResponse(ChallengeResponse {
+                hash_salt: [1, 2, 3, 4],
+                ..
+            }))
+        ));
+
+        let mut login = vec![4, 0, 0, 5, 0, 0x2c, 1, 0, 0];
+        login.extend_from_slice(&[0u8; 30]);
+        assert!(matches!(
+            DrcomPacket::decode(&login),
+            Ok(DrcomPacket::LoginResponse(LoginResponse {
+                used_time_minutes: 300,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            DrcomPacket::decode(&[4, 0, 0, 0]),
+            Ok(DrcomPacket::LogoutResponse(_))
+        ));
+        assert!(matches!(
+            DrcomPacket::decode(&[5, 0, 0, 5, 3, 0, 0, 0]),
+            Ok(DrcomPacket::LoginFailure(LoginFailure::WrongPassword))
+        ));
+
+        assert!(matches!(
+            DrcomPacket::decode(&[7, 0, 0, 0]),
+            Ok(DrcomPacket::PhaseOneResponse(_))
+        ));
+        let mut keep_alive2 = vec![7, 5, 0x28, 0, 0x0b, 1];
+        keep_alive2.extend_from_slice(&[0u8; 34]);
+        assert!(matches!(
+            DrcomPacket::decode(&keep_alive2),
+            Ok(DrcomPacket::PhaseTwoResponse(PhaseTwoResponse { sequence: 5, .. }))
+        ));
+        assert!(matches!(
+            DrcomPacket::decode(&[7, 6, 0x10, 0, 0x06, 0, 0, 0]),
+            Ok(DrcomPacket::KeepAliveFile { sequence: 6 })
+        ));
+        assert!(matches!(
+            DrcomPacket::decode(&[7, 5, 0x28, 0, 0x0b]),
+            Err(PacketError::Heartbeat(_))
+        ));
+
+        assert!(matches!(
+            DrcomPacket::decode(&[0x4d, 0x38, b'h', b'i']),
+            Ok(DrcomPacket::ServerMessage(ServerMessage { kind: 0x38, .. }))
+        ));
+        assert!(matches!(DrcomPacket::decode(&[0x4e, 0]), Ok(DrcomPacket::Unknown(0x4e))));
+        assert!(matches!(DrcomPacket::decode(&[]), Err(PacketError::Empty)));
+    }
+}
+
+#[cfg(test)]
+mod pppoe_tests {
+    use crate::drcom::pppoe::heartbeater::{
+        CRCHasherType, ChallengeRequest, ChallengeResponse, HeartbeatFlag, HeartbeatRequest,
+        HeartbeatResponse,
+    };
+    use std::io::BufReader;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_drcom_pppoe_challenge() {
+        let c = ChallengeRequest::new(1);
+        assert_eq!(c.as_bytes(), vec![7, 1, 8, 0, 1, 0, 0, 0]);
+
+        {
+            let fake_response: Vec<u8> = vec![
+                7, 1, 16, 0, 2, 0, 0, 0, 5, 0, 0, 0, 10, 30, 22, 17, 0, 0, 0, 0,
+            ];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            let cr = ChallengeResponse::from_bytes(&mut buffer).unwrap();
+            assert_eq!(cr.sequence, 1);
+            assert_eq!(cr.challenge_seed, [5, 0, 0, 0]);
+            assert_eq!(cr.source_ip, Ipv4Addr::new(10, 30, 22, 17));
+            assert_eq!(cr.crc_hasher_type(), CRCHasherType::MD5);
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![2, 1, 16, 0, 2, 0, 0, 0, 5, 0, 0, 0, 10, 30, 22, 17];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert!(ChallengeResponse::from_bytes(&mut buffer).is_err());
+        }
+    }
+
+    #[test]
+    fn test_drcom_pppoe_crc_hasher_type() {
+        assert_eq!(CRCHasherType::from_seed(&[0, 0, 0, 0]), CRCHasherType::NoEncrypt);
+        assert_eq!(CRCHasherType::from_seed(&[1, 0, 0, 0]), CRCHasherType::MD5);
+        assert_eq!(CRCHasherType::from_seed(&[2, 0, 0, 0]), CRCHasherType::MD4);
+        assert_eq!(CRCHasherType::from_seed(&[3, 0, 0, 0]), CRCHasherType::SHA1);
+        assert_eq!(CRCHasherType::from_seed(&[0x1b, 0x3f, 0x5e, 0xa4]), CRCHasherType::SHA1);
+    }
+
+    #[test]
+    fn test_drcom_pppoe_heartbeat() {
+        let flag_first = HeartbeatFlag::First;
+        let flag_not_first = HeartbeatFlag::NotFirst;
+        let mac_address = [0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80];
+        let source_ip = Ipv4Addr::new(10, 30, 22, 17);
+
+        let cases: Vec<(&HeartbeatFlag, [u8; 4], Vec<u8>)> = vec![
+            (
+                &flag_first,
+                [4, 0, 0, 0],
+                vec![
+                    7, 1, 96, 0, 3, 0, 184, 136, 227, 5, 22, 128, 10, 30, 22, 17, 0, 98, 0, 42, 4,
+                    0, 0, 0, 96, 105, 45, 189, 0, 0, 0, 0,
+                ],
+            ),
+            (
+                &flag_not_first,
+                [5, 0, 0, 0],
+                vec![
+                    7, 1, 96, 0, 3, 0, 184, 136, 227, 5, 22, 128, 10, 30, 22, 17, 0, 99, 0, 42, 5,
+                    0, 0, 0, 61, 150, 17, 172, 252, 205, 200, 27,
+                ],
+            ),
+            (
+                &flag_not_first,
+                [6, 0, 0, 0],
+                vec![
+                    7, 1, 96, 0, 3, 0, 184, 136, 227, 5, 22, 128, 10, 30, 22, 17, 0, 99, 0, 42, 6,
+                    0, 0, 0, 94, 190, 93, 72, 239, 69, 222, 43,
+                ],
+            ),
+            (
+                &flag_first,
+                [7, 0, 0, 0],
+                vec![
+                    7, 1, 96, 0, 3, 0, 184, 136, 227, 5, 22, 128, 10, 30, 22, 17, 0, 98, 0, 42, 7,
+                    0, 0, 0, 111, 199, 56, 198, 160, 218, 116, 161,
+                ],
+            ),
+        ];
+
+        for (flag, challenge_seed, mut origin_bytes) in cases {
+            let hr = HeartbeatRequest::new(1, mac_address, source_ip, flag, challenge_seed);
+            origin_bytes.resize(96, 0u8);
+            assert_eq!(hr.as_bytes(), origin_bytes);
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![7, 2, 96, 0, 4, 0, 0, 0];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert_eq!(HeartbeatResponse::from_bytes(&mut buffer).unwrap().sequence, 2);
+        }
+
+        {
+            let fake_response: Vec<u8> = vec![4, 2, 96, 0];
+            let mut buffer = BufReader::new(&fake_response as &[u8]);
+            assert!(HeartbeatResponse::from_bytes(&mut buffer).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod eapol_tests {
+    use crate::drcom::eapol::frame::{
+        DrCOMTrailer, EAPCode, EAPOLFrame, EAPOLType, EAPPacket, EAPType, PAE_GROUP_ADDRESS,
+    };
+    use crate::drcom::eapol::supplicant::{
+        FrameTransport, Supplicant, SupplicantError, SupplicantState,
+    };
+    use std::collections::VecDeque;
+    use std::io;
+    use std::io::BufReader;
+    use std::net::Ipv4Addr;
+
+    const CLIENT_MAC: [u8; 6] = [0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80];
+    const SERVER_MAC: [u8; 6] = [0x00, 0x1a, 0xa9, 0x17, 0x23, 0x45];
+
+    struct MemoryTransport {
+        incoming: VecDeque<Vec<u8>>,
+        sent:     Vec<Vec<u8>>,
+    }
+
+    impl FrameTransport for MemoryTransport {
+        fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.sent.push(frame.to_vec());
+            Ok(())
+        }
+
+        fn recv_frame(&mut self) -> io::Result<Vec<u8>> {
+            self.incoming
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))
+        }
+    }
+
+    fn request(code: EAPCode, identifier: u8, eap_type: Option<EAPType>, data: &[u8]) -> Vec<u8> {
+        EAPOLFrame::eap(
+            CLIENT_MAC,
+            SERVER_MAC,
+            EAPPacket {
+                code,
+                identifier,
+                eap_type,
+                data: data.to_vec(),
+            },
+        )
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_drcom_eapol_frame() {
+        assert_eq!(
+            EAPOLFrame::start(CLIENT_MAC).as_bytes(),
+            vec![
+                0x01, 0x80, 0xc2, 0x00, 0x00, 0x03, 0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80, 0x88, 0x8e,
+                1, 1, 0, 0,
+            ]
+        );
+
+        let trailer = DrCOMTrailer {
+            eap_type: EAPType::Identity,
+            host_ip:  Ipv4Addr::new(10, 30, 22, 17),
+        };
+        assert_eq!(
+            EAPOLFrame::eap(
+                SERVER_MAC,
+                CLIENT_MAC,
+                EAPPacket::identity_response(7, "user", &trailer)
+            )
+            .as_bytes(),
+            vec![
+                0x00, 0x1a, 0xa9, 0x17, 0x23, 0x45, 0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80, 0x88, 0x8e,
+                1, 0, 0, 18, 2, 7, 0, 18, 1, 117, 115, 101, 114, 0x00, 0x44, 0x61, 0x00, 0x00, 10,
+                30, 22, 17,
+            ]
+        );
+
+        let trailer = DrCOMTrailer {
+            eap_type: EAPType::MD5Challenge,
+            host_ip:  Ipv4Addr::new(10, 30, 22, 17),
+        };
+        let challenge: Vec<u8> = (1..17).collect();
+        assert_eq!(
+            EAPPacket::md5_challenge_response(2, "user", "password", &challenge, &trailer)
+                .as_bytes(),
+            vec![
+                2, 2, 0, 35, 4, 16, 210, 5, 4, 136, 255, 105, 252, 95, 83, 66, 39, 182, 26, 200,
+                177, 192, 117, 115, 101, 114, 0x00, 0x44, 0x61, 0x2a, 0x00, 10, 30, 22, 17,
+            ]
+        );
+
+        {
+            let mut fake_frame = request(EAPCode::Request, 2, Some(EAPType::MD5Challenge), &[
+                16, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+            ]);
+            // ethernet padding must be ignored
+            fake_frame.extend_from_slice(&[0u8; 8]);
+            let mut buffer = BufReader::new(&fake_frame as &[u8]);
+            let frame = EAPOLFrame::from_bytes(&mut buffer).unwrap();
+            assert_eq!(frame.source, SERVER_MAC);
+            assert_eq!(frame.eapol_type, EAPOLType::EAPPacket);
+            let eap_packet = frame.eap_packet.unwrap();
+            assert_eq!(eap_packet.code, EAPCode::Request);
+            assert_eq!(eap_packet.identifier, 2);
+            assert_eq!(eap_packet.md5_challenge(), Some(&challenge[..]));
+        }
+
+        {
+            let fake_frame = request(EAPCode::Success, 3, None, &[]);
+            let mut buffer = BufReader::new(&fake_frame as &[u8]);
+            let frame = EAPOLFrame::from_bytes(&mut buffer).unwrap();
+            assert_eq!(
+                frame.eap_packet.unwrap(),
+                EAPPacket {
+                    code:       EAPCode::Success,
+                    identifier: 3,
+                    eap_type:   None,
+                    data:       vec![],
+                }
+            );
+        }
+
+        {
+            let mut fake_frame = request(EAPCode::Success, 3, None, &[]);
+            fake_frame[12..14].copy_from_slice(&[0x08, 0x00]);
+            let mut buffer = BufReader::new(&fake_frame as &[u8]);
+            assert!(EAPOLFrame::from_bytes(&mut buffer).is_err());
+        }
+    }
+
+    #[test]
+    fn test_drcom_eapol_supplicant() {
+        let challenge: Vec<u8> = (1..17).collect();
+        let mut md5_request_data = vec![16u8];
+        md5_request_data.extend(&challenge);
+
+        let transport = MemoryTransport {
+            incoming: vec![
+                request(EAPCode::Request, 1, Some(EAPType::Identity), &[]),
+                request(EAPCode::Request, 2, Some(EAPType::MD5Challenge), &md5_request_data),
+                request(EAPCode::Success, 2, None, &[]),
+            ]
+            .into(),
+            sent:     vec![],
+        };
+        let host_ip = Ipv4Addr::new(10, 30, 22, 17);
+        let mut supplicant = Supplicant::new(transport, "user", "password", CLIENT_MAC, host_ip);
+        assert_eq!(supplicant.state(), SupplicantState::Disconnected);
+
+        supplicant.authenticate().unwrap();
+        assert_eq!(supplicant.state(), SupplicantState::Authenticated);
+
+        let sent = &supplicant.transport().sent;
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], EAPOLFrame::start(CLIENT_MAC).as_bytes());
+        assert_eq!(
+            sent[1],
+            EAPOLFrame::eap(
+                SERVER_MAC,
+                CLIENT_MAC,
+                EAPPacket::identity_response(1, "user", &DrCOMTrailer {
+                    eap_type: EAPType::Identity,
+                    host_ip,
+                })
+            )
+            .as_bytes()
+        );
+        assert_eq!(
+            sent[2],
+            EAPOLFrame::eap(
+                SERVER_MAC,
+                CLIENT_MAC,
+                EAPPacket::md5_challenge_response(2, "user", "password", &challenge, &DrCOMTrailer {
+                    eap_type: EAPType::MD5Challenge,
+                    host_ip,
+                })
+            )
+            .as_bytes()
+        );
+
+        supplicant.logoff().unwrap();
+        assert_eq!(supplicant.state(), SupplicantState::Disconnected);
+        assert_eq!(
+            supplicant.transport().sent[3],
+            EAPOLFrame::logoff(SERVER_MAC, CLIENT_MAC).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_drcom_eapol_supplicant_failure() {
+        let mut not_for_us = request(EAPCode::Success, 1, None, &[]);
+        not_for_us[0..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+
+        let transport = MemoryTransport {
+            incoming: vec![
+                not_for_us,
+                request(EAPCode::Request, 1, Some(EAPType::Identity), &[]),
+                request(EAPCode::Failure, 1, None, &[]),
+            ]
+            .into(),
+            sent:     vec![],
+        };
+        let mut supplicant = Supplicant::new(
+            transport,
+            "user",
+            "password",
+            CLIENT_MAC,
+            Ipv4Addr::new(10, 30, 22, 17),
+        );
+        match supplicant.authenticate() {
+            Err(SupplicantError::AuthenticationFailed) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(supplicant.state(), SupplicantState::Failed);
+        assert_eq!(supplicant.transport().sent[0][0..6], PAE_GROUP_ADDRESS);
+    }
+
+    #[test]
+    fn test_drcom_eapol_supplicant_gives_up() {
+        let supplicant = |incoming: Vec<Vec<u8>>| {
+            let transport = MemoryTransport {
+                incoming: incoming.into(),
+                sent:     vec![],
+            };
+            let host_ip = Ipv4Addr::new(10, 30, 22, 17);
+            Supplicant::new(transport, "user", "password", CLIENT_MAC, host_ip)
+        };
+
+        // a challenge value longer than the request holds
+        let mut truncated = supplicant(vec![
+            request(EAPCode::Request, 1, Some(EAPType::Identity), &[]),
+            request(EAPCode::Request, 2, Some(EAPType::MD5Challenge), &[16, 1, 2, 3]),
+        ]);
+        match truncated.authenticate() {
+            Err(SupplicantError::MalformedChallenge) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(truncated.transport().sent.len(), 2);
+
+        // an authenticator that keeps asking for the identity
+        let identities = (0..64)
+            .map(|i| request(EAPCode::Request, i, Some(EAPType::Identity), &[]))
+            .collect();
+        match supplicant(identities).authenticate() {
+            Err(SupplicantError::TooManyFrames) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod eportal_tests {
+    use crate::drcom::eportal::dialer::{
+        PortalAccount, PortalDialer, PortalError, PortalLoginResponse, PortalStatus,
+    };
+    use crate::common::http::tests::stand_in_server;
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Serve `count` connections, answering each path prefix with its canned reply.
+    fn stand_in_portal(
+        count: usize,
+        routes: Vec<(&'static str, &'static str, &'static str)>,
+    ) -> (String, mpsc::Receiver<String>) {
+        stand_in_server(count, move |request_line| {
+            let (status, body) = routes
+                .iter()
+                .find(|(prefix, _, _)| request_line.starts_with(&format!("GET {}", prefix)))
+                .map(|(_, status, body)| (*status, *body))
+                .unwrap_or(("404 Not Found", ""));
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/javascript\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+        })
+    }
+
+    #[test]
+    fn test_drcom_eportal_parse() {
+        let lr = PortalLoginResponse::from_bytes(
+            br#"dr1003({"result":1,"aolno":6421,"m46":0,"v46ip":"10.30.22.17","myv6ip":"","sms":0,"NID":"user"});"#,
+        )
+        .unwrap();
+        assert_eq!(
+            lr,
+            PortalLoginResponse {
+                success:   true,
+                message:   None,
+                client_ip: Some(Ipv4Addr::new(10, 30, 22, 17)),
+            }
+        );
+
+        let lr = PortalLoginResponse::from_bytes(
+            br#"dr1003({"result":"0","msg":"dXNlcmlkIGVycm9yMQ==","msga":"userid error1"})"#,
+        )
+        .unwrap();
+        assert!(!lr.success);
+        assert_eq!(lr.message, Some(String::from("userid error1")));
+
+        let status = PortalStatus::from_bytes(
+            br#"{"result":1,"time":7580,"flow":"3042379","fee":1000,"uid":"user","v4ip":"10.30.22.17"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            status,
+            PortalStatus {
+                online:            true,
+                username:          Some(String::from("user")),
+                client_ip:         Some(Ipv4Addr::new(10, 30, 22, 17)),
+                used_time_minutes: Some(7580),
+                used_traffic_kb:   Some(3_042_379),
+                balance:           Some(1000),
+            }
+        );
+
+        assert!(PortalStatus::from_bytes(b"dr1002(<html>)").is_err());
+        assert!(PortalStatus::from_bytes(br#"dr1002({"time":1})"#).is_err());
+    }
+
+    #[test]
+    fn test_drcom_eportal_dialer() {
+        let (addr, requests) = stand_in_portal(3, vec![
+            (
+                "/drcom/login",
+                "200 OK",
+                r#"dr1003({"result":1,"v46ip":"10.30.22.17"})"#,
+            ),
+            (
+                "/drcom/chkstatus",
+                "200 OK",
+                r#"dr1002({"result":1,"time":12,"flow":345,"fee":6789,"uid":"user","v4ip":"10.30.22.17"})"#,
+            ),
+            ("/drcom/logout", "200 OK", r#"dr1004({"result":1,"msga":"logout ok"})"#),
+        ]);
+        let dialer = PortalDialer::new(&addr).unwrap();
+
+        let mut account = PortalAccount::new("user name", "p&ss");
+        account.mkkey(String::from("654321"));
+        let lr = dialer.login(&account).unwrap();
+        assert!(lr.success);
+        assert_eq!(lr.client_ip, Some(Ipv4Addr::new(10, 30, 22, 17)));
+        let request_line = requests.recv().unwrap();
+        assert!(request_line.starts_with(
+            "GET /drcom/login?callback=dr1003&DDDDD=user%20name&upass=p%26ss&0MKKey=654321&"
+        ));
+
+        let status = dialer.check_status().unwrap();
+        assert!(status.online);
+        assert_eq!(status.used_traffic_kb, Some(345));
+        assert_eq!(
+            requests.recv().unwrap(),
+            "GET /drcom/chkstatus?callback=dr1002 HTTP/1.0"
+        );
+
+        let lr = dialer.logout().unwrap();
+        assert!(lr.success);
+        assert_eq!(lr.message, Some(String::from("logout ok")));
+        assert_eq!(
+            requests.recv().unwrap(),
+            "GET /drcom/logout?callback=dr1004 HTTP/1.0"
+        );
+
+        let (addr, _requests) = stand_in_portal(1, vec![(
+            "/drcom/chkstatus",
+            "502 Bad Gateway",
+            "",
+        )]);
+        let mut dialer = PortalDialer::new(&addr).unwrap();
+        dialer.timeout(Duration::from_secs(1));
+        match dialer.check_status() {
+            Err(PortalError::HttpStatus(502)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod wired_session_tests {
+    use crate::common::secret::SecretBytes;
+    use crate::drcom::wired::dialer::{LoginAccount, LoginFailure};
+    use crate::drcom::wired::heartbeater::PhaseOneRequest;
+    use crate::drcom::wired::retransmit::{RetransmitPolicy, Step, StepPolicy};
+    use crate::drcom::wired::session::{DropReason, SessionEvent, SessionState, WiredSession};
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    const SALT: [u8; 4] = [0x5e, 0xa4, 0x2c, 0x01];
+    const AUTH_TAIL: [u8; 16] = [
+        0x44, 0x72, 0x63, 0x6f, 0x7a, 0x13, 0xa9, 0x0e, 0xc4, 0x33, 0xa6, 0xe2, 0x04, 0x57, 0x3c,
+        0x8d,
+    ];
+
+    fn account() -> LoginAccount {
+        let mut la = LoginAccount::new("usernameusername", "password", [0u8; 4]);
+        la.mac_address([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80]);
+        la
+    }
+
+    fn challenge_response() -> Vec<u8> {
+        let mut response = vec![2, 2, 0x1b, 0x3f];
+        response.extend_from_slice(&SALT);
+        response.extend_from_slice(&[0u8; 12]);
+        response.extend_from_slice(&[10, 30, 22, 17]);
+        response.extend_from_slice(&[0x31, 0, 0, 0]);
+        response
+    }
+
+    fn login_response() -> Vec<u8> {
+        let mut response = vec![4, 0, 0, 5, 0];
+        response.extend_from_slice(&[0u8; 18]);
+        response.extend_from_slice(&AUTH_TAIL);
+        response
+    }
+
+    fn keep_alive2_response(sequence: u8, key: [u8; 4]) -> Vec<u8> {
+        let mut response = vec![7, sequence, 0x28, 0, 0x0b, 1];
+        response.extend_from_slice(&[0u8; 10]);
+        response.extend_from_slice(&key);
+        response.extend_from_slice(&[0u8; 20]);
+        response
+    }
+
+    /// Pending events except round trips, which `test_drcom_wired_session_round_trips` covers.
+    fn drain_events(session: &mut WiredSession) -> Vec<SessionEvent> {
+        let mut events = vec![];
+        while let Some(event) = session.poll_event() {
+            if !matches!(event, SessionEvent::RoundTrip { .. }) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn expect_keep_alive1(session: &mut WiredSession) {
+        let packet = session.poll_transmit().unwrap();
+        let mut la = account();
+        la.hash_salt(SALT);
+        let expected = PhaseOneRequest::new(
+            &la.password_md5_hash(),
+            &SecretBytes::new(AUTH_TAIL),
+            Some(0),
+        )
+        .as_bytes();
+        assert_eq!(packet[..36], expected[..36]);
+        assert!(session.poll_transmit().is_none());
+    }
+
+    fn expect_keep_alive2(session: &mut WiredSession, sequence: u8, type_id: u8, key: [u8; 4]) {
+        let packet = session.poll_transmit().unwrap();
+        assert_eq!(packet.len(), 40);
+        assert_eq!(packet[0], 7);
+        assert_eq!(packet[1], sequence);
+        assert_eq!(packet[5], type_id);
+        assert_eq!(packet[16..20], key);
+        if type_id == 3 {
+            assert_eq!(packet[28..32], [10, 30, 22, 17]);
+        }
+        assert!(session.poll_transmit().is_none());
+    }
+
+    #[test]
+    fn test_drcom_wired_session_handshake() {
+        let now = Instant::now();
+        let mut session = WiredSession::new(account());
+        session.heartbeat_interval(Duration::from_secs(20));
+        assert_eq!(session.state(), SessionState::Idle);
+
+        session.start(now);
+        assert_eq!(session.state(), SessionState::Challenging);
+        assert_eq!(session.poll_transmit().unwrap()[0], 1);
+
+        session.handle_datagram(now, &challenge_response());
+        assert_eq!(session.state(), SessionState::LoggingIn);
+        assert_eq!(session.host_ip(), Ipv4Addr::new(10, 30, 22, 17));
+        {
+            let mut expected = account();
+            expected
+                .hash_salt(SALT)
+                .ipaddresses(&[Ipv4Addr::new(10, 30, 22, 17)]);
+            assert_eq!(
+                session.poll_transmit().unwrap(),
+                expected.login_request().unwrap().as_bytes().unwrap()
+            );
+        }
+
+        session.handle_datagram(now, &login_response());
+        assert_eq!(session.state(), SessionState::KeepAliveOne);
+        expect_keep_alive1(&mut session);
+
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        assert_eq!(session.state(), SessionState::KeepAliveTwoFirst);
+        expect_keep_alive2(&mut session, 0, 1, [0u8; 4]);
+
+        session.handle_datagram(now, &keep_alive2_response(0, [0u8; 4]));
+        assert_eq!(session.state(), SessionState::KeepAliveTwoSecond);
+        expect_keep_alive2(&mut session, 0, 1, [0u8; 4]);
+
+        session.handle_datagram(now, &keep_alive2_response(0, [1, 2, 3, 4]));
+        assert_eq!(session.state(), SessionState::KeepAliveTwoThird);
+        expect_keep_alive2(&mut session, 1, 3, [1, 2, 3, 4]);
+
+        session.handle_datagram(now, &keep_alive2_response(1, [5, 6, 7, 8]));
+        assert_eq!(session.state(), SessionState::Online);
+        assert_eq!(session.sequence(), 2);
+        assert!(session.poll_transmit().is_none());
+        assert_eq!(session.poll_timeout(), Some(now + Duration::from_secs(20)));
+
+        let events = drain_events(&mut session);
+        assert!(matches!(events[0], SessionEvent::Challenged { .. }));
+        assert!(matches!(events[1], SessionEvent::LoggedIn(_)));
+        assert!(matches!(events[2], SessionEvent::Online { sequence: 2 }));
+        assert_eq!(events.len(), 3);
+
+        // nothing happens before the heartbeat is due
+        session.handle_tick(now + Duration::from_secs(19));
+        assert!(session.poll_transmit().is_none());
+
+        let now = now + Duration::from_secs(20);
+        session.handle_tick(now);
+        assert_eq!(session.state(), SessionState::HeartbeatOne);
+        expect_keep_alive1(&mut session);
+
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        assert_eq!(session.state(), SessionState::HeartbeatTwoFirst);
+        expect_keep_alive2(&mut session, 2, 1, [5, 6, 7, 8]);
+
+        session.handle_datagram(now, &keep_alive2_response(2, [9, 10, 11, 12]));
+        assert_eq!(session.state(), SessionState::HeartbeatTwoSecond);
+        expect_keep_alive2(&mut session, 3, 3, [9, 10, 11, 12]);
+
+        session.handle_datagram(
+            now + Duration::from_millis(30),
+            &keep_alive2_response(3, [13, 14, 15, 16]),
+        );
+        assert_eq!(session.state(), SessionState::Online);
+        assert_eq!(session.sequence(), 4);
+        assert_eq!(session.heartbeat_rtt(), Some(Duration::from_millis(30)));
+        assert!(matches!(
+            drain_events(&mut session)[..],
+            [SessionEvent::HeartbeatCompleted { sequence: 4 }]
+        ));
+    }
+
+    #[test]
+    fn test_drcom_wired_session_keep_alive_file() {
+        let now = Instant::now();
+        let mut session = WiredSession::new(account());
+        session.start(now);
+        session.handle_datagram(now, &challenge_response());
+        session.handle_datagram(now, &login_response());
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        while session.poll_transmit().is_some() {}
+        assert_eq!(session.state(), SessionState::KeepAliveTwoFirst);
+
+        // the file answers the first keep_alive2, the second one follows right away
+        let mut transmits = 0;
+        session.handle_datagram(now, &[7, 0, 0x10, 0, 0, 0, 0, 0]);
+        assert_eq!(session.state(), SessionState::KeepAliveTwoSecond);
+        while let Some(packet) = session.poll_transmit() {
+            assert_eq!(packet[1], 1);
+            assert_eq!(packet[5], 1);
+            transmits += 1;
+        }
+        session.handle_datagram(now, &keep_alive2_response(1, [1, 2, 3, 4]));
+        assert_eq!(session.state(), SessionState::KeepAliveTwoThird);
+        while let Some(packet) = session.poll_transmit() {
+            assert_eq!(packet[1], 2);
+            assert_eq!(packet[5], 3);
+            transmits += 1;
+        }
+        session.handle_datagram(now, &keep_alive2_response(2, [5, 6, 7, 8]));
+        assert_eq!(session.state(), SessionState::Online);
+        assert_eq!(session.sequence(), 3);
+        assert!(session.poll_transmit().is_none());
+        assert_eq!(transmits, 2);
+    }
+
+    #[test]
+    fn test_drcom_wired_session_follows_echoed_ip() {
+        let login_request = |ipaddresses: &[Ipv4Addr]| {
+            let mut expected = account();
+            expected.hash_salt(SALT).ipaddresses(ipaddresses);
+            expected.login_request().unwrap().as_bytes().unwrap()
+        };
+        let challenge_from = |ip: [u8; 4]| {
+            let mut response = challenge_response();
+            response[20..24].copy_from_slice(&ip);
+            response
+        };
+        let now = Instant::now();
+
+        // each relogin takes the address the server sees now
+        let mut session = WiredSession::new(account());
+        for ip in [[10, 30, 22, 17], [10, 30, 23, 5]] {
+            session.start(now);
+            session.poll_transmit();
+            session.handle_datagram(now, &challenge_from(ip));
+            assert_eq!(session.poll_transmit().unwrap(), login_request(&[ip.into()]));
+        }
+
+        // configured addresses are left alone
+        let configured = [Ipv4Addr::new(192, 168, 1, 2)];
+        let mut la = account();
+        la.ipaddresses(&configured);
+        let mut session = WiredSession::new(la);
+        for ip in [[10, 30, 22, 17], [10, 30, 23, 5]] {
+            session.start(now);
+            session.poll_transmit();
+            session.handle_datagram(now, &challenge_from(ip));
+            assert_eq!(session.poll_transmit().unwrap(), login_request(&configured));
+        }
+    }
+
+    #[test]
+    fn test_drcom_wired_session_sequence_wraps() {
+        let mut now = Instant::now();
+        let mut session = WiredSession::new(account());
+        session.start(now);
+        session.handle_datagram(now, &challenge_response());
+        session.handle_datagram(now, &login_response());
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        session.handle_datagram(now, &keep_alive2_response(0, [0u8; 4]));
+        session.handle_datagram(now, &keep_alive2_response(0, [0u8; 4]));
+        session.handle_datagram(now, &keep_alive2_response(1, [0u8; 4]));
+        assert_eq!(session.sequence(), 2);
+
+        for _ in 0..62 {
+            now += Duration::from_secs(20);
+            session.handle_tick(now);
+            session.handle_datagram(now, &[7, 0, 0, 0]);
+            session.handle_datagram(now, &keep_alive2_response(session.sequence(), [0u8; 4]));
+            session.handle_datagram(now, &keep_alive2_response(session.sequence(), [0u8; 4]));
+        }
+        assert_eq!(session.sequence(), 0x7e);
+
+        now += Duration::from_secs(20);
+        session.handle_tick(now);
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        session.handle_datagram(now, &keep_alive2_response(0x7e, [0u8; 4]));
+        while session.poll_transmit().is_some() {}
+        session.handle_datagram(now, &keep_alive2_response(0x7f, [0u8; 4]));
+        assert_eq!(session.state(), SessionState::Online);
+        assert_eq!(session.sequence(), 1);
+    }
+
+    #[test]
+    fn test_drcom_wired_session_drops() {
+        let now = Instant::now();
+        let mut session = online_session(now);
+        let now = now + Duration::from_secs(20);
+        session.handle_tick(now);
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        assert_eq!(session.state(), SessionState::HeartbeatTwoFirst);
+        drain_events(&mut session);
+
+        session.handle_datagram(now, &keep_alive2_response(9, [1, 2, 3, 4]));
+        session.handle_datagram(now, &[0x4d, 0x38, b'h', b'i']);
+        session.handle_datagram(now, &[4, 0, 0, 0]);
+        session.handle_datagram(now, &[7, 2, 0x28, 0, 0x0b]);
+        let reasons: Vec<_> = drain_events(&mut session)
+            .into_iter()
+            .map(|event| match event {
+                SessionEvent::PacketDropped {
+                    state: SessionState::HeartbeatTwoFirst,
+                    reason,
+                } => reason,
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert!(matches!(
+            reasons[..],
+            [
+                DropReason::UnexpectedSequence {
+                    expected: 2,
+                    received: 9
+                },
+                DropReason::ServerMessage(0x38),
+                DropReason::UnexpectedPacket("logout response"),
+                DropReason::Malformed(_)
+            ]
+        ));
+
+        session.handle_datagram(now, &keep_alive2_response(2, [0u8; 4]));
+        assert_eq!(session.state(), SessionState::HeartbeatTwoSecond);
+    }
+
+    #[test]
+    fn test_drcom_wired_session_failures() {
+        let now = Instant::now();
+
+        {
+            let mut session = WiredSession::new(account());
+            session.start(now);
+            session.handle_datagram(now, &[4, 0, 0, 0]);
+            assert_eq!(session.state(), SessionState::Challenging);
+            assert!(matches!(
+                drain_events(&mut session)[..],
+                [SessionEvent::PacketDropped {
+                    state: SessionState::Challenging,
+                    ..
+                }]
+            ));
+
+            session.handle_datagram(now, &challenge_response());
+            session.handle_datagram(now, &[5, 0, 0, 5, 3, 0, 0, 0]);
+            assert_eq!(session.state(), SessionState::Failed);
+            assert!(matches!(
+                drain_events(&mut session)[..],
+                [
+                    SessionEvent::Challenged { .. },
+                    SessionEvent::LoginFailed(LoginFailure::WrongPassword)
+                ]
+            ));
+        }
+
+        {
+            let mut session = WiredSession::new(account());
+            session.start(now);
+            session.handle_datagram(now, &[2, 2, 0x1b, 0x3f, 1, 2, 3, 4]);
+            assert_eq!(session.state(), SessionState::Failed);
+            assert!(matches!(
+                drain_events(&mut session)[..],
+                [SessionEvent::HostIPUnknown]
+            ));
+        }
+
+        {
+            let mut session = WiredSession::new(account());
+            session.host_ip_override(Some(Ipv4Addr::new(1, 2, 3, 4)));
+            session.start(now);
+            session.handle_datagram(now, &challenge_response());
+            assert_eq!(session.host_ip(), Ipv4Addr::new(1, 2, 3, 4));
+            assert_eq!(session.state(), SessionState::LoggingIn);
+        }
+    }
+
+    #[test]
+    fn test_drcom_wired_session_timeouts() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(5);
+
+        {
+            let mut session = WiredSession::new(account());
+            session.retransmit_policy(RetransmitPolicy::uniform(timeout, 0));
+            session.start(now);
+            assert_eq!(session.poll_timeout(), Some(now + timeout));
+
+            session.handle_tick(now + Duration::from_secs(4));
+            assert_eq!(session.state(), SessionState::Challenging);
+            session.handle_tick(now + timeout);
+            assert_eq!(session.state(), SessionState::Failed);
+            assert_eq!(session.poll_timeout(), None);
+            assert!(matches!(
+                drain_events(&mut session)[..],
+                [SessionEvent::ResponseTimeout {
+                    step: Step::Challenge,
+                    attempts: 1
+                }]
+            ));
+        }
+
+        let mut session = online_session(now);
+        session.retransmit_policy(RetransmitPolicy::uniform(timeout, 0));
+        let mut now = now + Duration::from_secs(20);
+        for missed in 1..=2 {
+            session.handle_tick(now);
+            assert_eq!(session.state(), SessionState::HeartbeatOne);
+            expect_keep_alive1(&mut session);
+
+            now += timeout;
+            session.handle_tick(now);
+            assert_eq!(session.state(), SessionState::Online);
+            assert_eq!(session.poll_timeout(), Some(now));
+            assert!(matches!(
+                drain_events(&mut session)[..],
+                [SessionEvent::HeartbeatMissed { missed: m }] if m == missed
+            ));
+        }
+
+        // a kick ends the session no matter what it was waiting for
+        session.handle_tick(now);
+        session.handle_datagram(now, &[0x4d, 0x15, 0x01, 0x02]);
+        assert_eq!(session.state(), SessionState::Failed);
+        assert!(matches!(
+            drain_events(&mut session)[..],
+            [SessionEvent::Kicked(ref message)] if message.content == [1, 2]
+        ));
+    }
+
+    #[test]
+    fn test_drcom_wired_session_retransmits() {
+        let now = Instant::now();
+        let mut policy = RetransmitPolicy::uniform(Duration::from_secs(3), 0);
+        policy
+            .set_step(Step::Challenge, StepPolicy::new(Duration::from_secs(2), 1))
+            .set_step(Step::KeepAliveOne, StepPolicy::new(Duration::from_secs(1), 2));
+        let mut session = WiredSession::new(account());
+        session.retransmit_policy(policy);
+
+        session.start(now);
+        let challenge = session.poll_transmit().unwrap();
+        assert_eq!(session.poll_timeout(), Some(now + Duration::from_secs(2)));
+        session.handle_tick(now + Duration::from_secs(2));
+        assert_eq!(session.state(), SessionState::Challenging);
+        assert_eq!(session.poll_transmit(), Some(challenge));
+        assert_eq!(session.poll_timeout(), Some(now + Duration::from_secs(4)));
+        assert!(matches!(
+            drain_events(&mut session)[..],
+            [SessionEvent::Retransmitted {
+                step: Step::Challenge,
+                attempt: 2
+            }]
+        ));
+
+        // an answer to the resent request moves on and resets the count
+        let now = now + Duration::from_secs(3);
+        session.handle_datagram(now, &challenge_response());
+        assert_eq!(session.poll_transmit().unwrap()[0], 3);
+        session.handle_datagram(now, &login_response());
+        assert_eq!(session.state(), SessionState::KeepAliveOne);
+        let keep_alive1 = session.poll_transmit().unwrap();
+        for attempt in 2..=3 {
+            session.handle_tick(session.poll_timeout().unwrap());
+            assert_eq!(session.poll_transmit().as_ref(), Some(&keep_alive1));
+            assert!(matches!(
+                drain_events(&mut session).last(),
+                Some(SessionEvent::Retransmitted {
+                    step: Step::KeepAliveOne,
+                    attempt: a
+                }) if *a == attempt
+            ));
+        }
+        session.handle_tick(session.poll_timeout().unwrap());
+        assert_eq!(session.state(), SessionState::Failed);
+        assert!(session.poll_transmit().is_none());
+        assert!(matches!(
+            drain_events(&mut session)[..],
+            [SessionEvent::ResponseTimeout {
+                step: Step::KeepAliveOne,
+                attempts: 3
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_drcom_wired_session_logout() {
+        let now = Instant::now();
+        let mut session = online_session(now);
+        session.logout(now);
+        assert_eq!(session.state(), SessionState::LogoutChallenging);
+        assert_eq!(session.poll_transmit().unwrap()[0], 1);
+        assert_eq!(session.poll_timeout(), Some(now + Duration::from_secs(3)));
+
+        session.handle_datagram(now, &challenge_response());
+        assert_eq!(session.state(), SessionState::LoggingOut);
+        let logout = session.poll_transmit().unwrap();
+        assert_eq!(logout[0], 6);
+        // the auth tail of the login closes the packet
+        assert_eq!(logout[logout.len() - 16..], AUTH_TAIL);
+
+        session.handle_datagram(now, &[4, 0, 0, 0]);
+        assert_eq!(session.state(), SessionState::LoggedOut);
+        assert_eq!(session.poll_timeout(), None);
+        assert!(matches!(drain_events(&mut session)[..], [SessionEvent::LoggedOut]));
+
+        // nothing to undo without a login
+        let mut session = WiredSession::new(account());
+        session.start(now);
+        session.logout(now);
+        assert_eq!(session.state(), SessionState::LoggedOut);
+        assert!(session.poll_transmit().is_none());
+    }
+
+    #[test]
+    fn test_drcom_wired_session_round_trips() {
+        let now = Instant::now();
+        let mut session = WiredSession::new(account());
+        session.retransmit_policy(RetransmitPolicy::uniform(Duration::from_secs(1), 1));
+        session.start(now);
+
+        // the challenge is resent, so its answer says nothing about the round trip
+        session.handle_tick(now + Duration::from_secs(1));
+        let mut now = now + Duration::from_millis(1200);
+        session.handle_datagram(now, &challenge_response());
+        let mut round_trips = vec![];
+        for (delay, response) in [(30, login_response()), (5, vec![7, 0, 0, 0])] {
+            now += Duration::from_millis(delay);
+            session.handle_datagram(now, &response);
+        }
+        while let Some(event) = session.poll_event() {
+            if let SessionEvent::RoundTrip { step, rtt } = event {
+                round_trips.push((step, rtt));
+            }
+        }
+        assert_eq!(
+            round_trips,
+            [
+                (Step::Login, Duration::from_millis(30)),
+                (Step::KeepAliveOne, Duration::from_millis(5))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drcom_wired_retransmit_policy_parse() {
+        assert_eq!(
+            RetransmitPolicy::parse_override("login=5:1"),
+            Ok((Step::Login, StepPolicy::new(Duration::from_secs(5), 1)))
+        );
+        assert_eq!(
+            RetransmitPolicy::parse_override("keep_alive2_third=0.5:0"),
+            Ok((
+                Step::KeepAliveTwoThird,
+                StepPolicy::new(Duration::from_millis(500), 0)
+            ))
+        );
+        assert!(RetransmitPolicy::parse_override("dhcp=5:1").is_err());
+        assert!(RetransmitPolicy::parse_override("login=5").is_err());
+        assert!(RetransmitPolicy::parse_override("login=0:1").is_err());
+        assert!(RetransmitPolicy::parse_override("login=-1:1").is_err());
+    }
+
+    pub(super) fn online_session(now: Instant) -> WiredSession {
+        let mut session = WiredSession::new(account());
+        session.start(now);
+        session.handle_datagram(now, &challenge_response());
+        session.handle_datagram(now, &login_response());
+        session.handle_datagram(now, &[7, 0, 0, 0]);
+        session.handle_datagram(now, &keep_alive2_response(0, [0u8; 4]));
+        session.handle_datagram(now, &keep_alive2_response(0, [0u8; 4]));
+        session.handle_datagram(now, &keep_alive2_response(1, [0u8; 4]));
+        assert_eq!(session.state(), SessionState::Online);
+        assert_eq!(session.last_heartbeat(), Some(now));
+        while session.poll_transmit().is_some() {}
+        drain_events(&mut session);
+        session
+    }
+
+    pub(super) fn handshake_responses() -> Vec<Vec<u8>> {
+        vec![
+            challenge_response(),
+            login_response(),
+            vec![7, 0, 0, 0],
+            keep_alive2_response(0, [0u8; 4]),
+            keep_alive2_response(0, [0u8; 4]),
+            keep_alive2_response(1, [0u8; 4]),
+        ]
+    }
+}
+
+mod wired_supervisor_tests {
+    use super::wired_session_tests::handshake_responses;
+    use crate::drcom::wired::dialer::LoginAccount;
+    use crate::drcom::wired::retransmit::RetransmitPolicy;
+    use crate::drcom::wired::session::{SessionEvent, SessionState, WiredSession};
+    use crate::drcom::wired::supervisor::{
+        ReloginReason, SessionSupervisor, SupervisorEvent, SupervisorState,
+    };
+    use std::time::{Duration, Instant};
+
+    fn supervisor() -> SessionSupervisor {
+        let mut la = LoginAccount::new("usernameusername", "password", [0u8; 4]);
+        la.mac_address([0xb8, 0x88, 0xe3, 0x05, 0x16, 0x80]);
+        let mut session = WiredSession::new(la);
+        session
+            .heartbeat_interval(Duration::from_secs(20))
+            .retransmit_policy(RetransmitPolicy::uniform(Duration::from_secs(5), 0));
+        let mut supervisor = SessionSupervisor::new(session);
+        supervisor
+            .initial_backoff(Duration::from_secs(5))
+            .max_backoff(Duration::from_secs(12))
+            .max_missed_heartbeats(2);
+        supervisor
+    }
+
+    fn drain_events(supervisor: &mut SessionSupervisor) -> Vec<SupervisorEvent> {
+        let mut events = vec![];
+        while let Some(event) = supervisor.poll_event() {
+            events.push(event);
+        }
+        while supervisor.poll_transmit().is_some() {}
+        events
+    }
+
+    fn bring_online(supervisor: &mut SessionSupervisor, now: Instant) {
+        for response in handshake_responses() {
+            supervisor.handle_datagram(now, &response);
+        }
+        assert_eq!(supervisor.state(), SupervisorState::Online);
+    }
+
+    fn relogin_scheduled(events: &[SupervisorEvent]) -> Option<(u32, Duration, ReloginReason)> {
+        events.iter().find_map(|e| match *e {
+            SupervisorEvent::ReloginScheduled {
+                attempt,
+                delay,
+                reason,
+            } => Some((attempt, delay, reason)),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_missed_heartbeats() {
+        let mut now = Instant::now();
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+        bring_online(&mut supervisor, now);
+        let events = drain_events(&mut supervisor);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            SupervisorEvent::StateChanged {
+                from: SupervisorState::Connecting,
+                to: SupervisorState::Online
+            }
+        )));
+
+        // first miss is retried right away, the second one gives up
+        now += Duration::from_secs(20);
+        supervisor.handle_tick(now);
+        now += Duration::from_secs(5);
+        supervisor.handle_tick(now);
+        assert_eq!(supervisor.state(), SupervisorState::Online);
+        assert_eq!(supervisor.poll_timeout(), Some(now));
+        supervisor.handle_tick(now);
+        now += Duration::from_secs(5);
+        supervisor.handle_tick(now);
+
+        let until = now + Duration::from_secs(5);
+        assert_eq!(supervisor.state(), SupervisorState::Backoff { until });
+        assert_eq!(supervisor.poll_timeout(), Some(until));
+        let events = drain_events(&mut supervisor);
+        assert_eq!(
+            relogin_scheduled(&events),
+            Some((1, Duration::from_secs(5), ReloginReason::HeartbeatMissed))
+        );
+
+        // redo the handshake after the backoff
+        supervisor.handle_tick(until);
+        assert_eq!(supervisor.state(), SupervisorState::Connecting);
+        assert_eq!(supervisor.session().state(), SessionState::Challenging);
+        assert_eq!(supervisor.poll_transmit().unwrap()[0], 1);
+        bring_online(&mut supervisor, until);
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_backoff() {
+        let mut now = Instant::now();
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+
+        for (attempt, delay) in [(1, 5), (2, 10), (3, 12), (4, 12)] {
+            now += Duration::from_secs(5);
+            supervisor.handle_tick(now);
+            let events = drain_events(&mut supervisor);
+            assert_eq!(
+                relogin_scheduled(&events),
+                Some((attempt, Duration::from_secs(delay), ReloginReason::Timeout))
+            );
+            now += Duration::from_secs(delay);
+            supervisor.handle_tick(now);
+            assert_eq!(supervisor.state(), SupervisorState::Connecting);
+        }
+
+        // getting online resets the backoff
+        bring_online(&mut supervisor, now);
+        supervisor.handle_datagram(now, &[0x4d, 0x15]);
+        let events = drain_events(&mut supervisor);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SupervisorEvent::Session(SessionEvent::Kicked(_)))));
+        assert_eq!(
+            relogin_scheduled(&events),
+            Some((1, Duration::from_secs(5), ReloginReason::Kicked))
+        );
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_login_failures() {
+        let now = Instant::now();
+        let responses = handshake_responses();
+
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+        supervisor.handle_datagram(now, &responses[0]);
+        // server busy is worth another try
+        supervisor.handle_datagram(now, &[5, 0, 0, 5, 2, 0, 0, 0]);
+        assert_eq!(
+            relogin_scheduled(&drain_events(&mut supervisor)),
+            Some((1, Duration::from_secs(5), ReloginReason::LoginFailed))
+        );
+
+        supervisor.handle_tick(now + Duration::from_secs(5));
+        supervisor.handle_datagram(now, &responses[0]);
+        // a wrong password is not
+        supervisor.handle_datagram(now, &[5, 0, 0, 5, 3, 0, 0, 0]);
+        assert_eq!(supervisor.state(), SupervisorState::Stopped);
+        assert_eq!(supervisor.poll_timeout(), None);
+        assert_eq!(relogin_scheduled(&drain_events(&mut supervisor)), None);
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_shutdown() {
+        let now = Instant::now();
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+        supervisor.shutdown(now);
+        assert_eq!(supervisor.state(), SupervisorState::Stopped);
+        assert!(supervisor.poll_transmit().is_none());
+
+        // an unanswered logout still stops
+        let mut supervisor = self::supervisor();
+        supervisor.start(now);
+        bring_online(&mut supervisor, now);
+        drain_events(&mut supervisor);
+        supervisor.shutdown(now);
+        assert_eq!(supervisor.state(), SupervisorState::Stopping);
+        assert_eq!(supervisor.poll_transmit().unwrap()[0], 1);
+        supervisor.handle_tick(now + Duration::from_secs(5));
+        assert_eq!(supervisor.state(), SupervisorState::Stopped);
+        let events = drain_events(&mut supervisor);
+        assert_eq!(relogin_scheduled(&events), None);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            SupervisorEvent::Session(SessionEvent::ResponseTimeout { .. })
+        )));
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_connectivity_lost() {
+        let now = Instant::now();
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+        // nothing to lose before the login
+        supervisor.connectivity_lost(now);
+        assert_eq!(supervisor.state(), SupervisorState::Connecting);
+
+        bring_online(&mut supervisor, now);
+        drain_events(&mut supervisor);
+        supervisor.connectivity_lost(now);
+        let until = now + Duration::from_secs(5);
+        assert_eq!(supervisor.state(), SupervisorState::Backoff { until });
+        assert!(supervisor.poll_transmit().is_none());
+        let events = drain_events(&mut supervisor);
+        assert_eq!(
+            relogin_scheduled(&events),
+            Some((1, Duration::from_secs(5), ReloginReason::ConnectivityLost))
+        );
+
+        supervisor.handle_tick(until);
+        assert_eq!(supervisor.state(), SupervisorState::Connecting);
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_link_changes() {
+        let now = Instant::now();
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+        bring_online(&mut supervisor, now);
+        drain_events(&mut supervisor);
+
+        // no heartbeats while the link is down
+        supervisor.link_down();
+        assert_eq!(supervisor.state(), SupervisorState::LinkDown);
+        assert_eq!(supervisor.poll_timeout(), None);
+        supervisor.handle_tick(now + Duration::from_secs(60));
+        assert!(supervisor.poll_transmit().is_none());
+
+        // a fresh login once it is back, without waiting for a backoff
+        let now = now + Duration::from_secs(60);
+        supervisor.link_up(now);
+        assert_eq!(supervisor.state(), SupervisorState::Connecting);
+        assert_eq!(supervisor.session().state(), SessionState::Challenging);
+        assert_eq!(supervisor.poll_transmit().unwrap()[0], 1);
+        let events = drain_events(&mut supervisor);
+        assert_eq!(
+            relogin_scheduled(&events),
+            Some((1, Duration::ZERO, ReloginReason::LinkRestored))
+        );
+        bring_online(&mut supervisor, now);
+        drain_events(&mut supervisor);
+
+        supervisor.address_changed(now);
+        assert_eq!(supervisor.state(), SupervisorState::Connecting);
+        assert_eq!(supervisor.poll_transmit().unwrap()[0], 1);
+        let events = drain_events(&mut supervisor);
+        assert_eq!(
+            relogin_scheduled(&events),
+            Some((1, Duration::ZERO, ReloginReason::AddressChanged))
+        );
+
+        // link_up only matters after link_down
+        supervisor.link_up(now);
+        assert!(drain_events(&mut supervisor).is_empty());
+        supervisor.shutdown(now);
+        supervisor.link_down();
+        assert_ne!(supervisor.state(), SupervisorState::LinkDown);
+    }
+
+    #[test]
+    fn test_drcom_wired_supervisor_heartbeat_validation_error() {
+        let now = Instant::now();
+        let mut supervisor = supervisor();
+        supervisor.start(now);
+        bring_online(&mut supervisor, now);
+
+        let now = now + Duration::from_secs(20);
+        supervisor.handle_tick(now);
+        // a late duplicate is not worth a relogin
+        supervisor.handle_datagram(now, &handshake_responses()[0]);
+        assert_eq!(relogin_scheduled(&drain_events(&mut supervisor)), None);
+
+        supervisor.handle_datagram(now, &[7, 0, 0, 0]);
+        supervisor.handle_datagram(now, &[7, 2, 0x28, 0, 0x0b]);
+        let events = drain_events(&mut supervisor);
+        assert_eq!(
+            relogin_scheduled(&events),
+            Some((1, Duration::from_secs(5), ReloginReason::ValidationError))
+        );
+    }
 }
@@ -3,18 +3,19 @@ use std::num::Wrapping;
 use std::str::FromStr;
 use std::{io, result};
 
-use byteorder::{ByteOrder, NetworkEndian};
+use byteorder::{ByteOrder, LittleEndian, NetworkEndian};
 use rand;
 use rand::Rng;
 
 use crate::common::bytes::{BytesAble, BytesAbleNum};
 use crate::common::hex::ToHex;
 use crate::common::reader::{ReadBytesError, ReaderHelper};
+use crate::common::secret::{Secret, SecretBytes, SecretString};
 use crate::common::utils::current_timestamp;
 use crate::crypto::hash::{HasherBuilder, HasherType};
 use crate::drcom::{
-    DrCOMCommon, DrCOMResponseCommon, DrCOMValidateError, PACKET_MAGIC_NUMBER, PASSWORD_MAX_LEN,
-    USERNAME_MAX_LEN,
+    DrCOMCommon, DrCOMResponseCommon, DrCOMValidateError, LOGOUT_MAGIC_NUMBER, PACKET_MAGIC_NUMBER,
+    PASSWORD_MAX_LEN, USERNAME_MAX_LEN,
 };
 
 #[derive(Debug)]
@@ -22,6 +23,7 @@ pub enum LoginError {
     ValidateError(DrCOMValidateError),
     PacketReadError(ReadBytesError),
     FieldValueOverflow(usize, usize),
+    LoginFailure(LoginFailure),
 }
 
 type LoginResult<T> = result::Result<T, LoginError>;
@@ -33,7 +35,12 @@ pub struct ChallengeRequest {
 
 #[derive(Debug)]
 pub struct ChallengeResponse {
-    pub hash_salt: [u8; 4],
+    /// Echo of the challenge request sequence.
+    pub sequence:        u16,
+    pub hash_salt:       [u8; 4],
+    /// Client address as seen by the server, if it was sent.
+    pub client_ip:       Option<Ipv4Addr>,
+    pub encrypt_version: Option<u8>,
 }
 
 #[derive(Debug)]
@@ -56,21 +63,21 @@ pub struct TagHostInfo {
 
 #[derive(Debug)]
 struct TagLDAPAuthInfo {
-    password_ror_hash: Vec<u8>,
+    password_ror_hash: Secret<Vec<u8>>,
 }
 
 #[derive(Debug)]
 struct TagAccountInfo {
     username:          String,
-    password_md5_hash: [u8; 16],
+    password_md5_hash: SecretBytes<16>,
 }
 
 #[derive(Debug)]
 struct TagAdapterInfo {
     counts: u8,
-    password_md5_hash: [u8; 16],
+    password_md5_hash: SecretBytes<16>,
     mac_address: [u8; 6],
-    password_md5_hash_validator: [u8; 16],
+    password_md5_hash_validator: SecretBytes<16>,
     ipaddresses: [Ipv4Addr; 4],
 }
 
@@ -78,6 +85,8 @@ struct TagAdapterInfo {
 struct TagAuthExtraInfo<'a> {
     origin_data: &'a [u8],
     mac_address: [u8; 6],
+    // not sent, the checksum only covers zeroed option bytes
+    #[allow(dead_code)]
     option:      u16,
 }
 
@@ -106,13 +115,52 @@ pub struct LoginRequest {
 
 #[derive(Debug)]
 pub struct LoginResponse {
-    pub keep_alive_key: [u8; 16],
+    /// Accumulated online time of the account, in minutes.
+    pub used_time_minutes: u32,
+    /// Accumulated traffic of the account, in KiB.
+    pub used_traffic_kb:   u32,
+    /// Remaining balance, in the smallest currency unit the server bills with.
+    pub balance:           u32,
+    /// The auth tail, required by keep-alive and logout.
+    pub keep_alive_key:    SecretBytes<16>,
 }
 
+/// Reasons carried by the 0x05 login failure packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginFailure {
+    AlreadyOnline {
+        online_ip:  Ipv4Addr,
+        online_mac: [u8; 6],
+    },
+    ServerBusy,
+    WrongPassword,
+    InsufficientBalance,
+    AccountFrozen,
+    WrongIP,
+    WrongMAC,
+    TooManyIPs,
+    ClientVersionTooOld,
+    WrongIPMACBinding,
+    ForceDHCP,
+    Unknown(u8),
+}
+
+#[derive(Debug)]
+pub struct LogoutRequest {
+    mac_address:          [u8; 6],
+    account_info:         TagAccountInfo,
+    control_check_status: u8,
+    adapter_count:        u8,
+    auth_tail:            SecretBytes<16>,
+}
+
+#[derive(Debug)]
+pub struct LogoutResponse;
+
 #[derive(Debug)]
 pub struct LoginAccount {
     username:             String,
-    password:             String,
+    password:             SecretString,
     hash_salt:            [u8; 4],
     adapter_count:        u8,
     mac_address:          [u8; 6],
@@ -138,8 +186,8 @@ pub struct LoginAccount {
     auth_extra_option:    u16,
 }
 
-const SERVICE_PACK_MAX_LEN: usize = 32;
-const HOSTNAME_MAX_LEN: usize = 32;
+pub(crate) const SERVICE_PACK_MAX_LEN: usize = 32;
+pub(crate) const HOSTNAME_MAX_LEN: usize = 32;
 
 macro_rules! validate_field_value_overflow {
     (
@@ -209,6 +257,12 @@ impl ChallengeRequest {
     }
 }
 
+impl DrCOMCommon for ChallengeResponse {
+    fn code() -> u8 {
+        2u8
+    }
+}
+
 impl DrCOMResponseCommon for ChallengeResponse {}
 
 impl ChallengeResponse {
@@ -217,16 +271,30 @@ impl ChallengeResponse {
         R: io::Read,
     {
         // validate packet and consume 1 byte
-        Self::validate_stream(input, |c| c == 0x02).map_err(LoginError::ValidateError)?;
+        Self::validate_stream(input, |c| c == Self::code()).map_err(LoginError::ValidateError)?;
 
-        // drain unknow bytes
-        input.read_bytes(3).map_err(LoginError::PacketReadError)?;
+        let header_bytes = input.read_bytes(3).map_err(LoginError::PacketReadError)?;
+        let sequence = LittleEndian::read_u16(&header_bytes[1..3]);
 
         let salt_bytes = input.read_bytes(4).map_err(LoginError::PacketReadError)?;
         let mut hash_salt = [0u8; 4];
         hash_salt.clone_from_slice(&salt_bytes);
 
-        Ok(ChallengeResponse { hash_salt })
+        // the trailing fields are not sent by every server
+        let client_ip = input
+            .read_bytes(12)
+            .and_then(|_| input.read_bytes(4))
+            .ok()
+            .map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            .filter(|ip| !ip.is_unspecified());
+        let encrypt_version = input.read_bytes(1).ok().map(|b| b[0]);
+
+        Ok(ChallengeResponse {
+            sequence,
+            hash_salt,
+            client_ip,
+            encrypt_version,
+        })
     }
 }
 
@@ -302,13 +370,13 @@ impl TagHostInfo {
 
 impl TagLDAPAuthInfo {
     fn validate(&self) -> LoginResult<()> {
-        validate_field_value_overflow!(self.password_ror_hash, PASSWORD_MAX_LEN);
+        validate_field_value_overflow!(self.password_ror_hash.expose(), PASSWORD_MAX_LEN);
         Ok(())
     }
 
     fn attribute_length(&self) -> usize {
         // code + password_ror_hash length + ()
-        1 + 1 + self.password_ror_hash.len()
+        1 + 1 + self.password_ror_hash.expose().len()
     }
 
     fn as_bytes(&self) -> LoginResult<Vec<u8>> {
@@ -316,8 +384,8 @@ impl TagLDAPAuthInfo {
 
         let mut result = Vec::with_capacity(self.attribute_length());
         result.push(0u8);
-        result.push(self.password_ror_hash.len() as u8);
-        result.extend(&self.password_ror_hash);
+        result.push(self.password_ror_hash.expose().len() as u8);
+        result.extend(self.password_ror_hash.expose());
 
         Ok(result)
     }
@@ -327,7 +395,7 @@ impl LoginAccount {
     pub fn new(username: &str, password: &str, hash_salt: [u8; 4]) -> Self {
         LoginAccount {
             username: username.to_string(),
-            password: password.to_string(),
+            password: SecretString::from(password),
             hash_salt,
             adapter_count: 1,
             mac_address: [0, 0, 0, 0, 0, 0],
@@ -357,12 +425,12 @@ impl LoginAccount {
     fn validate(&self) -> LoginResult<()> {
         validate_field_value_overflow!(
             self.username, USERNAME_MAX_LEN;
-            self.password, PASSWORD_MAX_LEN
+            self.password.expose(), PASSWORD_MAX_LEN
         );
         Ok(())
     }
 
-    fn ror(md5_digest: &[u8; 16], password: &str) -> LoginResult<Vec<u8>> {
+    fn ror(md5_digest: &[u8; 16], password: &str) -> LoginResult<Secret<Vec<u8>>> {
         if password.len() > PASSWORD_MAX_LEN {
             return Err(LoginError::FieldValueOverflow(
                 password.len(),
@@ -371,38 +439,43 @@ impl LoginAccount {
         }
 
         let mut result = Vec::with_capacity(PASSWORD_MAX_LEN);
-        for (i, c) in password.as_bytes().into_iter().enumerate() {
+        for (i, c) in password.as_bytes().iter().enumerate() {
             let x: u8 = md5_digest[i] ^ c;
-            result.push((x << 3) + (x >> 5));
+            result.push(x.rotate_left(3));
         }
-        Ok(result)
+        Ok(Secret::new(result))
     }
 
-    fn password_md5_hash(&self) -> [u8; 16] {
+    fn salted_password_md5_hash(
+        &self,
+        magic_number: u16,
+        hash_salt: &[u8; 4],
+    ) -> SecretBytes<16> {
         let mut md5 = HasherBuilder::build(HasherType::MD5);
-        md5.update(&PACKET_MAGIC_NUMBER.as_bytes_le());
-        md5.update(&self.hash_salt);
-        md5.update(self.password.as_bytes());
+        md5.update(&magic_number.as_bytes_le());
+        md5.update(hash_salt);
+        md5.update(self.password.expose().as_bytes());
+
+        SecretBytes::copy_from_slice(&Secret::new(md5.finish()).expose()[..])
+    }
 
-        let mut md5_digest = [0u8; 16];
-        md5_digest.copy_from_slice(&md5.finish());
-        md5_digest
+    /// The salted password hash, also what keep_alive1 proves the password with.
+    pub fn password_md5_hash(&self) -> SecretBytes<16> {
+        self.salted_password_md5_hash(PACKET_MAGIC_NUMBER, &self.hash_salt)
     }
 
-    fn password_ror_hash(&self) -> LoginResult<Vec<u8>> {
-        Self::ror(&self.password_md5_hash(), &self.password)
+    fn password_ror_hash(&self) -> LoginResult<Secret<Vec<u8>>> {
+        Self::ror(self.password_md5_hash().expose(), self.password.expose())
     }
 
-    fn password_md5_hash_validator(&self) -> [u8; 16] {
+    fn password_md5_hash_validator(&self) -> SecretBytes<16> {
         let mut md5 = HasherBuilder::build(HasherType::MD5);
         md5.update(&[1u8; 1]);
-        md5.update(self.password.as_bytes());
+        md5.update(self.password.expose().as_bytes());
         md5.update(&self.hash_salt);
         md5.update(&[0u8; 4]);
 
-        let mut md5_digest = [0u8; 16];
-        md5_digest.copy_from_slice(&md5.finish());
-        md5_digest
+        SecretBytes::copy_from_slice(&Secret::new(md5.finish()).expose()[..])
     }
 
     fn tag_account_info(&self) -> LoginResult<TagAccountInfo> {
@@ -477,16 +550,46 @@ impl LoginAccount {
         })
     }
 
+    /// Build a logout packet. `hash_salt` must come from a fresh challenge
+    /// and `auth_tail` is the `keep_alive_key` of the preceding login.
+    pub fn logout_request(
+        &self,
+        hash_salt: [u8; 4],
+        auth_tail: &SecretBytes<16>,
+    ) -> LoginResult<LogoutRequest> {
+        self.validate()?;
+
+        Ok(LogoutRequest {
+            mac_address:          self.mac_address,
+            account_info:         TagAccountInfo {
+                username:          self.username.clone(),
+                password_md5_hash: self.salted_password_md5_hash(LOGOUT_MAGIC_NUMBER, &hash_salt),
+            },
+            control_check_status: self.control_check_status,
+            adapter_count:        self.adapter_count,
+            auth_tail:            auth_tail.clone(),
+        })
+    }
+
     pub fn ipaddresses(&mut self, value: &[Ipv4Addr]) -> &mut Self {
         let mut fixed_ipaddresses = [Ipv4Addr::from(0x0); 4];
-        for (i, ip) in value.into_iter().take(4).enumerate() {
+        for (i, ip) in value.iter().take(4).enumerate() {
             fixed_ipaddresses[i] = *ip;
         }
         self.ipaddresses = fixed_ipaddresses;
         self
     }
 
+    pub(crate) fn has_ipaddresses(&self) -> bool {
+        self.ipaddresses.iter().any(|ip| !ip.is_unspecified())
+    }
+
+    pub(crate) fn has_only_ipaddress(&self, ip: Ipv4Addr) -> bool {
+        self.ipaddresses[0] == ip && self.ipaddresses[1..].iter().all(Ipv4Addr::is_unspecified)
+    }
+
     configurable_field!(
+        hash_salt: [u8; 4],
         adapter_count: u8,
         mac_address: [u8; 6],
         dog_flag: u8,
@@ -497,6 +600,7 @@ impl LoginAccount {
         hostname: String,
         service_pack: String,
         dns_server: Ipv4Addr,
+        dhcp_server: Ipv4Addr,
         backup_dns_server: Ipv4Addr,
         wins_ips: [Ipv4Addr; 2],
         major_version: u32,
@@ -517,7 +621,7 @@ impl TagAccountInfo {
     }
 
     fn content_length(&self) -> usize {
-        self.password_md5_hash.len() + self.username.len() + 4 // pading?
+        self.password_md5_hash.expose().len() + self.username.len() + 4 // pading?
     }
 
     fn attribute_length(&self) -> usize {
@@ -530,7 +634,7 @@ impl TagAccountInfo {
 
         let mut result = Vec::with_capacity(self.attribute_length());
         result.extend((self.content_length() as u16).as_bytes_be());
-        result.extend_from_slice(&self.password_md5_hash);
+        result.extend_from_slice(self.password_md5_hash.expose());
         result.extend_from_slice(self.username.as_bytes());
         Ok(result)
     }
@@ -548,7 +652,7 @@ impl TagAdapterInfo {
         let mac_address_u64 = NetworkEndian::read_uint(&mac_address, 6);
 
         let mut result = [0u8; 6];
-        result.clone_from_slice(&((prefix_hex_u64 ^ mac_address_u64) as u64).as_bytes_be()[2..8]);
+        result.clone_from_slice(&(prefix_hex_u64 ^ mac_address_u64).as_bytes_be()[2..8]);
         result
     }
 
@@ -557,9 +661,9 @@ impl TagAdapterInfo {
         result.push(self.counts);
         result.extend_from_slice(&Self::hash_mac_address(
             self.mac_address,
-            &self.password_md5_hash,
+            self.password_md5_hash.expose(),
         ));
-        result.extend_from_slice(&self.password_md5_hash_validator);
+        result.extend_from_slice(self.password_md5_hash_validator.expose());
 
         {
             let mut specified_ip_count = 0u8;
@@ -723,6 +827,141 @@ impl LoginRequest {
     }
 }
 
+impl DrCOMCommon for LoginFailure {
+    fn code() -> u8 {
+        5u8
+    }
+}
+
+impl DrCOMResponseCommon for LoginFailure {}
+
+impl LoginFailure {
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> LoginResult<Self>
+    where
+        R: io::Read,
+    {
+        // validate packet and consume 1 byte
+        Self::validate_stream(input, |c| c == Self::code()).map_err(LoginError::ValidateError)?;
+        Self::from_body(input)
+    }
+
+    fn from_body<R>(input: &mut io::BufReader<R>) -> LoginResult<Self>
+    where
+        R: io::Read,
+    {
+        // drain unknow bytes
+        input.read_bytes(3).map_err(LoginError::PacketReadError)?;
+
+        let error_code = input.read_bytes(1).map_err(LoginError::PacketReadError)?[0];
+        Ok(match error_code {
+            0x01 => {
+                let ip_bytes = input.read_bytes(4).map_err(LoginError::PacketReadError)?;
+                let mac_bytes = input.read_bytes(6).map_err(LoginError::PacketReadError)?;
+                let mut online_mac = [0u8; 6];
+                online_mac.copy_from_slice(&mac_bytes);
+                LoginFailure::AlreadyOnline {
+                    online_ip: Ipv4Addr::new(ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]),
+                    online_mac,
+                }
+            }
+            0x02 => LoginFailure::ServerBusy,
+            0x03 => LoginFailure::WrongPassword,
+            0x04 => LoginFailure::InsufficientBalance,
+            0x05 => LoginFailure::AccountFrozen,
+            0x07 => LoginFailure::WrongIP,
+            0x0b => LoginFailure::WrongMAC,
+            0x14 => LoginFailure::TooManyIPs,
+            0x15 => LoginFailure::ClientVersionTooOld,
+            0x16 => LoginFailure::WrongIPMACBinding,
+            0x17 => LoginFailure::ForceDHCP,
+            c => LoginFailure::Unknown(c),
+        })
+    }
+
+    /// Raw error code as sent by the server.
+    pub fn error_code(&self) -> u8 {
+        match *self {
+            LoginFailure::AlreadyOnline { .. } => 0x01,
+            LoginFailure::ServerBusy => 0x02,
+            LoginFailure::WrongPassword => 0x03,
+            LoginFailure::InsufficientBalance => 0x04,
+            LoginFailure::AccountFrozen => 0x05,
+            LoginFailure::WrongIP => 0x07,
+            LoginFailure::WrongMAC => 0x0b,
+            LoginFailure::TooManyIPs => 0x14,
+            LoginFailure::ClientVersionTooOld => 0x15,
+            LoginFailure::WrongIPMACBinding => 0x16,
+            LoginFailure::ForceDHCP => 0x17,
+            LoginFailure::Unknown(c) => c,
+        }
+    }
+
+    /// Short snake_case name, e.g. for labelling metrics.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LoginFailure::AlreadyOnline { .. } => "already_online",
+            LoginFailure::ServerBusy => "server_busy",
+            LoginFailure::WrongPassword => "wrong_password",
+            LoginFailure::InsufficientBalance => "insufficient_balance",
+            LoginFailure::AccountFrozen => "account_frozen",
+            LoginFailure::WrongIP => "wrong_ip",
+            LoginFailure::WrongMAC => "wrong_mac",
+            LoginFailure::TooManyIPs => "too_many_ips",
+            LoginFailure::ClientVersionTooOld => "client_version_too_old",
+            LoginFailure::WrongIPMACBinding => "wrong_ip_mac_binding",
+            LoginFailure::ForceDHCP => "force_dhcp",
+            LoginFailure::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Whether logging in again later may succeed without changing the account settings.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            *self,
+            LoginFailure::AlreadyOnline { .. } | LoginFailure::ServerBusy | LoginFailure::Unknown(_)
+        )
+    }
+}
+
+impl DrCOMCommon for LogoutRequest {
+    fn code() -> u8 {
+        6u8
+    }
+}
+
+impl LogoutRequest {
+    #[inline]
+    fn username_field_length() -> usize {
+        36
+    }
+
+    #[inline]
+    fn packet_length() -> usize {
+        // magic number + attribute length + password hash + username + control_check_status
+        // + adapter count + hashed mac address + auth tail
+        2 + 2 + 16 + Self::username_field_length() + 1 + 1 + 6 + 16
+    }
+
+    pub fn as_bytes(&self) -> LoginResult<Vec<u8>> {
+        let mut result = Vec::with_capacity(Self::packet_length());
+        result.extend(LOGOUT_MAGIC_NUMBER.as_bytes_le());
+        result.extend(self.account_info.as_bytes()?);
+        // username is zero padded to a fixed width
+        result.extend(vec![
+            0u8;
+            Self::username_field_length() - self.account_info.username.len()
+        ]);
+        result.push(self.control_check_status);
+        result.push(self.adapter_count);
+        result.extend_from_slice(&TagAdapterInfo::hash_mac_address(
+            self.mac_address,
+            self.account_info.password_md5_hash.expose(),
+        ));
+        result.extend_from_slice(self.auth_tail.expose());
+        Ok(result)
+    }
+}
+
 impl DrCOMResponseCommon for LoginResponse {}
 
 impl DrCOMCommon for LoginResponse {
@@ -737,16 +976,53 @@ impl LoginResponse {
         R: io::Read,
     {
         // validate packet and consume 1 byte
-        Self::validate_stream(input, |c| c == Self::code()).map_err(LoginError::ValidateError)?;
+        let code = Self::validate_stream(input, |c| {
+            c == Self::code() || c == LoginFailure::code()
+        })
+        .map_err(LoginError::ValidateError)?;
+        if code == LoginFailure::code() {
+            return Err(LoginError::LoginFailure(LoginFailure::from_body(input)?));
+        }
+
+        // drain unknow bytes
+        input.read_bytes(4).map_err(LoginError::PacketReadError)?;
+
+        let usage_bytes = input.read_bytes(12).map_err(LoginError::PacketReadError)?;
+        let used_time_minutes = LittleEndian::read_u32(&usage_bytes[0..4]);
+        let used_traffic_kb = LittleEndian::read_u32(&usage_bytes[4..8]);
+        let balance = LittleEndian::read_u32(&usage_bytes[8..12]);
 
         // drain unknow bytes
-        input.read_bytes(22).map_err(LoginError::PacketReadError)?;
+        input.read_bytes(6).map_err(LoginError::PacketReadError)?;
 
-        let key_bytes = input.read_bytes(16).map_err(LoginError::PacketReadError)?;
-        let mut keep_alive_key = [0u8; 16];
-        keep_alive_key.clone_from_slice(&key_bytes);
+        let key_bytes = Secret::new(input.read_bytes(16).map_err(LoginError::PacketReadError)?);
+        let keep_alive_key = SecretBytes::copy_from_slice(key_bytes.expose());
 
-        Ok(LoginResponse { keep_alive_key })
+        Ok(LoginResponse {
+            used_time_minutes,
+            used_traffic_kb,
+            balance,
+            keep_alive_key,
+        })
+    }
+}
+
+impl DrCOMCommon for LogoutResponse {
+    fn code() -> u8 {
+        4u8
+    }
+}
+
+impl DrCOMResponseCommon for LogoutResponse {}
+
+impl LogoutResponse {
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> LoginResult<Self>
+    where
+        R: io::Read,
+    {
+        // validate packet and consume 1 byte
+        Self::validate_stream(input, |c| c == Self::code()).map_err(LoginError::ValidateError)?;
+        Ok(LogoutResponse {})
     }
 }
 
@@ -809,7 +1085,7 @@ fn test_login_packet_attributes() {
 #[test]
 fn test_password_hash() {
     assert_eq!(
-        LoginAccount::ror(&[253u8; 16], "1234567812345678").unwrap(),
+        *LoginAccount::ror(&[253u8; 16], "1234567812345678").unwrap().expose(),
         vec![102, 126, 118, 78, 70, 94, 86, 46, 102, 126, 118, 78, 70, 94, 86, 46,]
     );
 
@@ -828,11 +1104,11 @@ fn test_password_hash() {
         .auth_extra_option(0x0);
 
     assert_eq!(
-        la.password_md5_hash(),
+        *la.password_md5_hash().expose(),
         [174, 175, 144, 214, 168, 238, 67, 106, 128, 153, 49, 172, 94, 102, 177, 222]
     );
     assert_eq!(
-        la.password_md5_hash_validator(),
+        *la.password_md5_hash_validator().expose(),
         [169, 80, 242, 73, 215, 59, 106, 173, 172, 242, 14, 27, 203, 29, 82, 153]
     );
 
@@ -847,7 +1123,7 @@ fn test_password_hash() {
     {
         let la = LoginAccount::new("usernameusername", "password", [0x7, 0x8, 0x9, 0x10]);
         assert_eq!(
-            la.password_md5_hash(),
+            *la.password_md5_hash().expose(),
             [227, 154, 169, 77, 33, 112, 224, 233, 249, 52, 229, 206, 20, 132, 105, 72]
         );
     }
@@ -1,3 +1,4 @@
+use std::io::Read;
 use std::net::Ipv4Addr;
 use std::{io, result};
 
@@ -5,9 +6,9 @@ use byteorder::{ByteOrder, NativeEndian};
 
 use crate::common::bytes::BytesAbleNum;
 use crate::common::reader::{ReadBytesError, ReaderHelper};
+use crate::common::secret::{Secret, SecretBytes};
 use crate::common::utils::current_timestamp;
-use crate::crypto::hash::{HasherBuilder, HasherType};
-use crate::drcom::{DrCOMCommon, DrCOMFlag, DrCOMResponseCommon, DrCOMValidateError, PACKET_MAGIC_NUMBER};
+use crate::drcom::{DrCOMCommon, DrCOMFlag, DrCOMResponseCommon, DrCOMValidateError};
 
 #[derive(Debug)]
 pub enum HeartbeatError {
@@ -21,26 +22,33 @@ type HeartbeatResult<T> = result::Result<T, HeartbeatError>;
 #[derive(Debug)]
 pub struct PhaseOneRequest {
     timestamp:      u32,
-    hash_salt:      [u8; 4],
-    password:       String,
-    keep_alive_key: [u8; 16],
+    password_hash:  SecretBytes<16>,
+    keep_alive_key: SecretBytes<16>,
 }
 
 #[derive(Debug)]
 pub struct PhaseTwoRequest<'a> {
     sequence:       u8,
-    keep_alive_key: [u8; 4],
+    keep_alive_key: SecretBytes<4>,
     flag:           &'a (dyn DrCOMFlag + 'a),
     type_id:        u8,
     host_ip:        Ipv4Addr,
 }
 
+#[derive(Debug)]
 pub struct PhaseOneResponse;
 
 #[derive(Debug)]
 pub struct PhaseTwoResponse {
     pub sequence:       u8,
-    pub keep_alive_key: [u8; 4],
+    pub keep_alive_key: SecretBytes<4>,
+}
+
+/// Unsolicited 0x4d packet pushed by the server, e.g. notices or a kick.
+#[derive(Debug)]
+pub struct ServerMessage {
+    pub kind:    u8,
+    pub content: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -56,17 +64,17 @@ impl DrCOMCommon for PhaseOneRequest {
 }
 
 impl PhaseOneRequest {
+    /// `password_hash` is `LoginAccount::password_md5_hash` for the current
+    /// salt, `keep_alive_key` the auth tail of the login response.
     pub fn new(
-        hash_salt: [u8; 4],
-        password: &str,
-        keep_alive_key: [u8; 16],
+        password_hash: &SecretBytes<16>,
+        keep_alive_key: &SecretBytes<16>,
         timestamp: Option<u32>,
     ) -> Self {
         PhaseOneRequest {
             timestamp: timestamp.unwrap_or_else(current_timestamp),
-            hash_salt,
-            password: password.to_string(),
-            keep_alive_key,
+            password_hash: password_hash.clone(),
+            keep_alive_key: keep_alive_key.clone(),
         }
     }
 
@@ -75,24 +83,13 @@ impl PhaseOneRequest {
         1 + 16 + 3 + 16 + 2 + 4
     }
 
-    fn password_hash(&self) -> [u8; 16] {
-        let mut md5 = HasherBuilder::build(HasherType::MD5);
-        md5.update(&PACKET_MAGIC_NUMBER.as_bytes_le());
-        md5.update(&self.hash_salt);
-        md5.update(self.password.as_bytes());
-
-        let mut md5_digest = [0u8; 16];
-        md5_digest.copy_from_slice(&md5.finish());
-        md5_digest
-    }
-
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut result = Vec::with_capacity(Self::packet_length());
         result.push(Self::code());
-        result.extend_from_slice(&self.password_hash());
+        result.extend_from_slice(self.password_hash.expose());
         // padding?
         result.extend_from_slice(&[0u8; 3]);
-        result.extend_from_slice(&self.keep_alive_key);
+        result.extend_from_slice(self.keep_alive_key.expose());
         result.extend(((self.timestamp % 0xFFFF) as u16).as_bytes_be());
         // padding?
         result.extend_from_slice(&[0u8; 4]);
@@ -138,7 +135,7 @@ impl<'a> DrCOMCommon for PhaseTwoRequest<'a> {
 impl<'a> PhaseTwoRequest<'a> {
     pub fn new<F>(
         sequence: u8,
-        keep_alive_key: [u8; 4],
+        keep_alive_key: &SecretBytes<4>,
         flag: &'a F,
         host_ip: Ipv4Addr,
         type_id: Option<u8>,
@@ -148,7 +145,7 @@ impl<'a> PhaseTwoRequest<'a> {
     {
         PhaseTwoRequest {
             sequence,
-            keep_alive_key,
+            keep_alive_key: keep_alive_key.clone(),
             flag,
             type_id: type_id.unwrap_or(1),
             host_ip,
@@ -184,7 +181,7 @@ impl<'a> PhaseTwoRequest<'a> {
         result.extend(self.flag.as_u32().as_bytes_le());
         // padding?
         result.extend_from_slice(&[0u8; 6]);
-        result.extend_from_slice(&self.keep_alive_key);
+        result.extend_from_slice(self.keep_alive_key.expose());
         // padding?
         result.extend_from_slice(&[0u8; 4]);
 
@@ -239,15 +236,50 @@ impl PhaseTwoResponse {
             .read_bytes(12)
             .map_err(HeartbeatError::PacketReadError)?;
 
-        let mut keep_alive_key = [0u8; 4];
-        keep_alive_key.copy_from_slice(
-            &input
+        let key_bytes = Secret::new(
+            input
                 .read_bytes(4)
                 .map_err(HeartbeatError::PacketReadError)?,
         );
+        let keep_alive_key = SecretBytes::copy_from_slice(key_bytes.expose());
         Ok(PhaseTwoResponse {
             sequence,
             keep_alive_key,
         })
     }
 }
+
+impl DrCOMCommon for ServerMessage {
+    fn code() -> u8 {
+        0x4du8
+    }
+}
+
+impl DrCOMResponseCommon for ServerMessage {}
+
+impl ServerMessage {
+    const KICK_KIND: u8 = 0x15;
+
+    pub fn from_bytes<R>(input: &mut io::BufReader<R>) -> HeartbeatResult<Self>
+    where
+        R: io::Read,
+    {
+        // validate packet and consume 1 byte
+        Self::validate_stream(input, |c| c == Self::code())
+            .map_err(HeartbeatError::ValidateError)?;
+
+        let kind = input
+            .read_bytes(1)
+            .map_err(HeartbeatError::PacketReadError)?[0];
+        let mut content = Vec::new();
+        input
+            .read_to_end(&mut content)
+            .map_err(|e| HeartbeatError::PacketReadError(ReadBytesError::IOError(e)))?;
+        Ok(ServerMessage { kind, content })
+    }
+
+    /// Whether the server ended our session, e.g. because the account logged in elsewhere.
+    pub fn is_kick(&self) -> bool {
+        self.kind == Self::KICK_KIND
+    }
+}
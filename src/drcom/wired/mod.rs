@@ -1,2 +1,6 @@
 pub mod dialer;
 pub mod heartbeater;
+pub mod packet;
+pub mod retransmit;
+pub mod session;
+pub mod supervisor;
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::io;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::drcom::wired::dialer::{
+    ChallengeResponse, LoginError, LoginFailure, LoginResponse, LogoutResponse,
+};
+use crate::drcom::wired::heartbeater::{
+    HeartbeatError, PhaseOneResponse, PhaseTwoResponse, ServerMessage,
+};
+use crate::drcom::DrCOMCommon;
+
+/// Length announced by a keep_alive2 response.
+const PHASE_TWO_LENGTH: u16 = 0x28;
+/// Length announced by the "file" the server may answer the first keep_alive2 with.
+const KEEP_ALIVE_FILE_LENGTH: u16 = 0x10;
+/// A login response up to the keep alive key.
+const LOGIN_RESPONSE_MIN_LEN: usize = 39;
+
+#[derive(Debug)]
+pub enum PacketError {
+    Empty,
+    Login(LoginError),
+    Heartbeat(HeartbeatError),
+}
+
+type PacketResult<T> = Result<T, PacketError>;
+
+/// Anything the wired server sends, told apart by its code and type bytes so
+/// that unsolicited packets can be routed instead of failing the current step.
+#[derive(Debug)]
+pub enum DrcomPacket {
+    ChallengeResponse(ChallengeResponse),
+    LoginResponse(LoginResponse),
+    /// A 0x04 too short to be a login response, how the server acknowledges a logout.
+    LogoutResponse(LogoutResponse),
+    LoginFailure(LoginFailure),
+    /// Any other 0x07, servers disagree on what follows the code.
+    PhaseOneResponse(PhaseOneResponse),
+    PhaseTwoResponse(PhaseTwoResponse),
+    /// Sent instead of a keep_alive2 response, asking for the next sequence.
+    KeepAliveFile { sequence: u8 },
+    ServerMessage(ServerMessage),
+    Unknown(u8),
+}
+
+impl DrcomPacket {
+    pub fn decode(datagram: &[u8]) -> PacketResult<Self> {
+        let code = *datagram.first().ok_or(PacketError::Empty)?;
+        let mut input = io::BufReader::new(datagram);
+        let packet = match code {
+            c if c == ChallengeResponse::code() => DrcomPacket::ChallengeResponse(
+                ChallengeResponse::from_bytes(&mut input).map_err(PacketError::Login)?,
+            ),
+            c if c == LoginResponse::code() && datagram.len() < LOGIN_RESPONSE_MIN_LEN => {
+                DrcomPacket::LogoutResponse(
+                    LogoutResponse::from_bytes(&mut input).map_err(PacketError::Login)?,
+                )
+            }
+            c if c == LoginResponse::code() => DrcomPacket::LoginResponse(
+                LoginResponse::from_bytes(&mut input).map_err(PacketError::Login)?,
+            ),
+            c if c == LoginFailure::code() => DrcomPacket::LoginFailure(
+                LoginFailure::from_bytes(&mut input).map_err(PacketError::Login)?,
+            ),
+            c if c == PhaseTwoResponse::code() => match datagram.get(2..4) {
+                Some(length) if LittleEndian::read_u16(length) == PHASE_TWO_LENGTH => {
+                    DrcomPacket::PhaseTwoResponse(
+                        PhaseTwoResponse::from_bytes(&mut input).map_err(PacketError::Heartbeat)?,
+                    )
+                }
+                Some(length) if LittleEndian::read_u16(length) == KEEP_ALIVE_FILE_LENGTH => {
+                    DrcomPacket::KeepAliveFile {
+                        sequence: datagram[1],
+                    }
+                }
+                _ => DrcomPacket::PhaseOneResponse(
+                    PhaseOneResponse::from_bytes(&mut input).map_err(PacketError::Heartbeat)?,
+                ),
+            },
+            c if c == ServerMessage::code() => DrcomPacket::ServerMessage(
+                ServerMessage::from_bytes(&mut input).map_err(PacketError::Heartbeat)?,
+            ),
+            c => DrcomPacket::Unknown(c),
+        };
+        Ok(packet)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DrcomPacket::ChallengeResponse(_) => "challenge response",
+            DrcomPacket::LoginResponse(_) => "login response",
+            DrcomPacket::LogoutResponse(_) => "logout response",
+            DrcomPacket::LoginFailure(_) => "login failure",
+            DrcomPacket::PhaseOneResponse(_) => "keep_alive1 response",
+            DrcomPacket::PhaseTwoResponse(_) => "keep_alive2 response",
+            DrcomPacket::KeepAliveFile { .. } => "keep_alive2 file",
+            DrcomPacket::ServerMessage(_) => "server message",
+            DrcomPacket::Unknown(_) => "unknown packet",
+        }
+    }
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketError::Empty => write!(f, "empty datagram"),
+            PacketError::Login(e) => write!(f, "{:?}", e),
+            PacketError::Heartbeat(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_STEP_RETRIES: u32 = 2;
+
+/// One request/response exchange of the wired handshake or heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Challenge,
+    Login,
+    KeepAliveOne,
+    /// keep_alive2 type 1 with the first flag, only sent during the handshake.
+    KeepAliveTwoFirst,
+    /// keep_alive2 type 1 with the not-first flag.
+    KeepAliveTwoSecond,
+    /// keep_alive2 type 3.
+    KeepAliveTwoThird,
+}
+
+/// How long to wait for a response and how many times to resend the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+/// Per-step receive timeouts and retransmission counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetransmitPolicy {
+    pub challenge:          StepPolicy,
+    pub login:              StepPolicy,
+    pub keep_alive1:        StepPolicy,
+    pub keep_alive2_first:  StepPolicy,
+    pub keep_alive2_second: StepPolicy,
+    pub keep_alive2_third:  StepPolicy,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RetransmitParseError {
+    UnknownStep(String),
+    MalformedPolicy(String),
+}
+
+impl Step {
+    pub const ALL: [Step; 6] = [
+        Step::Challenge,
+        Step::Login,
+        Step::KeepAliveOne,
+        Step::KeepAliveTwoFirst,
+        Step::KeepAliveTwoSecond,
+        Step::KeepAliveTwoThird,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Step::Challenge => "challenge",
+            Step::Login => "login",
+            Step::KeepAliveOne => "keep-alive1",
+            Step::KeepAliveTwoFirst => "keep-alive2-first",
+            Step::KeepAliveTwoSecond => "keep-alive2-second",
+            Step::KeepAliveTwoThird => "keep-alive2-third",
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Step {
+    type Err = RetransmitParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().replace('_', "-");
+        Step::ALL
+            .iter()
+            .find(|step| step.name() == normalized)
+            .copied()
+            .ok_or_else(|| RetransmitParseError::UnknownStep(s.to_string()))
+    }
+}
+
+impl StepPolicy {
+    pub fn new(timeout: Duration, retries: u32) -> Self {
+        StepPolicy { timeout, retries }
+    }
+}
+
+impl Default for StepPolicy {
+    fn default() -> Self {
+        StepPolicy::new(DEFAULT_STEP_TIMEOUT, DEFAULT_STEP_RETRIES)
+    }
+}
+
+impl FromStr for StepPolicy {
+    type Err = RetransmitParseError;
+
+    /// Parse `<timeout seconds>:<retries>`, e.g. `2.5:3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || RetransmitParseError::MalformedPolicy(s.to_string());
+        let (timeout, retries) = s.split_once(':').ok_or_else(malformed)?;
+        let timeout = timeout
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(malformed)?;
+        let retries = retries.trim().parse::<u32>().map_err(|_| malformed())?;
+        Ok(StepPolicy::new(timeout, retries))
+    }
+}
+
+impl RetransmitPolicy {
+    /// Use the same timeout and retransmission count for every step.
+    pub fn uniform(timeout: Duration, retries: u32) -> Self {
+        let policy = StepPolicy::new(timeout, retries);
+        RetransmitPolicy {
+            challenge: policy,
+            login: policy,
+            keep_alive1: policy,
+            keep_alive2_first: policy,
+            keep_alive2_second: policy,
+            keep_alive2_third: policy,
+        }
+    }
+
+    pub fn step(&self, step: Step) -> StepPolicy {
+        match step {
+            Step::Challenge => self.challenge,
+            Step::Login => self.login,
+            Step::KeepAliveOne => self.keep_alive1,
+            Step::KeepAliveTwoFirst => self.keep_alive2_first,
+            Step::KeepAliveTwoSecond => self.keep_alive2_second,
+            Step::KeepAliveTwoThird => self.keep_alive2_third,
+        }
+    }
+
+    pub fn set_step(&mut self, step: Step, policy: StepPolicy) -> &mut Self {
+        *match step {
+            Step::Challenge => &mut self.challenge,
+            Step::Login => &mut self.login,
+            Step::KeepAliveOne => &mut self.keep_alive1,
+            Step::KeepAliveTwoFirst => &mut self.keep_alive2_first,
+            Step::KeepAliveTwoSecond => &mut self.keep_alive2_second,
+            Step::KeepAliveTwoThird => &mut self.keep_alive2_third,
+        } = policy;
+        self
+    }
+
+    /// Parse a `<step>=<timeout seconds>:<retries>` override, e.g. `login=5:1`.
+    pub fn parse_override(s: &str) -> Result<(Step, StepPolicy), RetransmitParseError> {
+        let (step, policy) = s
+            .split_once('=')
+            .ok_or_else(|| RetransmitParseError::MalformedPolicy(s.to_string()))?;
+        Ok((step.parse()?, policy.parse()?))
+    }
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        RetransmitPolicy::uniform(DEFAULT_STEP_TIMEOUT, DEFAULT_STEP_RETRIES)
+    }
+}
+
+impl fmt::Display for RetransmitParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetransmitParseError::UnknownStep(step) => {
+                let names: Vec<&str> = Step::ALL.iter().map(Step::name).collect();
+                write!(f, "unknown step `{}`, expected one of {}", step, names.join(", "))
+            }
+            RetransmitParseError::MalformedPolicy(policy) => write!(
+                f,
+                "malformed policy `{}`, expected <step>=<timeout seconds>:<retries>",
+                policy
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RetransmitParseError {}
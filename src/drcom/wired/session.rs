@@ -0,0 +1,616 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use crate::common::secret::SecretBytes;
+use crate::drcom::wired::dialer::{
+    ChallengeRequest, ChallengeResponse, LoginAccount, LoginFailure, LoginResponse,
+};
+use crate::drcom::wired::heartbeater::{
+    HeartbeatFlag, PhaseOneRequest, PhaseTwoRequest, ServerMessage,
+};
+use crate::drcom::wired::packet::DrcomPacket;
+use crate::drcom::wired::retransmit::{RetransmitPolicy, Step};
+
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Idle,
+    Challenging,
+    LoggingIn,
+    KeepAliveOne,
+    KeepAliveTwoFirst,
+    KeepAliveTwoSecond,
+    KeepAliveTwoThird,
+    Online,
+    HeartbeatOne,
+    HeartbeatTwoFirst,
+    HeartbeatTwoSecond,
+    LogoutChallenging,
+    LoggingOut,
+    LoggedOut,
+    Failed,
+}
+
+impl SessionState {
+    /// Whether the periodic heartbeat is waiting for a response.
+    pub fn is_heartbeat(&self) -> bool {
+        matches!(
+            *self,
+            SessionState::HeartbeatOne
+                | SessionState::HeartbeatTwoFirst
+                | SessionState::HeartbeatTwoSecond
+        )
+    }
+
+    /// The exchange the session is waiting on, `None` if it is not waiting for a response.
+    pub fn step(&self) -> Option<Step> {
+        match *self {
+            SessionState::Challenging | SessionState::LogoutChallenging => Some(Step::Challenge),
+            SessionState::LoggingIn | SessionState::LoggingOut => Some(Step::Login),
+            SessionState::KeepAliveOne | SessionState::HeartbeatOne => Some(Step::KeepAliveOne),
+            SessionState::KeepAliveTwoFirst => Some(Step::KeepAliveTwoFirst),
+            SessionState::KeepAliveTwoSecond | SessionState::HeartbeatTwoFirst => {
+                Some(Step::KeepAliveTwoSecond)
+            }
+            SessionState::KeepAliveTwoThird | SessionState::HeartbeatTwoSecond => {
+                Some(Step::KeepAliveTwoThird)
+            }
+            SessionState::Idle
+            | SessionState::Online
+            | SessionState::LoggedOut
+            | SessionState::Failed => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionEvent {
+    Challenged { host_ip: Ipv4Addr },
+    LoggedIn(LoginResponse),
+    LoginFailed(LoginFailure),
+    HostIPUnknown,
+    Online { sequence: u8 },
+    HeartbeatCompleted { sequence: u8 },
+    /// A heartbeat round got no response in time, `missed` counts consecutive misses.
+    HeartbeatMissed { missed: u32 },
+    /// A request was sent again after `attempt - 1` went unanswered.
+    Retransmitted { step: Step, attempt: u32 },
+    /// The handshake got no response to `step` after `attempts` tries.
+    ResponseTimeout { step: Step, attempts: u32 },
+    Kicked(ServerMessage),
+    /// The server confirmed the logout, or there was no login to undo.
+    LoggedOut,
+    /// `step` got its response `rtt` after the request, not reported for resent requests.
+    RoundTrip { step: Step, rtt: Duration },
+    PacketDropped { state: SessionState, reason: DropReason },
+}
+
+/// Why `handle_datagram` ignored a datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DropReason {
+    /// Nothing is expected in the current state.
+    Unsolicited,
+    /// Not the kind of response the current step waits for, e.g. a duplicate.
+    UnexpectedPacket(&'static str),
+    /// A keep_alive2 response to some other request, e.g. a late duplicate.
+    UnexpectedSequence { expected: u8, received: u8 },
+    /// The expected kind of response, but it does not parse.
+    Malformed(String),
+    /// A server notice that is not a kick.
+    ServerMessage(u8),
+    /// The response was fine but the next request could not be built from it.
+    InvalidRequest(String),
+}
+
+impl DropReason {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DropReason::Unsolicited => "unsolicited",
+            DropReason::UnexpectedPacket(_) => "unexpected_packet",
+            DropReason::UnexpectedSequence { .. } => "unexpected_sequence",
+            DropReason::Malformed(_) => "malformed",
+            DropReason::ServerMessage(_) => "server_message",
+            DropReason::InvalidRequest(_) => "invalid_request",
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DropReason::Unsolicited => write!(f, "no response expected"),
+            DropReason::UnexpectedPacket(packet) => write!(f, "unexpected {}", packet),
+            DropReason::UnexpectedSequence { expected, received } => {
+                write!(f, "sequence {} instead of {}", received, expected)
+            }
+            DropReason::Malformed(reason) => write!(f, "malformed: {}", reason),
+            DropReason::ServerMessage(kind) => write!(f, "server message {:#x}", kind),
+            DropReason::InvalidRequest(reason) => write!(f, "cannot build request: {}", reason),
+        }
+    }
+}
+
+/// Sans-IO driver of the wired challenge, login and keep-alive sequence.
+///
+/// Feed it received datagrams and timer ticks, then drain the datagrams to
+/// send with `poll_transmit` and what happened with `poll_event`.
+#[derive(Debug)]
+pub struct WiredSession {
+    account:            LoginAccount,
+    host_ip_override:   Option<Ipv4Addr>,
+    /// The echoed address put into an account without any, kept up to date from then on.
+    filled_ipaddress:   Option<Ipv4Addr>,
+    heartbeat_interval: Duration,
+    retransmit_policy:  RetransmitPolicy,
+    state:              SessionState,
+    hash_salt:          [u8; 4],
+    host_ip:            Ipv4Addr,
+    password_hash:      SecretBytes<16>,
+    auth_tail:          SecretBytes<16>,
+    keep_alive_key:     SecretBytes<4>,
+    logged_in:          bool,
+    sequence:           u8,
+    next_heartbeat_at:  Option<Instant>,
+    last_heartbeat_at:  Option<Instant>,
+    heartbeat_rtt:      Option<Duration>,
+    online_since:       Option<Instant>,
+    round_started_at:   Option<Instant>,
+    response_deadline:  Option<Instant>,
+    transmitted_at:     Option<Instant>,
+    last_transmit:      Vec<u8>,
+    retransmits:        u32,
+    missed_heartbeats:  u32,
+    transmits:          VecDeque<Vec<u8>>,
+    events:             VecDeque<SessionEvent>,
+}
+
+impl WiredSession {
+    pub fn new(account: LoginAccount) -> Self {
+        WiredSession {
+            account,
+            host_ip_override: None,
+            filled_ipaddress: None,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            retransmit_policy: RetransmitPolicy::default(),
+            state: SessionState::Idle,
+            hash_salt: [0u8; 4],
+            host_ip: Ipv4Addr::UNSPECIFIED,
+            password_hash: SecretBytes::default(),
+            auth_tail: SecretBytes::default(),
+            keep_alive_key: SecretBytes::default(),
+            logged_in: false,
+            sequence: 0,
+            next_heartbeat_at: None,
+            last_heartbeat_at: None,
+            heartbeat_rtt: None,
+            online_since: None,
+            round_started_at: None,
+            response_deadline: None,
+            transmitted_at: None,
+            last_transmit: vec![],
+            retransmits: 0,
+            missed_heartbeats: 0,
+            transmits: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Use `host_ip` instead of the address echoed in the challenge response.
+    pub fn host_ip_override(&mut self, value: Option<Ipv4Addr>) -> &mut Self {
+        self.host_ip_override = value;
+        self
+    }
+
+    pub fn heartbeat_interval(&mut self, value: Duration) -> &mut Self {
+        self.heartbeat_interval = value;
+        self
+    }
+
+    pub fn retransmit_policy(&mut self, value: RetransmitPolicy) -> &mut Self {
+        self.retransmit_policy = value;
+        self
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    pub fn sequence(&self) -> u8 {
+        self.sequence
+    }
+
+    pub fn host_ip(&self) -> Ipv4Addr {
+        self.host_ip
+    }
+
+    pub fn account(&self) -> &LoginAccount {
+        &self.account
+    }
+
+    /// Changes take effect from the next login.
+    pub fn account_mut(&mut self) -> &mut LoginAccount {
+        &mut self.account
+    }
+
+    /// When the handshake or the latest heartbeat round got its last response.
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.last_heartbeat_at
+    }
+
+    /// How long the latest heartbeat round took, from keep_alive1 to the last keep_alive2 response.
+    pub fn heartbeat_rtt(&self) -> Option<Duration> {
+        self.heartbeat_rtt
+    }
+
+    /// When the handshake completed, `None` before that.
+    pub fn online_since(&self) -> Option<Instant> {
+        self.online_since
+    }
+
+    /// Begin a new session with a challenge, dropping any previous progress.
+    pub fn start(&mut self, now: Instant) {
+        self.sequence = 0;
+        self.keep_alive_key = SecretBytes::default();
+        self.logged_in = false;
+        self.next_heartbeat_at = None;
+        self.last_heartbeat_at = None;
+        self.heartbeat_rtt = None;
+        self.online_since = None;
+        self.missed_heartbeats = 0;
+        self.transmits.clear();
+        self.transmit(
+            now,
+            ChallengeRequest::new(None).as_bytes(),
+            SessionState::Challenging,
+        );
+    }
+
+    /// Tell the server we are leaving, which takes a fresh challenge for the salt.
+    ///
+    /// Ends in `LoggedOut` right away if the server does not know about us.
+    pub fn logout(&mut self, now: Instant) {
+        self.next_heartbeat_at = None;
+        self.transmits.clear();
+        if !self.logged_in {
+            self.state = SessionState::LoggedOut;
+            self.response_deadline = None;
+            self.events.push_back(SessionEvent::LoggedOut);
+            return;
+        }
+        self.transmit(
+            now,
+            ChallengeRequest::new(None).as_bytes(),
+            SessionState::LogoutChallenging,
+        );
+    }
+
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmits.pop_front()
+    }
+
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+
+    /// When `handle_tick` has to be called next, `None` if there is nothing to wait for.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.state {
+            SessionState::Online => self.next_heartbeat_at,
+            s if s.step().is_some() => self.response_deadline,
+            _ => None,
+        }
+    }
+
+    pub fn handle_tick(&mut self, now: Instant) {
+        if self.state == SessionState::Online {
+            if self.next_heartbeat_at.is_none_or(|t| now >= t) {
+                self.next_heartbeat_at = None;
+                self.round_started_at = Some(now);
+                self.send_keep_alive1(now, SessionState::HeartbeatOne);
+            }
+            return;
+        }
+
+        let step = match self.state.step() {
+            Some(step) if self.response_deadline.is_some_and(|t| now >= t) => step,
+            _ => return,
+        };
+        let policy = self.retransmit_policy.step(step);
+        if self.retransmits < policy.retries {
+            self.retransmits += 1;
+            self.transmits.push_back(self.last_transmit.clone());
+            self.response_deadline = Some(now + policy.timeout);
+            self.events.push_back(SessionEvent::Retransmitted {
+                step,
+                attempt: self.retransmits + 1,
+            });
+            return;
+        }
+
+        self.response_deadline = None;
+        if self.state.is_heartbeat() {
+            // retry the whole round right away
+            self.missed_heartbeats += 1;
+            self.state = SessionState::Online;
+            self.next_heartbeat_at = Some(now);
+            self.events.push_back(SessionEvent::HeartbeatMissed {
+                missed: self.missed_heartbeats,
+            });
+        } else {
+            self.state = SessionState::Failed;
+            self.events.push_back(SessionEvent::ResponseTimeout {
+                step,
+                attempts: self.retransmits + 1,
+            });
+        }
+    }
+
+    pub fn handle_datagram(&mut self, now: Instant, datagram: &[u8]) {
+        let packet = match DrcomPacket::decode(datagram) {
+            Ok(packet) => packet,
+            Err(e) => return self.drop_packet(DropReason::Malformed(e.to_string())),
+        };
+
+        match (self.state, packet) {
+            (state, DrcomPacket::ServerMessage(message)) => {
+                if message.is_kick() && state != SessionState::Idle {
+                    self.state = SessionState::Failed;
+                    self.logged_in = false;
+                    self.response_deadline = None;
+                    self.events.push_back(SessionEvent::Kicked(message));
+                } else {
+                    self.drop_packet(DropReason::ServerMessage(message.kind))
+                }
+            }
+            (SessionState::Challenging, DrcomPacket::ChallengeResponse(response)) => {
+                self.record_round_trip(now);
+                self.on_challenge(now, response)
+            }
+            (SessionState::LoggingIn, DrcomPacket::LoginResponse(response)) => {
+                self.record_round_trip(now);
+                self.auth_tail = response.keep_alive_key.clone();
+                self.logged_in = true;
+                self.events.push_back(SessionEvent::LoggedIn(response));
+                self.send_keep_alive1(now, SessionState::KeepAliveOne);
+            }
+            (SessionState::LoggingIn, DrcomPacket::LoginFailure(failure)) => {
+                self.record_round_trip(now);
+                self.state = SessionState::Failed;
+                self.events.push_back(SessionEvent::LoginFailed(failure));
+            }
+            // only the code of a keep_alive1 response is reliable
+            (
+                SessionState::KeepAliveOne | SessionState::HeartbeatOne,
+                DrcomPacket::PhaseOneResponse(_)
+                | DrcomPacket::PhaseTwoResponse(_)
+                | DrcomPacket::KeepAliveFile { .. },
+            ) => {
+                self.record_round_trip(now);
+                let (next_state, flag) = match self.state {
+                    SessionState::KeepAliveOne => {
+                        (SessionState::KeepAliveTwoFirst, HeartbeatFlag::First)
+                    }
+                    _ => (SessionState::HeartbeatTwoFirst, HeartbeatFlag::NotFirst),
+                };
+                self.send_keep_alive2(now, &flag, 1, next_state);
+            }
+            (SessionState::KeepAliveTwoFirst, DrcomPacket::PhaseTwoResponse(response))
+                if response.sequence == 0 || response.sequence == self.sequence =>
+            {
+                self.record_round_trip(now);
+                self.send_keep_alive2(
+                    now,
+                    &HeartbeatFlag::NotFirst,
+                    1,
+                    SessionState::KeepAliveTwoSecond,
+                );
+            }
+            (SessionState::KeepAliveTwoFirst, DrcomPacket::KeepAliveFile { .. }) => {
+                // the file stands in for the first response, go on with the next sequence
+                self.record_round_trip(now);
+                self.sequence = self.sequence.wrapping_add(1);
+                self.send_keep_alive2(
+                    now,
+                    &HeartbeatFlag::NotFirst,
+                    1,
+                    SessionState::KeepAliveTwoSecond,
+                );
+            }
+            (
+                SessionState::KeepAliveTwoFirst
+                | SessionState::KeepAliveTwoSecond
+                | SessionState::KeepAliveTwoThird
+                | SessionState::HeartbeatTwoFirst
+                | SessionState::HeartbeatTwoSecond,
+                DrcomPacket::PhaseTwoResponse(response),
+            ) if response.sequence != self.sequence => self.unexpected_sequence(response.sequence),
+            (SessionState::KeepAliveTwoSecond, DrcomPacket::PhaseTwoResponse(response)) => {
+                self.record_round_trip(now);
+                self.sequence = self.sequence.wrapping_add(1);
+                self.keep_alive_key = response.keep_alive_key;
+                self.send_keep_alive2(
+                    now,
+                    &HeartbeatFlag::NotFirst,
+                    3,
+                    SessionState::KeepAliveTwoThird,
+                );
+            }
+            (SessionState::KeepAliveTwoThird, DrcomPacket::PhaseTwoResponse(response)) => {
+                self.record_round_trip(now);
+                self.sequence = self.sequence.wrapping_add(1);
+                self.keep_alive_key = response.keep_alive_key;
+                self.online_since = Some(now);
+                self.go_online(now);
+                self.events.push_back(SessionEvent::Online {
+                    sequence: self.sequence,
+                });
+            }
+            (SessionState::HeartbeatTwoFirst, DrcomPacket::PhaseTwoResponse(response)) => {
+                self.record_round_trip(now);
+                self.keep_alive_key = response.keep_alive_key;
+                self.sequence = self.sequence.wrapping_add(1);
+                self.send_keep_alive2(
+                    now,
+                    &HeartbeatFlag::NotFirst,
+                    3,
+                    SessionState::HeartbeatTwoSecond,
+                );
+            }
+            (SessionState::HeartbeatTwoSecond, DrcomPacket::PhaseTwoResponse(response)) => {
+                self.record_round_trip(now);
+                self.keep_alive_key = response.keep_alive_key;
+                self.sequence = self.sequence.wrapping_add(1) % 0x7F;
+                self.missed_heartbeats = 0;
+                self.heartbeat_rtt =
+                    self.round_started_at.map(|t| now.saturating_duration_since(t));
+                self.go_online(now);
+                self.events.push_back(SessionEvent::HeartbeatCompleted {
+                    sequence: self.sequence,
+                });
+            }
+            (SessionState::LogoutChallenging, DrcomPacket::ChallengeResponse(response)) => {
+                self.record_round_trip(now);
+                self.on_logout_challenge(now, response)
+            }
+            (
+                SessionState::LoggingOut,
+                DrcomPacket::LogoutResponse(_) | DrcomPacket::LoginResponse(_),
+            ) => {
+                self.record_round_trip(now);
+                self.state = SessionState::LoggedOut;
+                self.logged_in = false;
+                self.response_deadline = None;
+                self.events.push_back(SessionEvent::LoggedOut);
+            }
+            (
+                SessionState::Idle
+                | SessionState::Online
+                | SessionState::LoggedOut
+                | SessionState::Failed,
+                _,
+            ) => self.drop_packet(DropReason::Unsolicited),
+            (_, packet) => self.drop_packet(DropReason::UnexpectedPacket(packet.name())),
+        }
+    }
+
+    fn on_challenge(&mut self, now: Instant, response: ChallengeResponse) {
+        let host_ip = match self.host_ip_override.or(response.client_ip) {
+            Some(host_ip) => host_ip,
+            None => {
+                self.state = SessionState::Failed;
+                self.events.push_back(SessionEvent::HostIPUnknown);
+                return;
+            }
+        };
+        self.hash_salt = response.hash_salt;
+        self.host_ip = host_ip;
+        self.events.push_back(SessionEvent::Challenged { host_ip });
+
+        self.account.hash_salt(self.hash_salt);
+        self.password_hash = self.account.password_md5_hash();
+        // DHCP may hand out another address between logins, configured ones stay
+        let filled_in = self
+            .filled_ipaddress
+            .is_some_and(|ip| self.account.has_only_ipaddress(ip));
+        if filled_in || !self.account.has_ipaddresses() {
+            self.account.ipaddresses(&[host_ip]);
+            self.filled_ipaddress = Some(host_ip);
+        }
+        match self
+            .account
+            .login_request()
+            .and_then(|request| request.as_bytes())
+        {
+            Ok(packet) => self.transmit(now, packet, SessionState::LoggingIn),
+            Err(e) => {
+                self.state = SessionState::Failed;
+                self.drop_packet(DropReason::InvalidRequest(format!("login: {:?}", e)));
+            }
+        }
+    }
+
+    fn on_logout_challenge(&mut self, now: Instant, response: ChallengeResponse) {
+        match self
+            .account
+            .logout_request(response.hash_salt, &self.auth_tail)
+            .and_then(|request| request.as_bytes())
+        {
+            Ok(packet) => self.transmit(now, packet, SessionState::LoggingOut),
+            Err(e) => {
+                self.state = SessionState::Failed;
+                self.drop_packet(DropReason::InvalidRequest(format!("logout: {:?}", e)));
+            }
+        }
+    }
+
+    fn go_online(&mut self, now: Instant) {
+        self.state = SessionState::Online;
+        self.response_deadline = None;
+        self.last_heartbeat_at = Some(now);
+        self.next_heartbeat_at = Some(now + self.heartbeat_interval);
+    }
+
+    fn send_keep_alive1(&mut self, now: Instant, next_state: SessionState) {
+        let request = PhaseOneRequest::new(&self.password_hash, &self.auth_tail, None);
+        self.transmit(now, request.as_bytes(), next_state);
+    }
+
+    fn send_keep_alive2(
+        &mut self,
+        now: Instant,
+        flag: &HeartbeatFlag,
+        type_id: u8,
+        next_state: SessionState,
+    ) {
+        let request = PhaseTwoRequest::new(
+            self.sequence,
+            &self.keep_alive_key,
+            flag,
+            self.host_ip,
+            Some(type_id),
+        );
+        self.transmit(now, request.as_bytes(), next_state);
+    }
+
+    fn transmit(&mut self, now: Instant, packet: Vec<u8>, next_state: SessionState) {
+        let timeout = next_state
+            .step()
+            .map_or(Duration::ZERO, |step| self.retransmit_policy.step(step).timeout);
+        self.transmits.push_back(packet.clone());
+        self.last_transmit = packet;
+        self.transmitted_at = Some(now);
+        self.retransmits = 0;
+        self.state = next_state;
+        self.response_deadline = Some(now + timeout);
+    }
+
+    /// Report how long the current step took, unless the request had to be resent
+    /// and it is unclear which copy got answered.
+    fn record_round_trip(&mut self, now: Instant) {
+        if let (Some(step), Some(at), 0) =
+            (self.state.step(), self.transmitted_at, self.retransmits)
+        {
+            self.events.push_back(SessionEvent::RoundTrip {
+                step,
+                rtt: now.saturating_duration_since(at),
+            });
+        }
+    }
+
+    fn unexpected_sequence(&mut self, received: u8) {
+        self.drop_packet(DropReason::UnexpectedSequence {
+            expected: self.sequence,
+            received,
+        })
+    }
+
+    fn drop_packet(&mut self, reason: DropReason) {
+        self.events.push_back(SessionEvent::PacketDropped {
+            state: self.state,
+            reason,
+        });
+    }
+}
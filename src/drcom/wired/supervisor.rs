@@ -0,0 +1,337 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::drcom::wired::dialer::LoginAccount;
+use crate::drcom::wired::session::{DropReason, SessionEvent, SessionState, WiredSession};
+
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisorState {
+    Idle,
+    Connecting,
+    Online,
+    Backoff { until: Instant },
+    /// Heartbeats paused until the network link is back.
+    LinkDown,
+    /// Logging out before stopping.
+    Stopping,
+    Stopped,
+}
+
+impl SupervisorState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SupervisorState::Idle => "idle",
+            SupervisorState::Connecting => "connecting",
+            SupervisorState::Online => "online",
+            SupervisorState::Backoff { .. } => "backoff",
+            SupervisorState::LinkDown => "link_down",
+            SupervisorState::Stopping => "stopping",
+            SupervisorState::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloginReason {
+    HeartbeatMissed,
+    ValidationError,
+    Kicked,
+    LoginFailed,
+    Timeout,
+    /// The heartbeat still works but traffic does not get through.
+    ConnectivityLost,
+    /// The network link came back after being down.
+    LinkRestored,
+    /// Our IPv4 address changed, e.g. after a DHCP renew.
+    AddressChanged,
+}
+
+impl ReloginReason {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ReloginReason::HeartbeatMissed => "heartbeat_missed",
+            ReloginReason::ValidationError => "validation_error",
+            ReloginReason::Kicked => "kicked",
+            ReloginReason::LoginFailed => "login_failed",
+            ReloginReason::Timeout => "timeout",
+            ReloginReason::ConnectivityLost => "connectivity_lost",
+            ReloginReason::LinkRestored => "link_restored",
+            ReloginReason::AddressChanged => "address_changed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SupervisorEvent {
+    Session(SessionEvent),
+    StateChanged {
+        from: SupervisorState,
+        to:   SupervisorState,
+    },
+    ReloginScheduled {
+        attempt: u32,
+        delay:   Duration,
+        reason:  ReloginReason,
+    },
+}
+
+/// Keeps a `WiredSession` online, redoing challenge and login with an
+/// exponential backoff whenever the heartbeat breaks down.
+///
+/// Like the session it wraps, the supervisor does no IO by itself and is
+/// driven through `handle_datagram`, `handle_tick` and the `poll_*` methods.
+#[derive(Debug)]
+pub struct SessionSupervisor {
+    session:               WiredSession,
+    initial_backoff:       Duration,
+    max_backoff:           Duration,
+    max_missed_heartbeats: u32,
+    state:                 SupervisorState,
+    attempts:              u32,
+    events:                VecDeque<SupervisorEvent>,
+}
+
+impl SessionSupervisor {
+    pub fn new(session: WiredSession) -> Self {
+        SessionSupervisor {
+            session,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_missed_heartbeats: DEFAULT_MAX_MISSED_HEARTBEATS,
+            state: SupervisorState::Idle,
+            attempts: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn initial_backoff(&mut self, value: Duration) -> &mut Self {
+        self.initial_backoff = value;
+        self
+    }
+
+    pub fn max_backoff(&mut self, value: Duration) -> &mut Self {
+        self.max_backoff = value;
+        self
+    }
+
+    pub fn max_missed_heartbeats(&mut self, value: u32) -> &mut Self {
+        self.max_missed_heartbeats = value.max(1);
+        self
+    }
+
+    pub fn state(&self) -> SupervisorState {
+        self.state
+    }
+
+    pub fn session(&self) -> &WiredSession {
+        &self.session
+    }
+
+    /// The account used by the next login, e.g. to refresh its addresses.
+    pub fn account_mut(&mut self) -> &mut LoginAccount {
+        self.session.account_mut()
+    }
+
+    pub fn start(&mut self, now: Instant) {
+        self.attempts = 0;
+        self.connect(now);
+    }
+
+    /// Log out and stop, the supervisor ends up `Stopped` once the server
+    /// confirmed or the logout timed out.
+    pub fn shutdown(&mut self, now: Instant) {
+        if let SupervisorState::Stopping | SupervisorState::Stopped = self.state {
+            return;
+        }
+        self.set_state(SupervisorState::Stopping);
+        self.session.logout(now);
+        self.pump_session_events(now);
+    }
+
+    /// Log in again although the heartbeat looks fine, e.g. when a
+    /// connectivity check ends up at the captive portal. Only acts when online.
+    pub fn connectivity_lost(&mut self, now: Instant) {
+        if self.state != SupervisorState::Online {
+            return;
+        }
+        self.schedule_relogin(now, ReloginReason::ConnectivityLost);
+        self.discard_session_output();
+    }
+
+    /// Stop talking to the server until `link_up`, whatever the session was doing.
+    pub fn link_down(&mut self) {
+        if self.is_running() {
+            self.discard_session_output();
+            self.set_state(SupervisorState::LinkDown);
+        }
+    }
+
+    /// Redo challenge and login right away if the link was down, the server
+    /// may well have dropped us in the meantime.
+    pub fn link_up(&mut self, now: Instant) {
+        if self.state == SupervisorState::LinkDown {
+            self.relogin_now(now, ReloginReason::LinkRestored);
+        }
+    }
+
+    /// Redo challenge and login right away, the server only accepts
+    /// heartbeats from the address we logged in with.
+    pub fn address_changed(&mut self, now: Instant) {
+        if self.is_running() {
+            self.relogin_now(now, ReloginReason::AddressChanged);
+        }
+    }
+
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.session.poll_transmit()
+    }
+
+    pub fn poll_event(&mut self) -> Option<SupervisorEvent> {
+        self.events.pop_front()
+    }
+
+    /// When `handle_tick` has to be called next, `None` if there is nothing to wait for.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.state {
+            SupervisorState::Connecting | SupervisorState::Online | SupervisorState::Stopping => {
+                self.session.poll_timeout()
+            }
+            SupervisorState::Backoff { until } => Some(until),
+            SupervisorState::Idle | SupervisorState::LinkDown | SupervisorState::Stopped => None,
+        }
+    }
+
+    pub fn handle_tick(&mut self, now: Instant) {
+        match self.state {
+            SupervisorState::Connecting | SupervisorState::Online | SupervisorState::Stopping => {
+                self.session.handle_tick(now);
+                self.pump_session_events(now);
+            }
+            SupervisorState::Backoff { until } if now >= until => self.connect(now),
+            _ => {}
+        }
+    }
+
+    pub fn handle_datagram(&mut self, now: Instant, datagram: &[u8]) {
+        if let SupervisorState::Connecting | SupervisorState::Online | SupervisorState::Stopping =
+            self.state
+        {
+            self.session.handle_datagram(now, datagram);
+            self.pump_session_events(now);
+        }
+    }
+
+    /// Started and neither paused nor on the way out.
+    fn is_running(&self) -> bool {
+        matches!(
+            self.state,
+            SupervisorState::Connecting | SupervisorState::Online | SupervisorState::Backoff { .. }
+        )
+    }
+
+    fn connect(&mut self, now: Instant) {
+        self.session.start(now);
+        self.set_state(SupervisorState::Connecting);
+    }
+
+    fn pump_session_events(&mut self, now: Instant) {
+        while let Some(event) = self.session.poll_event() {
+            if self.state == SupervisorState::Stopping {
+                self.events.push_back(SupervisorEvent::Session(event));
+                continue;
+            }
+
+            let reaction = match event {
+                SessionEvent::Online { .. } => {
+                    self.attempts = 0;
+                    Some(Ok(SupervisorState::Online))
+                }
+                SessionEvent::HeartbeatMissed { missed } if missed >= self.max_missed_heartbeats => {
+                    Some(Err(ReloginReason::HeartbeatMissed))
+                }
+                // stray and stale packets are ignored, a garbled answer is not
+                SessionEvent::PacketDropped {
+                    state,
+                    reason: DropReason::Malformed(_),
+                } if state.is_heartbeat() => Some(Err(ReloginReason::ValidationError)),
+                SessionEvent::ResponseTimeout { .. } => Some(Err(ReloginReason::Timeout)),
+                SessionEvent::Kicked(_) => Some(Err(ReloginReason::Kicked)),
+                SessionEvent::LoginFailed(ref failure) if failure.is_retryable() => {
+                    Some(Err(ReloginReason::LoginFailed))
+                }
+                SessionEvent::LoginFailed(_) | SessionEvent::HostIPUnknown => {
+                    Some(Ok(SupervisorState::Stopped))
+                }
+                _ => None,
+            };
+            self.events.push_back(SupervisorEvent::Session(event));
+
+            match reaction {
+                Some(Ok(state)) => self.set_state(state),
+                Some(Err(reason)) => {
+                    self.schedule_relogin(now, reason);
+                    self.discard_session_output();
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        // a logout that failed or timed out is as good as it gets
+        if self.state == SupervisorState::Stopping
+            && matches!(
+                self.session.state(),
+                SessionState::LoggedOut | SessionState::Failed
+            )
+        {
+            self.set_state(SupervisorState::Stopped);
+        }
+    }
+
+    /// Whatever the old session still had to say is moot once we give up on it.
+    fn discard_session_output(&mut self) {
+        while self.session.poll_event().is_some() {}
+        while self.session.poll_transmit().is_some() {}
+    }
+
+    /// Start over without waiting, the network changed under us so this
+    /// is no repeated failure and the backoff starts from scratch.
+    fn relogin_now(&mut self, now: Instant, reason: ReloginReason) {
+        self.discard_session_output();
+        self.attempts = 0;
+        self.events.push_back(SupervisorEvent::ReloginScheduled {
+            attempt: 1,
+            delay: Duration::ZERO,
+            reason,
+        });
+        self.connect(now);
+    }
+
+    fn schedule_relogin(&mut self, now: Instant, reason: ReloginReason) {
+        self.attempts = self.attempts.saturating_add(1);
+        let delay = self
+            .initial_backoff
+            .checked_mul(1 << (self.attempts - 1).min(16))
+            .map_or(self.max_backoff, |d| d.min(self.max_backoff));
+
+        self.events.push_back(SupervisorEvent::ReloginScheduled {
+            attempt: self.attempts,
+            delay,
+            reason,
+        });
+        self.set_state(SupervisorState::Backoff { until: now + delay });
+    }
+
+    fn set_state(&mut self, to: SupervisorState) {
+        if self.state == to {
+            return;
+        }
+        let from = self.state;
+        self.state = to;
+        self.events.push_back(SupervisorEvent::StateChanged { from, to });
+    }
+}
@@ -0,0 +1,445 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::drcom::wired::session::SessionEvent;
+use crate::drcom::wired::supervisor::{SupervisorEvent, SupervisorState};
+
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a running hook is checked for having exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Transitions that hook commands can be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Online,
+    Offline,
+    LoginFailed,
+    Kicked,
+    Relogin,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HookParseError {
+    UnknownEvent(String),
+    MalformedHook(String),
+}
+
+/// A hook event that happened, with the `DRCOM_*` variables describing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    pub event: HookEvent,
+    pub env:   Vec<(&'static str, String)>,
+}
+
+/// Runs shell commands on supervisor transitions, each through `/bin/sh -c`
+/// in its own process group so that a timeout takes down its children too.
+///
+/// Hooks run in the background and their output ends up in the log, one
+/// record per line.
+#[derive(Debug)]
+pub struct Hooks {
+    commands:     Vec<(HookEvent, String)>,
+    timeout:      Duration,
+    online_since: Option<Instant>,
+    running:      Vec<JoinHandle<()>>,
+}
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 5] = [
+        HookEvent::Online,
+        HookEvent::Offline,
+        HookEvent::LoginFailed,
+        HookEvent::Kicked,
+        HookEvent::Relogin,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HookEvent::Online => "online",
+            HookEvent::Offline => "offline",
+            HookEvent::LoginFailed => "login-failed",
+            HookEvent::Kicked => "kicked",
+            HookEvent::Relogin => "relogin",
+        }
+    }
+
+    /// Parse a command line `<event>=<command>`, e.g. `online=systemctl restart wg-quick@wg0`.
+    pub fn parse_hook(s: &str) -> Result<(HookEvent, String), HookParseError> {
+        let (event, command) = s
+            .split_once('=')
+            .filter(|(_, command)| !command.trim().is_empty())
+            .ok_or_else(|| HookParseError::MalformedHook(s.to_string()))?;
+        Ok((event.parse()?, command.to_string()))
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HookEvent {
+    type Err = HookParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().replace('_', "-");
+        HookEvent::ALL
+            .iter()
+            .find(|event| event.name() == normalized)
+            .copied()
+            .ok_or_else(|| HookParseError::UnknownEvent(s.to_string()))
+    }
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            commands:     Vec::new(),
+            timeout:      DEFAULT_HOOK_TIMEOUT,
+            online_since: None,
+            running:      Vec::new(),
+        }
+    }
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the configured commands, an event may have several.
+    pub fn commands(&mut self, value: Vec<(HookEvent, String)>) -> &mut Self {
+        self.commands = value;
+        self
+    }
+
+    /// How long a hook may run before its process group is killed.
+    pub fn timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeout = value;
+        self
+    }
+
+    /// The hook `event` triggers, `None` if it triggers none or nothing is attached to it.
+    pub fn trigger(&mut self, event: &SupervisorEvent, now: Instant) -> Option<Trigger> {
+        let mut env = Vec::new();
+        if let Some(since) = self.online_since {
+            let session = now.saturating_duration_since(since).as_secs();
+            env.push(("DRCOM_SESSION_SECS", session.to_string()));
+        }
+
+        let hook_event = match event {
+            SupervisorEvent::StateChanged { from, to } if *to == SupervisorState::Online => {
+                env.push(("DRCOM_PREVIOUS_STATE", from.name().to_string()));
+                self.online_since = Some(now);
+                HookEvent::Online
+            }
+            SupervisorEvent::StateChanged {
+                from: SupervisorState::Online,
+                to,
+            } => {
+                env.push(("DRCOM_STATE", to.name().to_string()));
+                self.online_since = None;
+                HookEvent::Offline
+            }
+            SupervisorEvent::Session(SessionEvent::LoginFailed(failure)) => {
+                env.push(("DRCOM_FAILURE", failure.name().to_string()));
+                env.push(("DRCOM_FAILURE_CODE", format!("{:#04x}", failure.error_code())));
+                HookEvent::LoginFailed
+            }
+            SupervisorEvent::Session(SessionEvent::HostIPUnknown) => {
+                env.push(("DRCOM_FAILURE", String::from("host_ip_unknown")));
+                HookEvent::LoginFailed
+            }
+            SupervisorEvent::Session(SessionEvent::Kicked(message)) => {
+                env.push(("DRCOM_KICK_KIND", format!("{:#04x}", message.kind)));
+                HookEvent::Kicked
+            }
+            SupervisorEvent::ReloginScheduled {
+                attempt,
+                delay,
+                reason,
+            } => {
+                env.push(("DRCOM_RELOGIN_REASON", reason.name().to_string()));
+                env.push(("DRCOM_RELOGIN_ATTEMPT", attempt.to_string()));
+                env.push(("DRCOM_RELOGIN_DELAY_SECS", delay.as_secs().to_string()));
+                HookEvent::Relogin
+            }
+            _ => return None,
+        };
+
+        self.commands
+            .iter()
+            .any(|(event, _)| *event == hook_event)
+            .then_some(Trigger {
+                event: hook_event,
+                env,
+            })
+    }
+
+    /// Start every command attached to the triggered event, never waits for them.
+    pub fn run(&mut self, trigger: &Trigger) {
+        self.running.retain(|handle| !handle.is_finished());
+        for (_, command) in self.commands.iter().filter(|(e, _)| *e == trigger.event) {
+            match spawn(command, trigger) {
+                Ok(child) => {
+                    let (event, timeout) = (trigger.event, self.timeout);
+                    self.running
+                        .push(thread::spawn(move || supervise(child, event, timeout)));
+                }
+                Err(e) => warn!(hook:% = trigger.event, error:% = e; "cannot run hook"),
+            }
+        }
+    }
+
+    /// Wait for the hooks still running, e.g. the `offline` one before exiting.
+    pub fn wait(&mut self) {
+        for handle in self.running.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn spawn(command: &str, trigger: &Trigger) -> std::io::Result<Child> {
+    Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .env("DRCOM_EVENT", trigger.event.name())
+        .envs(trigger.env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+}
+
+/// Log the output of `child` and reap it, killing it once `timeout` passed.
+fn supervise(mut child: Child, event: HookEvent, timeout: Duration) {
+    let readers: Vec<JoinHandle<()>> = [
+        child.stdout.take().map(|out| log_lines(out, event, false)),
+        child.stderr.take().map(|err| log_lines(err, event, true)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) if Instant::now() >= deadline => {
+                warn!(hook:% = event, timeout_secs = timeout.as_secs_f64(); "hook timed out");
+                // the shell is the leader of the group, this reaches whatever it started
+                unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+                break child.wait();
+            }
+            Ok(None) => thread::sleep(WAIT_INTERVAL),
+            Err(e) => break Err(e),
+        }
+    };
+    // a child left in the background, e.g. with `setsid`, may hold on to the pipes for good,
+    // readers still blocked past the deadline are left to log whatever it writes
+    let reader_deadline = deadline.max(Instant::now() + WAIT_INTERVAL);
+    while readers.iter().any(|reader| !reader.is_finished()) && Instant::now() < reader_deadline {
+        thread::sleep(WAIT_INTERVAL);
+    }
+    for reader in readers.into_iter().filter(JoinHandle::is_finished) {
+        let _ = reader.join();
+    }
+
+    match status {
+        Ok(status) if status.success() => debug!(hook:% = event; "hook finished"),
+        Ok(status) => warn!(hook:% = event, status:% = status; "hook failed"),
+        Err(e) => warn!(hook:% = event, error:% = e; "cannot wait for hook"),
+    }
+}
+
+fn log_lines<R: Read + Send + 'static>(
+    output: R,
+    event: HookEvent,
+    stderr: bool,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            match stderr {
+                false => info!(hook:% = event; "{}", line),
+                true => warn!(hook:% = event; "{}", line),
+            }
+        }
+    })
+}
+
+impl fmt::Display for HookParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HookParseError::UnknownEvent(event) => {
+                let names: Vec<&str> = HookEvent::ALL.iter().map(HookEvent::name).collect();
+                write!(f, "unknown hook event `{}`, expected one of {}", event, names.join(", "))
+            }
+            HookParseError::MalformedHook(hook) => {
+                write!(f, "malformed hook `{}`, expected <event>=<command>", hook)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HookParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drcom::wired::dialer::LoginFailure;
+    use crate::drcom::wired::supervisor::ReloginReason;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn test_hook_triggers() {
+        let mut hooks = Hooks::new();
+        hooks.commands(
+            HookEvent::ALL
+                .iter()
+                .map(|event| (*event, String::from("true")))
+                .collect(),
+        );
+        let start = Instant::now();
+
+        let online = hooks
+            .trigger(
+                &SupervisorEvent::StateChanged {
+                    from: SupervisorState::Connecting,
+                    to:   SupervisorState::Online,
+                },
+                start,
+            )
+            .unwrap();
+        assert_eq!(online.event, HookEvent::Online);
+        assert_eq!(online.env, vec![("DRCOM_PREVIOUS_STATE", String::from("connecting"))]);
+
+        let failed = hooks.trigger(
+            &SupervisorEvent::Session(SessionEvent::LoginFailed(LoginFailure::WrongPassword)),
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(
+            failed.unwrap().env,
+            vec![
+                ("DRCOM_SESSION_SECS", String::from("1")),
+                ("DRCOM_FAILURE", String::from("wrong_password")),
+                ("DRCOM_FAILURE_CODE", String::from("0x03")),
+            ]
+        );
+
+        let offline = hooks
+            .trigger(
+                &SupervisorEvent::StateChanged {
+                    from: SupervisorState::Online,
+                    to:   SupervisorState::Stopping,
+                },
+                start + Duration::from_secs(90),
+            )
+            .unwrap();
+        assert_eq!(offline.event, HookEvent::Offline);
+        assert!(offline.env.contains(&("DRCOM_SESSION_SECS", String::from("90"))));
+        assert!(offline.env.contains(&("DRCOM_STATE", String::from("stopping"))));
+
+        let relogin = hooks
+            .trigger(
+                &SupervisorEvent::ReloginScheduled {
+                    attempt: 2,
+                    delay:   Duration::from_secs(10),
+                    reason:  ReloginReason::Kicked,
+                },
+                start + Duration::from_secs(91),
+            )
+            .unwrap();
+        assert_eq!(
+            relogin.env,
+            vec![
+                ("DRCOM_RELOGIN_REASON", String::from("kicked")),
+                ("DRCOM_RELOGIN_ATTEMPT", String::from("2")),
+                ("DRCOM_RELOGIN_DELAY_SECS", String::from("10")),
+            ]
+        );
+
+        // nothing attached, nothing triggered
+        hooks.commands(vec![(HookEvent::Kicked, String::from("true"))]);
+        assert!(hooks
+            .trigger(
+                &SupervisorEvent::Session(SessionEvent::HostIPUnknown),
+                start
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_hook_run() {
+        let path = env::temp_dir().join(format!("drcom-rs-hook-{}", process::id()));
+        let mut hooks = Hooks::new();
+        hooks
+            .commands(vec![
+                (
+                    HookEvent::Online,
+                    format!("echo \"$DRCOM_EVENT $DRCOM_SERVER\" > {}", path.display()),
+                ),
+                (HookEvent::Online, String::from("sleep 10")),
+            ])
+            .timeout(Duration::from_millis(200));
+
+        let started = Instant::now();
+        hooks.run(&Trigger {
+            event: HookEvent::Online,
+            env:   vec![("DRCOM_SERVER", String::from("10.100.61.3:61440"))],
+        });
+        hooks.wait();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "online 10.100.61.3:61440\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_hook_background_child() {
+        let mut hooks = Hooks::new();
+        hooks
+            .commands(vec![
+                (HookEvent::Offline, String::from("sleep 10 &")),
+                (HookEvent::Offline, String::from("setsid sleep 10 &")),
+            ])
+            .timeout(Duration::from_millis(200));
+
+        // the shell is gone at once, the sleeps keep its output open
+        let started = Instant::now();
+        hooks.run(&Trigger {
+            event: HookEvent::Offline,
+            env:   vec![],
+        });
+        hooks.wait();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_parse_hook() {
+        assert_eq!(
+            HookEvent::parse_hook("login_failed=notify-send 'drcom: $DRCOM_FAILURE'"),
+            Ok((
+                HookEvent::LoginFailed,
+                String::from("notify-send 'drcom: $DRCOM_FAILURE'")
+            ))
+        );
+        assert!(matches!(
+            HookEvent::parse_hook("online"),
+            Err(HookParseError::MalformedHook(_))
+        ));
+        assert!(matches!(
+            HookEvent::parse_hook("up=true"),
+            Err(HookParseError::UnknownEvent(_))
+        ));
+    }
+}
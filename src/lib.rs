@@ -0,0 +1,15 @@
+pub mod common;
+pub mod config;
+pub mod control;
+pub mod credentials;
+pub mod crypto;
+pub mod daemon;
+pub mod discovery;
+pub mod drcom;
+pub mod hooks;
+pub mod logging;
+pub mod metrics;
+pub mod netlink;
+pub mod notify;
+pub mod probe;
+pub mod socket;
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::kv::{self, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json::{Map, Value};
+
+use crate::common::hex::ToHex;
+
+const REDACTED: &str = "[REDACTED]";
+/// Fields whose name contains any of these are never written out.
+const SECRET_FIELD_MARKERS: [&str; 6] = ["password", "secret", "token", "key", "hash", "auth_tail"];
+/// Login and logout requests and keep_alive1 carry password hashes, the login
+/// response carries the auth tail and keep_alive2 the keep alive key.
+const SECRET_PACKET_CODES: [u8; 5] = [0x03, 0x04, 0x06, 0x07, 0xff];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `<timestamp> <level> <target>: <message> key=value ...`, for terminals and journald.
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+/// Leveled logger writing structured records to stderr.
+#[derive(Debug)]
+pub struct Logger {
+    level:  LevelFilter,
+    format: LogFormat,
+}
+
+impl Logger {
+    pub fn new(level: LevelFilter, format: LogFormat) -> Self {
+        Logger { level, format }
+    }
+
+    /// Install as the global logger, only the first call in a process succeeds.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    pub fn format_record(&self, record: &Record, timestamp: DateTime<Utc>) -> String {
+        let mut fields = FieldCollector(Vec::new());
+        // a failing visitor only means a partial field list, the message still counts
+        let _ = record.key_values().visit(&mut fields);
+        let timestamp = timestamp.to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        match self.format {
+            LogFormat::Text => {
+                let mut line = format!(
+                    "{} {:<5} {}: {}",
+                    timestamp,
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+                for (key, value) in fields.0 {
+                    line.push_str(&format!(" {}={}", key, text_value(&value)));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let mut object = Map::new();
+                object.insert("timestamp".to_string(), Value::from(timestamp));
+                object.insert("level".to_string(), Value::from(record.level().as_str()));
+                object.insert("target".to_string(), Value::from(record.target()));
+                object.insert("message".to_string(), Value::from(record.args().to_string()));
+                if !fields.0.is_empty() {
+                    object.insert("fields".to_string(), Value::Object(fields.0.into_iter().collect()));
+                }
+                Value::Object(object).to_string()
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format_record(record, Utc::now());
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{}`, expected text or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogFormat::Text => f.write_str("text"),
+            LogFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+pub fn is_secret_field(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_FIELD_MARKERS.iter().any(|marker| key.contains(marker))
+}
+
+/// Hex dump of a packet for trace logs, packets carrying secrets only show their code.
+pub fn packet_dump(packet: &[u8]) -> String {
+    match packet.first() {
+        Some(code) if SECRET_PACKET_CODES.contains(code) => format!("{:02x}{}", code, REDACTED),
+        _ => packet.to_hex(),
+    }
+}
+
+struct FieldCollector(Vec<(String, Value)>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if is_secret_field(key.as_str()) {
+            Value::from(REDACTED)
+        } else if let Some(b) = value.to_bool() {
+            Value::from(b)
+        } else if let Some(n) = value.to_u64() {
+            Value::from(n)
+        } else if let Some(n) = value.to_i64() {
+            Value::from(n)
+        } else if let Some(n) = value.to_f64() {
+            Value::from(n)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.push((key.as_str().to_string(), value));
+        Ok(())
+    }
+}
+
+fn text_value(value: &Value) -> String {
+    match value {
+        Value::String(s) if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '"') => {
+            format!("{:?}", s)
+        }
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn format(format: LogFormat, record: &Record) -> String {
+        let timestamp = DateTime::parse_from_rfc3339("2022-10-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Logger::new(LevelFilter::Trace, format).format_record(record, timestamp)
+    }
+
+    #[test]
+    fn test_logger_formats() {
+        let fields: [(&str, kv::Value); 4] = [
+            ("sequence", kv::Value::from(3u8)),
+            ("state", kv::Value::from("Heartbeat One")),
+            ("password", kv::Value::from("hunter2")),
+            ("keep_alive_key", kv::Value::from("0a0b0c0d")),
+        ];
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("drcom_rs")
+            .args(format_args!("heartbeat ok"))
+            .key_values(&fields)
+            .build();
+
+        assert_eq!(
+            format(LogFormat::Text, &record),
+            "2022-10-01T08:00:00.000Z INFO  drcom_rs: heartbeat ok sequence=3 \
+             state=\"Heartbeat One\" password=[REDACTED] keep_alive_key=[REDACTED]"
+        );
+        let json: Value = serde_json::from_str(&format(LogFormat::Json, &record)).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["message"], "heartbeat ok");
+        assert_eq!(json["fields"]["sequence"], 3);
+        assert_eq!(json["fields"]["password"], REDACTED);
+        assert_eq!(json["fields"]["keep_alive_key"], REDACTED);
+    }
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert!("yaml".parse::<LogFormat>().is_err());
+        assert!(is_secret_field("auth_tail"));
+        assert!(!is_secret_field("sequence"));
+        assert_eq!(packet_dump(&[0x02, 0x01, 0x28]), "020128");
+        assert_eq!(packet_dump(&[0x07, 0x01, 0x28]), "07[REDACTED]");
+        assert_eq!(packet_dump(&[0xff, 0x01, 0x28]), "ff[REDACTED]");
+        assert_eq!(packet_dump(&[]), "");
+    }
+}
@@ -1,277 +1,918 @@
-mod common;
-mod crypto;
-mod drcom;
-
-use std::io::BufReader;
+use std::io;
 use std::net::Ipv4Addr;
-use std::net::SocketAddr;
-use std::net::UdpSocket;
-use std::net::ToSocketAddrs;
-use std::thread::sleep;
-
-use clap::Parser;
-use drcom::wired::dialer::ChallengeResponse;
-use drcom::wired::dialer::LoginResponse;
-use drcom::wired::dialer::{LoginAccount, ChallengeRequest};
-use drcom::wired::heartbeater::HeartbeatError;
-use drcom::wired::heartbeater::HeartbeatFlag;
-use drcom::wired::heartbeater::PhaseOneRequest;
-use drcom::wired::heartbeater::PhaseOneResponse;
-use drcom::wired::heartbeater::PhaseTwoRequest;
-use drcom::wired::heartbeater::PhaseTwoResponse;
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::path::{self, Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand};
+use log::{debug, error, info, trace, warn, LevelFilter};
+use serde_json::{json, Value};
+
+use drcom_rs::common::secret::SecretString;
+use drcom_rs::config::{AccountConfig, Config, ConfigError};
+use drcom_rs::control::{self, Command, ControlError, ControlServer};
+use drcom_rs::credentials::{CredentialError, PasswordSource, DEFAULT_CREDENTIAL};
+use drcom_rs::daemon::{self, Pidfile, Signals};
+use drcom_rs::discovery::{InterfaceInfo, DEFAULT_SYSTEM_ROOT};
+use drcom_rs::drcom::wired::dialer::LoginAccount;
+use drcom_rs::drcom::wired::retransmit::{RetransmitPolicy, Step, StepPolicy};
+use drcom_rs::drcom::wired::session::{SessionEvent, WiredSession};
+use drcom_rs::drcom::wired::supervisor::{SessionSupervisor, SupervisorEvent, SupervisorState};
+use drcom_rs::hooks::{HookEvent, Hooks};
+use drcom_rs::logging::{packet_dump, LogFormat, Logger};
+use drcom_rs::metrics::{Metrics, MetricsServer};
+use drcom_rs::netlink::{NetworkChange, NetworkMonitor};
+use drcom_rs::notify::Notifier;
+use drcom_rs::probe::{ProbeTarget, Prober};
+use drcom_rs::socket::{self, SocketOptions};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// TOML config file, command line flags take precedence over it
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
     /// Server address
     #[arg(short, long)]
-    server: String,
+    server: Option<String>,
 
     /// Username
     #[arg(short, long)]
-    username: String,
+    username: Option<String>,
+
+    /// Password, visible to every user through ps, prefer the options below
+    #[arg(short, long, group = "password_source")]
+    password: Option<String>,
+
+    /// Read the password from the first line of a file that is not world-readable
+    #[arg(long, value_name = "PATH", group = "password_source")]
+    password_file: Option<PathBuf>,
+
+    /// Read the password from an environment variable
+    #[arg(long, value_name = "VAR", group = "password_source")]
+    password_env: Option<String>,
+
+    /// Read the password from stdin, prompting if it is a terminal
+    #[arg(long, group = "password_source")]
+    password_stdin: bool,
+
+    /// Read the password from a systemd credential in $CREDENTIALS_DIRECTORY
+    #[arg(
+        long,
+        value_name = "NAME",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_CREDENTIAL,
+        group = "password_source"
+    )]
+    password_credential: Option<String>,
+
+    /// Host IP reported to the server, defaults to the one echoed in the challenge
+    #[arg(long)]
+    host_ip: Option<Ipv4Addr>,
+
+    /// Interface facing the campus network: its MAC, addresses, DNS servers and the hostname
+    /// fill in the account, its link and address changes trigger a new login
+    #[arg(short, long, value_name = "NAME")]
+    interface: Option<String>,
+
+    /// Read /sys, /proc and /etc from under this directory when looking up --interface
+    #[arg(long, value_name = "PATH")]
+    system_root: Option<PathBuf>,
+
+    /// Local address and port to send from, port 0 picks an ephemeral one
+    /// [default: 0.0.0.0:61440]
+    #[arg(long, value_name = "ADDR[:PORT]", value_parser = parse_bind_address)]
+    bind: Option<SocketAddrV4>,
+
+    /// Keep auth traffic on this interface (SO_BINDTODEVICE), needs CAP_NET_RAW
+    #[arg(long, value_name = "NAME")]
+    bind_device: Option<String>,
+
+    /// Send from an ephemeral port when the bound one is taken, if the server accepts it
+    #[arg(long)]
+    ephemeral_port: bool,
+
+    /// Leave SO_REUSEADDR off, so that a second instance cannot bind the same port but a
+    /// restart may fail while the previous socket lingers
+    #[arg(long)]
+    no_reuse_address: bool,
+
+    /// Seconds to wait for each response before resending the request [default: 3]
+    #[arg(long, value_parser = parse_timeout)]
+    response_timeout: Option<f64>,
+
+    /// How many times a request is resent before the step fails [default: 2]
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// Per-step override as <step>=<timeout seconds>:<retries>, e.g. login=5:1
+    #[arg(long = "retransmit", value_name = "STEP=TIMEOUT:RETRIES", value_parser = parse_step_policy)]
+    retransmit: Vec<(Step, StepPolicy)>,
+
+    /// Check connectivity while online and log in again when it is lost: an http:// URL
+    /// answering 204, e.g. http://connect.rom.miui.com/generate_204, or a host:port to connect to
+    #[arg(long, value_name = "TARGET", value_parser = parse_probe_target)]
+    probe: Option<ProbeTarget>,
+
+    /// Seconds between connectivity checks [default: 60]
+    #[arg(long, value_name = "SECS", value_parser = parse_timeout)]
+    probe_interval: Option<f64>,
+
+    /// Run a shell command on an event: online, offline, login-failed, kicked or relogin,
+    /// e.g. online='systemctl restart wg-quick@wg0'
+    #[arg(long = "hook", value_name = "EVENT=COMMAND", value_parser = parse_hook)]
+    hooks: Vec<(HookEvent, String)>,
 
-    /// Password
+    /// Override a config key, e.g. mac_address=b8:88:e3:05:16:80 or account.dog_flag=0x01
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+
+    /// Most verbose level written out: off, error, warn, info, debug or trace [default: info]
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<LevelFilter>,
+
+    /// Log line format, text or json [default: text]
+    #[arg(long, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Detach into the background, for systems without a service manager
     #[arg(short, long)]
-    password: String,
+    daemon: bool,
+
+    /// Keep our pid in this file, locked so that a second instance refuses to start
+    #[arg(long, value_name = "PATH")]
+    pidfile: Option<PathBuf>,
+
+    /// Where logs go once detached with --daemon [default: discarded]
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    log_file: Option<PathBuf>,
+
+    /// Unix socket taking control commands [default: $RUNTIME_DIRECTORY/drcom-rs.sock,
+    /// $XDG_RUNTIME_DIR/drcom-rs.sock or /run/drcom-rs.sock]
+    #[arg(long, value_name = "PATH", global = true)]
+    control_socket: Option<PathBuf>,
+
+    /// Serve Prometheus metrics over HTTP at ADDR/metrics, e.g. 127.0.0.1:9100
+    #[arg(long, value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
 }
 
-macro_rules! read_socket {
-    ($socket:ident, $buf:ident) => {
-        {
-            $socket.recv_from(&mut $buf).unwrap();
-            BufReader::new(&$buf[..])
-        }
-    };
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// Send a command to the running client: status, logout, relogin or reload
+    Ctl { command: Command },
 }
 
-fn phase1(
-    socket: &UdpSocket, 
-    hash_salt: [u8; 4],
-    keep_alive_key: [u8; 16], 
-    password: &str,
-    remote_server: &SocketAddr,
-) -> Result<PhaseOneResponse, HeartbeatError> {
-    let mut recv_buf = [0u8; 1024];
-    let p1_request = PhaseOneRequest::new(
-        hash_salt,
-        password,
-        keep_alive_key,
-        None
-    );
-    let p1_packet = p1_request.as_bytes();
-    println!("[Phase 1] Sending P1 Request... {:#X?}", &p1_packet);
-    socket.send_to(&p1_packet, remote_server).unwrap();
-    let mut recv_reader = {
-        socket.recv_from(&mut recv_buf).unwrap();
-        println!("[Phase 1] Receiving response... {:#X?}", recv_buf);
-        BufReader::new(&recv_buf[..])
-    };
-    PhaseOneResponse::from_bytes(&mut recv_reader)
+/// Check for signals at least this often while waiting on the socket.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Why `drive` handed control back.
+enum Interrupt {
+    Stopped,
+    Shutdown,
+    Reload,
+    Control(Command),
 }
 
-fn main() {
-    let args = Args::parse();
+fn parse_timeout(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && Duration::try_from_secs_f64(secs).is_ok() => Ok(secs),
+        _ => Err(format!("`{}` is not a positive number of seconds", s)),
+    }
+}
 
-    let socket = UdpSocket::bind("0.0.0.0:61440").unwrap();
-    let remote_server = args.server.to_socket_addrs().unwrap().next().unwrap();
+fn parse_bind_address(s: &str) -> Result<SocketAddrV4, String> {
+    socket::parse_bind_address(s).map_err(|_| format!("`{}` is not an IPv4 address[:port]", s))
+}
 
-    let host_ip = Ipv4Addr::new(10,30,22,17);
+fn parse_step_policy(s: &str) -> Result<(Step, StepPolicy), String> {
+    RetransmitPolicy::parse_override(s).map_err(|e| e.to_string())
+}
 
-    let mut recv_buf = [0u8; 1024];
+fn parse_probe_target(s: &str) -> Result<ProbeTarget, String> {
+    s.parse().map_err(|e: drcom_rs::probe::ProbeError| e.to_string())
+}
 
-    let challenge_response = {
-        let challenge_request = ChallengeRequest::new(None);
-        let challenge_packet = challenge_request.as_bytes();
-        println!("[ChalResp] Sending challenge... {:#X?}", &challenge_packet);
-        socket.send_to(&challenge_packet, remote_server).unwrap();
-        let mut recv_reader = read_socket!(socket, recv_buf);
-        println!("[ChalResp] Receiving response... {:#X?}", recv_buf);
-        ChallengeResponse::from_bytes(&mut recv_reader).unwrap()
+fn parse_hook(s: &str) -> Result<(HookEvent, String), String> {
+    HookEvent::parse_hook(s).map_err(|e| e.to_string())
+}
+
+/// Merge the config file with the command line, the latter wins.
+fn load_config(args: &Args) -> Result<Config, ConfigError> {
+    let mut config = match args.config {
+        Some(ref path) => Config::from_file(path)?,
+        None => Config::default(),
     };
+    for assignment in args.overrides.iter() {
+        config.set_override(assignment)?;
+    }
 
-    let mut account = LoginAccount::new(
-        &args.username,
-        &args.password,
-        challenge_response.hash_salt,
-    );
+    config.server = args.server.clone().or(config.server);
+    config.username = args.username.clone().or(config.username);
+    config.password = password_source(args).or(config.password);
+    config.host_ip = args.host_ip.or(config.host_ip);
+    config.interface = args.interface.clone().or(config.interface);
+    config.system_root = args.system_root.clone().or(config.system_root);
+    config.bind_address = args.bind.or(config.bind_address);
+    config.bind_device = args.bind_device.clone().or(config.bind_device);
+    if args.ephemeral_port {
+        config.ephemeral_port = Some(true);
+    }
+    if args.no_reuse_address {
+        config.reuse_address = Some(false);
+    }
+    if let Some(secs) = args.response_timeout {
+        config.retransmit.timeout = Some(Duration::from_secs_f64(secs));
+    }
+    config.retransmit.retries = args.retries.or(config.retransmit.retries);
+    config.retransmit.steps.extend(args.retransmit.iter().copied());
+    config.probe.target = args.probe.clone().or(config.probe.target);
+    if let Some(secs) = args.probe_interval {
+        config.probe.interval = Some(Duration::from_secs_f64(secs));
+    }
+    config.hooks.commands.extend(args.hooks.iter().cloned());
+    config.log_level = args.log_level.or(config.log_level);
+    config.log_format = args.log_format.or(config.log_format);
+    config.control_socket = args.control_socket.clone().or(config.control_socket);
+    config.metrics_listen = args.metrics_listen.or(config.metrics_listen);
+    Ok(config)
+}
+
+fn password_source(args: &Args) -> Option<PasswordSource> {
+    if let Some(ref password) = args.password {
+        eprintln!("warning: --password is visible to other users, prefer --password-file");
+        return Some(PasswordSource::Literal(SecretString::from(password.as_str())));
+    }
+    if let Some(ref path) = args.password_file {
+        return Some(PasswordSource::File(path.clone()));
+    }
+    if let Some(ref name) = args.password_env {
+        return Some(PasswordSource::Env(name.clone()));
+    }
+    if args.password_stdin {
+        return Some(PasswordSource::Stdin);
+    }
+    args.password_credential.clone().map(PasswordSource::Credential)
+}
 
+fn required(value: Option<String>, key: &str) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing `{}`, pass --{} or set it in the config file", key, key))
+}
+
+/// Read the password again on reload, stdin only works once so keep what it gave.
+fn reload_password(
+    config: &Config,
+    previous: &SecretString,
+) -> Result<SecretString, CredentialError> {
+    match config.password.clone().map_or_else(PasswordSource::discover, Ok) {
+        Ok(PasswordSource::Stdin) | Err(CredentialError::NoSource) => Ok(previous.clone()),
+        source => source?.read(),
+    }
+}
+
+fn build_supervisor(
+    config: &Config,
+    password: &SecretString,
+) -> Result<(SessionSupervisor, SocketAddr), String> {
+    let server = required(config.server.clone(), "server")?;
+    let username = required(config.username.clone(), "username")?;
+    let remote_server = server
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve server `{}`: {}", server, e))?
+        .next()
+        .ok_or_else(|| format!("server `{}` has no address", server))?;
+
+    let mut account = LoginAccount::new(&username, password.expose(), [0u8; 4]);
     account.client_version(0xf);
+    populate_account(
+        &mut account,
+        config.interface.as_deref(),
+        &system_root(config),
+        &config.account,
+    );
 
-    let login_response = {
-        let login_request = account.login_request().unwrap();
-        let login_packet = login_request.as_bytes().unwrap();
-        println!("[Login] Logging in... {:#X?}", &login_packet);
-        socket.send_to(&login_packet, remote_server).unwrap();
-        let mut recv_reader = read_socket!(socket, recv_buf);
-        println!("[Login] Receiving login response... {:#X?}", recv_buf);
-        LoginResponse::from_bytes(&mut recv_reader)
-    };
+    let mut session = WiredSession::new(account);
+    session
+        .host_ip_override(config.host_ip)
+        .retransmit_policy(config.retransmit.policy());
+    if let Some(interval) = config.heartbeat_interval {
+        session.heartbeat_interval(interval);
+    }
+    Ok((SessionSupervisor::new(session), remote_server))
+}
 
-    if login_response.is_err() {
-        continue;
+fn system_root(config: &Config) -> PathBuf {
+    config.system_root.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_SYSTEM_ROOT))
+}
+
+/// Fill in `account` from what `interface` looks like, then the config file on top.
+fn populate_account(
+    account: &mut LoginAccount,
+    interface: Option<&str>,
+    system_root: &Path,
+    overrides: &AccountConfig,
+) {
+    if let Some(interface) = interface {
+        match InterfaceInfo::discover(system_root, interface) {
+            Ok(info) => {
+                debug!(interface, info:? = info; "interface discovered");
+                info.apply(account);
+            }
+            Err(e) => warn!(interface, error:% = e; "cannot look up the interface"),
+        }
+    }
+    overrides.apply(account);
+}
+
+fn log_event(event: &SessionEvent) {
+    match event {
+        SessionEvent::Challenged { host_ip } => debug!(host_ip:% = host_ip; "challenge answered"),
+        SessionEvent::LoggedIn(login_response) => info!(
+            used_minutes = login_response.used_time_minutes,
+            used_kib = login_response.used_traffic_kb,
+            balance = login_response.balance;
+            "logged in"
+        ),
+        SessionEvent::LoginFailed(failure) => error!(
+            code = failure.error_code(),
+            failure:? = failure;
+            "login failed"
+        ),
+        SessionEvent::HostIPUnknown => {
+            error!("server did not echo the client IP, please specify --host-ip")
+        }
+        SessionEvent::Online { sequence } => info!(sequence; "online, heartbeat loop started"),
+        SessionEvent::HeartbeatCompleted { sequence } => info!(sequence; "heartbeat ok"),
+        SessionEvent::HeartbeatMissed { missed } => warn!(missed; "heartbeat got no response"),
+        SessionEvent::Retransmitted { step, attempt } => {
+            warn!(step:% = step, attempt; "no response, sending again")
+        }
+        SessionEvent::ResponseTimeout { step, attempts } => {
+            error!(step:% = step, attempts; "server did not respond")
+        }
+        SessionEvent::Kicked(message) => warn!(kind = message.kind; "kicked by the server"),
+        SessionEvent::LoggedOut => info!("logged out"),
+        SessionEvent::RoundTrip { step, rtt } => {
+            trace!(step:% = step, rtt_ms = rtt.as_secs_f64() * 1000.0; "response received")
+        }
+        SessionEvent::PacketDropped { state, reason } => {
+            warn!(state:? = state, reason:% = reason; "dropped unexpected packet")
+        }
     }
+}
 
-    let login_response = login_response.unwrap();
+fn log_supervisor_event(event: &SupervisorEvent) {
+    match event {
+        SupervisorEvent::Session(event) => log_event(event),
+        SupervisorEvent::StateChanged { from, to } => {
+            info!(from:? = from, to:? = to; "supervisor state changed")
+        }
+        SupervisorEvent::ReloginScheduled {
+            attempt,
+            delay,
+            reason,
+        } => info!(
+            attempt,
+            delay_secs = delay.as_secs_f64(),
+            reason:? = reason;
+            "re-login scheduled"
+        ),
+    }
+}
 
-    phase1(&socket, challenge_response.hash_salt, login_response.keep_alive_key, &args.password, &remote_server).unwrap();
+/// The IO around a supervisor: its socket, signals and the service manager.
+struct Context {
+    socket:         UdpSocket,
+    socket_options: SocketOptions,
+    remote_server:  SocketAddr,
+    signals:        Signals,
+    notifier:       Option<Notifier>,
+    control:        Option<ControlServer>,
+    /// Shared with the metrics server thread, if any.
+    metrics:        Arc<Mutex<Metrics>>,
+    hooks:          Hooks,
+    prober:         Option<Prober>,
+    monitor:        Option<NetworkMonitor>,
+    system_root:    PathBuf,
+    /// The `[account]` section, applied again over a fresh interface lookup.
+    account:        AccountConfig,
+    /// Whether READY=1 went out since startup or the last reload.
+    ready:          bool,
+    /// Logged out through the control socket, waiting for a relogin.
+    logged_out:     bool,
+    /// Waiting for a logout to finish, other commands are turned away meanwhile.
+    logging_out:    bool,
+}
 
-    {
-        let mut sequence = 0;
-        let mut keep_alive_key = [0u8; 4];
+fn status_line(supervisor: &SessionSupervisor, now: Instant) -> String {
+    match supervisor.state() {
+        SupervisorState::Idle => String::from("starting"),
+        SupervisorState::Connecting => String::from("logging in"),
+        SupervisorState::Online => match supervisor.session().last_heartbeat() {
+            Some(at) => format!(
+                "online, seq {:#x}, last heartbeat {}s ago",
+                supervisor.session().sequence(),
+                now.saturating_duration_since(at).as_secs()
+            ),
+            None => String::from("online"),
+        },
+        SupervisorState::Backoff { until } => format!(
+            "offline, logging in again in {}s",
+            until.saturating_duration_since(now).as_secs()
+        ),
+        SupervisorState::LinkDown => String::from("link down, waiting for it to come back"),
+        SupervisorState::Stopping => String::from("logging out"),
+        SupervisorState::Stopped => String::from("stopped"),
+    }
+}
+
+fn status_json(supervisor: &SessionSupervisor, remote_server: SocketAddr, now: Instant) -> Value {
+    let session = supervisor.session();
+    let host_ip = Some(session.host_ip()).filter(|ip| !ip.is_unspecified());
+    json!({
+        "state": supervisor.state().name(),
+        "server": remote_server.to_string(),
+        "host_ip": host_ip.map(|ip| ip.to_string()),
+        "sequence": session.sequence(),
+        "last_heartbeat_rtt_ms": session.heartbeat_rtt().map(|rtt| rtt.as_secs_f64() * 1000.0),
+        "uptime_secs": session.online_since().map(|t| now.saturating_duration_since(t).as_secs()),
+    })
+}
+
+impl Context {
+    /// Keep systemd posted, `changed` forces a STATUS= update besides the one
+    /// sent with every watchdog ping.
+    fn notify(&mut self, supervisor: &SessionSupervisor, changed: bool) {
+        let notifier = match self.notifier {
+            Some(ref mut notifier) => notifier,
+            None => return,
+        };
+        let now = Instant::now();
+        let mut result = Ok(());
+        if !self.ready && supervisor.state() == SupervisorState::Online {
+            self.ready = true;
+            result = result.and(notifier.ready());
+        }
+        if changed || notifier.watchdog_due(now) {
+            result = result.and(notifier.status(&status_line(supervisor, now)));
+        }
+        result = result.and(notifier.watchdog(now).map(|_| ()));
+        if let Err(e) = result {
+            warn!(error:% = e; "cannot notify systemd");
+        }
+    }
+
+    fn metrics(&self) -> MutexGuard<'_, Metrics> {
+        // the metrics thread only renders while holding the lock
+        self.metrics.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
+    fn notify_with(&self, notify: fn(&Notifier) -> io::Result<()>) {
+        if let Some(Err(e)) = self.notifier.as_ref().map(notify) {
+            warn!(error:% = e; "cannot notify systemd");
+        }
+    }
+
+    /// Pump packets between the supervisor and the socket until it stops or a signal arrives.
+    fn drive(&mut self, supervisor: &mut SessionSupervisor) -> io::Result<Interrupt> {
+        let mut recv_buf = [0u8; 1024];
         loop {
-            let p2_response = {
-                let p2_request = PhaseTwoRequest::new(
-                    sequence, 
-                    keep_alive_key,
-                    &HeartbeatFlag::First,
-                    host_ip,
-                    Some(1),
+            while let Some(packet) = supervisor.poll_transmit() {
+                trace!(
+                    to:% = self.remote_server,
+                    length = packet.len(),
+                    packet:% = packet_dump(&packet);
+                    "send"
                 );
-                let p2_packet = p2_request.as_bytes();
-                println!("[keep-alive2] send1");
-                socket.send_to(&p2_packet, remote_server).unwrap();
-                println!("[keep-alive2] recv1");
-                let mut recv_reader = read_socket!(socket, recv_buf);
-                PhaseTwoResponse::from_bytes(&mut recv_reader)
-            };
+                // a lost packet is caught by the response timeout, no need to bail out
+                if let Err(e) = self.socket.send_to(&packet, self.remote_server) {
+                    warn!(error:% = e; "failed to send packet");
+                }
+            }
+            let mut changed = false;
+            while let Some(event) = supervisor.poll_event() {
+                log_supervisor_event(&event);
+                self.metrics().observe(&event);
+                self.run_hooks(supervisor, &event);
+                changed = true;
+            }
+            self.notify(supervisor, changed);
+            if supervisor.state() == SupervisorState::Stopped && !self.logged_out {
+                return Ok(Interrupt::Stopped);
+            }
+            if self.signals.take_shutdown() {
+                return Ok(Interrupt::Shutdown);
+            }
+            if self.signals.take_reload() {
+                return Ok(Interrupt::Reload);
+            }
+            if let Some(command) = self.serve_control(supervisor) {
+                return Ok(Interrupt::Control(command));
+            }
+            if self.probe(supervisor) || self.watch_network(supervisor) {
+                continue;
+            }
 
-            if p2_response.is_ok() {
-                let p2_response = p2_response.unwrap();
-                if p2_response.sequence == 0 || p2_response.sequence == sequence {
-                    break;
-                } else {
-                    println!("[keep-alive2] recv file, resending...");
-                    sequence += 1;
-                    break;
+            let timeout = supervisor
+                .poll_timeout()
+                .map(|t| t.saturating_duration_since(Instant::now()));
+            if timeout.is_some_and(|t| t.is_zero()) {
+                supervisor.handle_tick(Instant::now());
+                continue;
+            }
+            let timeout = timeout.map_or(SIGNAL_CHECK_INTERVAL, |t| t.min(SIGNAL_CHECK_INTERVAL));
+            self.socket.set_read_timeout(Some(timeout))?;
+
+            match self.socket.recv_from(&mut recv_buf) {
+                // anyone on the LAN can reach our port, only the server gets a say
+                Ok((length, from)) if from != self.remote_server => {
+                    warn!(from:% = from, length; "dropped packet from a foreign source");
+                    self.metrics().packet_dropped("foreign_source");
+                }
+                Ok((length, from)) => {
+                    let packet = &recv_buf[..length];
+                    trace!(from:% = from, length, packet:% = packet_dump(packet); "receive");
+                    supervisor.handle_datagram(Instant::now(), packet)
                 }
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    supervisor.handle_tick(Instant::now())
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
         }
+    }
 
-        {
-            let p2_request = PhaseTwoRequest::new(
-                sequence, 
-                keep_alive_key,
-                &HeartbeatFlag::NotFirst,
-                host_ip,
-                Some(1),
-            );
-            let p2_packet = p2_request.as_bytes();
-            println!("[keep-alive2] send2");
-            socket.send_to(&p2_packet, remote_server).unwrap();
-            loop {
-                let mut recv_reader = read_socket!(socket, recv_buf);
-                let p2_response = PhaseTwoResponse::from_bytes(&mut recv_reader);
-                match p2_response {
-                    Ok(resp) => {
-                        sequence += 1;
-                        keep_alive_key = resp.keep_alive_key;
-                        break;
-                    },
-                    Err(drcom::wired::heartbeater::HeartbeatError::ValidateError(_)) => {
-                        println!("[keep-alive2] recv2/unexpected");
-                    }
-                    _ => {
-                        unreachable!("[keep-alive2] recv2/unexpected error");
-                    }
+    fn run_hooks(&mut self, supervisor: &SessionSupervisor, event: &SupervisorEvent) {
+        let mut trigger = match self.hooks.trigger(event, Instant::now()) {
+            Some(trigger) => trigger,
+            None => return,
+        };
+        trigger.env.push(("DRCOM_SERVER", self.remote_server.to_string()));
+        let host_ip = supervisor.session().host_ip();
+        if !host_ip.is_unspecified() {
+            trigger.env.push(("DRCOM_HOST_IP", host_ip.to_string()));
+        }
+        self.hooks.run(&trigger);
+    }
+
+    /// Run the connectivity check while online, returns whether it asked for a relogin.
+    fn probe(&mut self, supervisor: &mut SessionSupervisor) -> bool {
+        let prober = match self.prober {
+            Some(ref mut prober) => prober,
+            None => return false,
+        };
+        if supervisor.state() != SupervisorState::Online {
+            prober.pause();
+            return false;
+        }
+        let now = Instant::now();
+        prober.resume(now);
+        let result = match prober.poll(now) {
+            Some(result) => result,
+            None => return false,
+        };
+
+        if result.outcome.is_reachable() {
+            debug!(target:% = prober.target(); "connectivity check passed");
+            return false;
+        }
+        warn!(
+            target:% = prober.target(),
+            failures = result.failures,
+            outcome:% = result.outcome;
+            "connectivity check failed"
+        );
+        if result.lost {
+            warn!("traffic does not get through, logging in again");
+            supervisor.connectivity_lost(now);
+        }
+        result.lost
+    }
+
+    /// Follow link and address changes of the interface, returns whether there were any.
+    fn watch_network(&mut self, supervisor: &mut SessionSupervisor) -> bool {
+        let monitor = match self.monitor {
+            Some(ref mut monitor) => monitor,
+            None => return false,
+        };
+        let changes = match monitor.poll() {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!(error:% = e; "cannot read network changes");
+                return false;
+            }
+        };
+        for change in changes.iter() {
+            let interface = monitor.interface().name.as_str();
+            info!(interface, change:% = change; "network changed");
+            match change {
+                NetworkChange::LinkDown => supervisor.link_down(),
+                NetworkChange::LinkUp => supervisor.link_up(Instant::now()),
+                NetworkChange::AddressChanged { .. } => {
+                    populate_account(
+                        supervisor.account_mut(),
+                        Some(interface),
+                        &self.system_root,
+                        &self.account,
+                    );
+                    supervisor.address_changed(Instant::now())
                 }
+                // nothing to do until a new one shows up
+                NetworkChange::AddressRemoved(_) => {}
             }
         }
-    
-        {
-            let p2_request = PhaseTwoRequest::new(
-                sequence, 
-                keep_alive_key,
-                &HeartbeatFlag::NotFirst,
-                host_ip,
-                Some(3),
-            );
-            let p2_packet = p2_request.as_bytes();
-            println!("[keep-alive2] send3");
-            socket.send_to(&p2_packet, remote_server).unwrap();
-            loop {
-                let mut recv_reader = read_socket!(socket, recv_buf);
-                let p2_response = PhaseTwoResponse::from_bytes(&mut recv_reader);
-                match p2_response {
-                    Ok(resp) => {
-                        sequence += 1;
-                        keep_alive_key = resp.keep_alive_key;
-                        break;
-                    },
-                    Err(drcom::wired::heartbeater::HeartbeatError::ValidateError(_)) => {
-                        println!("[keep-alive2] recv2/unexpected");
-                    }
-                    _ => {
-                        unreachable!("[keep-alive2] recv2/unexpected error");
-                    }
+        !changes.is_empty()
+    }
+
+    /// Answer pending control clients, `status` right away and anything else
+    /// after handing it to the caller.
+    fn serve_control(&mut self, supervisor: &SessionSupervisor) -> Option<Command> {
+        let control = self.control.as_mut()?;
+        loop {
+            let (connection, command) = match control.next_request() {
+                Ok(Some(request)) => request,
+                Ok(None) => return None,
+                Err(e) => {
+                    warn!(error:% = e; "cannot accept control connection");
+                    return None;
                 }
+            };
+            let (result, command) = match command {
+                Ok(Command::Status) => {
+                    let status = status_json(supervisor, self.remote_server, Instant::now());
+                    (connection.respond_ok(status), None)
+                }
+                Ok(_) if self.logging_out => (connection.respond_error(&ControlError::Busy), None),
+                Ok(command) => (connection.respond_ok(json!({})), Some(command)),
+                Err(e) => (connection.respond_error(&e), None),
+            };
+            if let Err(e) = result {
+                warn!(error:% = e; "cannot answer control connection");
             }
+            if let Some(command) = command {
+                info!(command:% = command; "control command");
+                return Some(command);
+            }
+        }
+    }
+
+    /// Switch to a socket bound with `options` if they changed, keeps the current one on failure.
+    fn rebind(&mut self, options: SocketOptions) {
+        if options == self.socket_options {
+            return;
+        }
+        match bind_socket(&options) {
+            Ok(socket) => (self.socket, self.socket_options) = (socket, options),
+            Err(e) => error!(error:% = e; "cannot bind the new socket, keeping the current one"),
         }
-    
-        let mut i = sequence;
-        println!("[keep-alive2] keep-alive2 loop was in daemon.");
+    }
 
-        loop {
-            sleep(std::time::Duration::from_secs(20));
-            phase1(&socket, challenge_response.hash_salt, login_response.keep_alive_key, &args.password, &remote_server).unwrap();
-            
-            {
-                let p2_request = PhaseTwoRequest::new(
-                    i, 
-                    keep_alive_key,
-                    &HeartbeatFlag::NotFirst,
-                    host_ip,
-                    Some(1),
-                );
-                let p2_packet = p2_request.as_bytes();
-                println!("[keep-alive2] send");
-                socket.send_to(&p2_packet, remote_server).unwrap();
-                let mut recv_reader = read_socket!(socket, recv_buf);
-                let p2_response = PhaseTwoResponse::from_bytes(&mut recv_reader);
-                match p2_response {
-                    Ok(resp) => {
-                        keep_alive_key = resp.keep_alive_key;
-                    },
-                    _ => {
-                        continue;
-                    }
+    /// Log out and wait for the server to confirm, a SIGTERM/SIGINT cuts it short.
+    /// Returns whether it was cut short, the caller should exit then.
+    fn logout(&mut self, supervisor: &mut SessionSupervisor) -> io::Result<bool> {
+        if supervisor.state() == SupervisorState::Stopped {
+            return Ok(false);
+        }
+        supervisor.shutdown(Instant::now());
+        self.logging_out = true;
+        let interrupted = loop {
+            match self.drive(supervisor) {
+                Ok(Interrupt::Stopped) => break Ok(false),
+                Ok(Interrupt::Shutdown) => {
+                    warn!("interrupted, exiting without waiting for the logout");
+                    break Ok(true);
                 }
+                Ok(Interrupt::Reload) => warn!("busy logging out, ignoring SIGHUP"),
+                // only status gets through, the others are answered busy
+                Ok(Interrupt::Control(_)) => {}
+                Err(e) => break Err(e),
             }
-        
-            {
-                let p2_request = PhaseTwoRequest::new(
-                    i+1, 
-                    keep_alive_key,
-                    &HeartbeatFlag::NotFirst,
-                    host_ip,
-                    Some(3),
-                );
-                let p2_packet = p2_request.as_bytes();
-                println!("[keep-alive2] send");
-                socket.send_to(&p2_packet, remote_server).unwrap();
-                loop {
-                    let mut recv_reader = read_socket!(socket, recv_buf);
-                    let p2_response = PhaseTwoResponse::from_bytes(&mut recv_reader);
-                    match p2_response {
-                        Ok(resp) => {
-                            keep_alive_key = resp.keep_alive_key;
-                            break;
-                        },
-                        _ => {
-                            continue;
+        };
+        self.logging_out = false;
+        interrupted
+    }
+}
+
+fn bind_socket(options: &SocketOptions) -> io::Result<UdpSocket> {
+    let socket = options.bind()?;
+    info!(address:% = socket.local_addr()?; "socket bound");
+    Ok(socket)
+}
+
+fn watch_interface(interface: &str) -> Option<NetworkMonitor> {
+    NetworkMonitor::new(interface)
+        .inspect_err(|e| warn!(interface, error:% = e; "not following network changes"))
+        .ok()
+}
+
+fn reload(
+    args: &Args,
+    password: &SecretString,
+) -> Result<(Config, SessionSupervisor, SocketAddr, SecretString), String> {
+    let config = load_config(args).map_err(|e| e.to_string())?;
+    let password = reload_password(&config, password).map_err(|e| e.to_string())?;
+    let (supervisor, remote_server) = build_supervisor(&config, &password)?;
+    Ok((config, supervisor, remote_server, password))
+}
+
+fn run(
+    args: &Args,
+    config: &Config,
+    control_socket: &Path,
+    mut supervisor: SessionSupervisor,
+    remote_server: SocketAddr,
+    mut password: SecretString,
+) -> io::Result<i32> {
+    let control = ControlServer::bind(control_socket)
+        .inspect_err(|e| warn!(error:% = e; "control socket disabled"))
+        .ok();
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    if let Some(address) = config.metrics_listen {
+        MetricsServer::spawn(address, Arc::clone(&metrics)).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot serve metrics on {}: {}", address, e))
+        })?;
+    }
+    let mut context = Context {
+        socket: bind_socket(&config.socket_options())?,
+        socket_options: config.socket_options(),
+        remote_server,
+        signals: Signals::register().map_err(io::Error::other)?,
+        notifier: Notifier::from_env()?,
+        control,
+        metrics,
+        hooks: Hooks::new(),
+        prober: config.probe.prober(),
+        monitor: config.interface.as_deref().and_then(watch_interface),
+        system_root: system_root(config),
+        account: config.account.clone(),
+        ready: false,
+        logged_out: false,
+        logging_out: false,
+    };
+
+    config.hooks.apply(&mut context.hooks);
+
+    supervisor.start(Instant::now());
+    loop {
+        match context.drive(&mut supervisor)? {
+            Interrupt::Stopped => {
+                context.hooks.wait();
+                return Ok(1);
+            }
+            Interrupt::Shutdown => {
+                info!("shutting down, logging out");
+                context.notify_with(Notifier::stopping);
+                context.logout(&mut supervisor)?;
+                context.hooks.wait();
+                return Ok(0);
+            }
+            Interrupt::Control(Command::Logout) => {
+                if context.logout(&mut supervisor)? {
+                    context.hooks.wait();
+                    return Ok(0);
+                }
+                context.logged_out = true;
+                info!("logged out, waiting for relogin");
+            }
+            Interrupt::Control(Command::Relogin) => {
+                if context.logout(&mut supervisor)? {
+                    context.hooks.wait();
+                    return Ok(0);
+                }
+                context.logged_out = false;
+                supervisor.start(Instant::now());
+            }
+            Interrupt::Control(Command::Status) => {}
+            Interrupt::Reload | Interrupt::Control(Command::Reload) => {
+                info!("reloading configuration");
+                match reload(args, &password) {
+                    Ok((config, new_supervisor, new_remote_server, new_password)) => {
+                        context.notify_with(Notifier::reloading);
+                        if context.logout(&mut supervisor)? {
+                            context.hooks.wait();
+                            return Ok(0);
                         }
+                        config.hooks.apply(&mut context.hooks);
+                        context.prober = config.probe.prober();
+                        context.monitor = config.interface.as_deref().and_then(watch_interface);
+                        context.system_root = system_root(&config);
+                        context.rebind(config.socket_options());
+                        context.account = config.account.clone();
+                        (supervisor, password) = (new_supervisor, new_password);
+                        context.remote_server = new_remote_server;
+                        context.ready = false;
+                        context.logged_out = false;
+                        supervisor.start(Instant::now());
+                    }
+                    Err(e) => {
+                        error!(error:% = e; "reload failed, keeping the current configuration")
                     }
                 }
             }
+        }
+    }
+}
 
-            i = (i+2) % 0x7F;
+/// `drcom-rs ctl <command>`, prints the response as JSON.
+fn ctl(control_socket: &Path, command: Command) -> i32 {
+    match control::request(control_socket, command) {
+        Ok(mut response) => {
+            if let Some(response) = response.as_object_mut() {
+                response.remove("ok");
+                // commands other than status only acknowledge
+                if response.is_empty() {
+                    return 0;
+                }
+            }
+            println!("{}", serde_json::to_string_pretty(&response).unwrap_or_default());
+            0
+        }
+        Err(ControlError::IOError(e)) => {
+            eprintln!("cannot reach {}: {}", control_socket.display(), e);
+            1
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
         }
     }
+}
 
+fn main() {
+    let mut args = Args::parse();
+    // daemonizing moves to `/` and a reload reads the config again
+    for path in [&mut args.config, &mut args.pidfile, &mut args.log_file]
+        .into_iter()
+        .flatten()
+    {
+        *path = path::absolute(&*path).unwrap_or_else(|_| path.clone());
+    }
 
+    let config = load_config(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2)
+    });
+    let control_socket = config
+        .control_socket
+        .clone()
+        .map_or_else(control::default_path, |path| {
+            path::absolute(&path).unwrap_or(path)
+        });
+    if let Some(CliCommand::Ctl { command }) = args.command {
+        process::exit(ctl(&control_socket, command))
+    }
 
+    let logger = Logger::new(
+        config.log_level.unwrap_or(LevelFilter::Info),
+        config.log_format.unwrap_or(LogFormat::Text),
+    );
+    logger.init().expect("logger is only installed once");
 
-    
+    let password = config
+        .password
+        .clone()
+        .map_or_else(PasswordSource::discover, Ok)
+        .and_then(|source| source.read())
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2)
+        });
+    let (supervisor, remote_server) = build_supervisor(&config, &password).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2)
+    });
 
+    // lock the pidfile while errors still reach the terminal, the lock survives the forks
+    let pidfile = args.pidfile.as_ref().map(Pidfile::create).transpose().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2)
+    });
+    if args.daemon {
+        if let Err(e) = daemon::daemonize(args.log_file.as_deref()) {
+            eprintln!("cannot detach: {}", e);
+            process::exit(1)
+        }
+        if let Some(Err(e)) = pidfile.as_ref().map(Pidfile::write_pid) {
+            error!(error:% = e; "cannot start");
+            drop(pidfile);
+            process::exit(2)
+        }
+    }
+    let code = run(&args, &config, &control_socket, supervisor, remote_server, password)
+        .unwrap_or_else(|e| {
+            error!(error:% = e; "socket error");
+            1
+        });
+    drop(pidfile);
+    process::exit(code)
 }
@@ -0,0 +1,381 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::drcom::wired::retransmit::Step;
+use crate::drcom::wired::session::SessionEvent;
+use crate::drcom::wired::supervisor::{SupervisorEvent, SupervisorState};
+
+/// Round trip buckets in seconds, campus servers answer within milliseconds
+/// unless something is wrong.
+const RTT_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+/// How long a scraper gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative, the last one is `+Inf`.
+    counts: Vec<u64>,
+    sum:    f64,
+    count:  u64,
+}
+
+/// Counters and gauges fed from supervisor events, rendered in the
+/// Prometheus text exposition format.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    state:             SupervisorState,
+    login_attempts:    u64,
+    login_successes:   u64,
+    login_failures:    BTreeMap<&'static str, u64>,
+    keep_alive1_rtt:   Histogram,
+    keep_alive2_rtt:   Histogram,
+    heartbeats:        u64,
+    missed_heartbeats: u64,
+    relogins:          BTreeMap<&'static str, u64>,
+    dropped_packets:   BTreeMap<&'static str, u64>,
+    used_time_minutes: Option<u32>,
+    used_traffic_kb:   Option<u32>,
+    balance:           Option<u32>,
+}
+
+/// Plain HTTP listener answering `GET /metrics` from its own thread, so that a
+/// slow scraper cannot hold up the heartbeat loop.
+#[derive(Debug)]
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            state: SupervisorState::Idle,
+            login_attempts: 0,
+            login_successes: 0,
+            login_failures: BTreeMap::new(),
+            keep_alive1_rtt: Histogram::new(&RTT_BUCKETS),
+            keep_alive2_rtt: Histogram::new(&RTT_BUCKETS),
+            heartbeats: 0,
+            missed_heartbeats: 0,
+            relogins: BTreeMap::new(),
+            dropped_packets: BTreeMap::new(),
+            used_time_minutes: None,
+            used_traffic_kb: None,
+            balance: None,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, event: &SupervisorEvent) {
+        match event {
+            SupervisorEvent::Session(event) => self.observe_session(event),
+            SupervisorEvent::StateChanged { to, .. } => self.state = *to,
+            SupervisorEvent::ReloginScheduled { reason, .. } => {
+                *self.relogins.entry(reason.name()).or_default() += 1
+            }
+        }
+    }
+
+    fn observe_session(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::LoggedIn(response) => {
+                self.login_attempts += 1;
+                self.login_successes += 1;
+                self.used_time_minutes = Some(response.used_time_minutes);
+                self.used_traffic_kb = Some(response.used_traffic_kb);
+                self.balance = Some(response.balance);
+            }
+            SessionEvent::LoginFailed(failure) => self.login_failed(failure.name()),
+            SessionEvent::HostIPUnknown => self.login_failed("host_ip_unknown"),
+            // the logout handshake uses the same steps
+            SessionEvent::ResponseTimeout {
+                step: Step::Challenge | Step::Login,
+                ..
+            } if self.state != SupervisorState::Stopping => self.login_failed("timeout"),
+            SessionEvent::RoundTrip { step, rtt } => match step {
+                Step::KeepAliveOne => self.keep_alive1_rtt.observe(rtt.as_secs_f64()),
+                Step::KeepAliveTwoFirst | Step::KeepAliveTwoSecond | Step::KeepAliveTwoThird => {
+                    self.keep_alive2_rtt.observe(rtt.as_secs_f64())
+                }
+                Step::Challenge | Step::Login => {}
+            },
+            SessionEvent::HeartbeatCompleted { .. } => self.heartbeats += 1,
+            SessionEvent::HeartbeatMissed { .. } => self.missed_heartbeats += 1,
+            SessionEvent::PacketDropped { reason, .. } => self.packet_dropped(reason.name()),
+            _ => {}
+        }
+    }
+
+    /// Count a datagram ignored before it reached the session, e.g. from a foreign source.
+    pub fn packet_dropped(&mut self, reason: &'static str) {
+        *self.dropped_packets.entry(reason).or_default() += 1;
+    }
+
+    fn login_failed(&mut self, reason: &'static str) {
+        self.login_attempts += 1;
+        *self.login_failures.entry(reason).or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut rendered = String::new();
+        let out = &mut rendered;
+        let online = u8::from(self.state == SupervisorState::Online);
+        let ka1 = "drcom_keep_alive1_rtt_seconds";
+        let ka2 = "drcom_keep_alive2_rtt_seconds";
+
+        let attempts = "Logins that got an answer or timed out.";
+        single(out, "drcom_login_attempts_total", "counter", attempts, self.login_attempts);
+        let successes = "Logins accepted by the server.";
+        single(out, "drcom_login_successes_total", "counter", successes, self.login_successes);
+        let failures = "Failed logins by reason.";
+        labelled(out, "drcom_login_failures_total", failures, &self.login_failures);
+        header(out, ka1, "histogram", "Round trip time of keep_alive1.");
+        self.keep_alive1_rtt.render(out, ka1);
+        header(out, ka2, "histogram", "Round trip time of keep_alive2.");
+        self.keep_alive2_rtt.render(out, ka2);
+        let heartbeats = "Completed heartbeat rounds.";
+        single(out, "drcom_heartbeats_total", "counter", heartbeats, self.heartbeats);
+        let missed = "Heartbeat rounds without a response.";
+        single(out, "drcom_missed_heartbeats_total", "counter", missed, self.missed_heartbeats);
+        labelled(out, "drcom_relogins_total", "Scheduled re-logins by reason.", &self.relogins);
+        let dropped = "Received datagrams ignored by reason.";
+        labelled(out, "drcom_dropped_packets_total", dropped, &self.dropped_packets);
+        single(out, "drcom_online", "gauge", "Whether the session is online.", online);
+
+        // only known once the server told us on login
+        let usage = [
+            ("drcom_account_used_time_minutes", "Online time", self.used_time_minutes),
+            ("drcom_account_used_traffic_kibibytes", "Traffic", self.used_traffic_kb),
+            ("drcom_account_balance", "Balance", self.balance),
+        ];
+        for (name, what, value) in usage {
+            if let Some(value) = value {
+                let help = format!("{} of the account as of the last login.", what);
+                single(out, name, "gauge", &help, value);
+            }
+        }
+        rendered
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn single<T: fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// A counter with one `reason` label, series appear once they are non-zero.
+fn labelled(out: &mut String, name: &str, help: &str, counts: &BTreeMap<&'static str, u64>) {
+    header(out, name, "counter", help);
+    for (reason, count) in counts.iter() {
+        let _ = writeln!(out, "{}{{reason=\"{}\"}} {}", name, reason, count);
+    }
+}
+
+impl MetricsServer {
+    /// Listen on `address` and answer scrapes with what `metrics` holds at the time.
+    pub fn spawn(address: SocketAddr, metrics: Arc<Mutex<Metrics>>) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let local_addr = listener.local_addr()?;
+        thread::Builder::new()
+            .name(String::from("metrics"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        // one broken scraper should not keep the others waiting
+                        Ok(stream) => drop(respond(stream, &metrics)),
+                        Err(e) => {
+                            warn!(error:% = e; "cannot serve metrics");
+                            thread::sleep(REQUEST_TIMEOUT);
+                        }
+                    }
+                }
+            })?;
+        Ok(MetricsServer { local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+fn respond(stream: TcpStream, metrics: &Mutex<Metrics>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            // the heartbeat loop only waits for the rendering, never for the scraper
+            metrics.lock().unwrap_or_else(PoisonError::into_inner).render(),
+        ),
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", String::from("not found\n")),
+        _ => ("405 Method Not Allowed", "text/plain", String::from("method not allowed\n")),
+    };
+    let stream = reader.get_mut();
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drcom::wired::dialer::LoginFailure;
+    use crate::drcom::wired::session::{DropReason, SessionState};
+    use crate::drcom::wired::supervisor::ReloginReason;
+    use std::io::Read;
+
+    fn events() -> Vec<SupervisorEvent> {
+        vec![
+            SupervisorEvent::Session(SessionEvent::LoginFailed(LoginFailure::WrongPassword)),
+            SupervisorEvent::ReloginScheduled {
+                attempt: 1,
+                delay:   Duration::from_secs(5),
+                reason:  ReloginReason::LoginFailed,
+            },
+            SupervisorEvent::Session(SessionEvent::ResponseTimeout {
+                step:     Step::Challenge,
+                attempts: 3,
+            }),
+            SupervisorEvent::StateChanged {
+                from: SupervisorState::Connecting,
+                to:   SupervisorState::Online,
+            },
+            SupervisorEvent::Session(SessionEvent::RoundTrip {
+                step: Step::KeepAliveOne,
+                rtt:  Duration::from_millis(8),
+            }),
+            SupervisorEvent::Session(SessionEvent::RoundTrip {
+                step: Step::KeepAliveTwoThird,
+                rtt:  Duration::from_secs(10),
+            }),
+            SupervisorEvent::Session(SessionEvent::HeartbeatMissed { missed: 1 }),
+            SupervisorEvent::Session(SessionEvent::HeartbeatCompleted { sequence: 3 }),
+            SupervisorEvent::Session(SessionEvent::PacketDropped {
+                state:  SessionState::HeartbeatTwoFirst,
+                reason: DropReason::UnexpectedSequence {
+                    expected: 3,
+                    received: 9,
+                },
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_metrics_render() {
+        let mut metrics = Metrics::new();
+        for event in events().iter() {
+            metrics.observe(event);
+        }
+        metrics.packet_dropped("foreign_source");
+        let rendered = metrics.render();
+        for line in [
+            "drcom_login_attempts_total 2",
+            "drcom_login_successes_total 0",
+            "drcom_login_failures_total{reason=\"timeout\"} 1",
+            "drcom_login_failures_total{reason=\"wrong_password\"} 1",
+            "drcom_keep_alive1_rtt_seconds_bucket{le=\"0.005\"} 0",
+            "drcom_keep_alive1_rtt_seconds_bucket{le=\"0.01\"} 1",
+            "drcom_keep_alive1_rtt_seconds_bucket{le=\"+Inf\"} 1",
+            "drcom_keep_alive2_rtt_seconds_bucket{le=\"5\"} 0",
+            "drcom_keep_alive2_rtt_seconds_bucket{le=\"+Inf\"} 1",
+            "drcom_keep_alive2_rtt_seconds_sum 10",
+            "drcom_heartbeats_total 1",
+            "drcom_missed_heartbeats_total 1",
+            "drcom_relogins_total{reason=\"login_failed\"} 1",
+            "drcom_dropped_packets_total{reason=\"foreign_source\"} 1",
+            "drcom_dropped_packets_total{reason=\"unexpected_sequence\"} 1",
+            "drcom_online 1",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {}", line);
+        }
+        assert!(!rendered.contains("drcom_account_balance"));
+    }
+
+    #[test]
+    fn test_metrics_server() {
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let server =
+            MetricsServer::spawn("127.0.0.1:0".parse().unwrap(), Arc::clone(&metrics)).unwrap();
+        let address = server.local_addr();
+
+        // a scraper that never sends its request leaves the metrics free to update
+        let silent = TcpStream::connect(address).unwrap();
+        metrics.lock().unwrap().packet_dropped("foreign_source");
+        drop(silent);
+
+        let scrape = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = scrape("/metrics");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP drcom_login_attempts_total"));
+        assert!(response.contains("\ndrcom_dropped_packets_total{reason=\"foreign_source\"} 1\n"));
+        assert!(scrape("/").starts_with("HTTP/1.0 404"));
+    }
+}
@@ -0,0 +1,556 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+const NLMSG_HEADER_LEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const RTATTR_HEADER_LEN: usize = 4;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+
+const IFLA_IFNAME: u16 = 3;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_LABEL: u16 = 3;
+const IFA_F_SECONDARY: u8 = 0x01;
+
+const RTMGRP_LINK: u32 = 0x01;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+
+#[derive(Debug)]
+pub enum NetlinkError {
+    IOError(io::Error),
+    Malformed(String),
+}
+
+type NetlinkResult<T> = Result<T, NetlinkError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub index: u32,
+    pub name:  Option<String>,
+    /// Administratively up and operationally running, i.e. able to carry traffic.
+    pub up:    bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressInfo {
+    pub index:      u32,
+    pub address:    Ipv4Addr,
+    pub prefix_len: u8,
+    /// Not the primary address of its subnet on the interface.
+    pub secondary:  bool,
+    pub label:      Option<String>,
+}
+
+/// The rtnetlink notifications we care about, IPv6 ones are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetlinkMessage {
+    LinkChanged(LinkInfo),
+    LinkRemoved(LinkInfo),
+    AddressAdded(AddressInfo),
+    AddressRemoved(AddressInfo),
+}
+
+/// What happened to the watched interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkChange {
+    LinkDown,
+    LinkUp,
+    AddressChanged {
+        from: Option<Ipv4Addr>,
+        to:   Ipv4Addr,
+    },
+    AddressRemoved(Ipv4Addr),
+}
+
+/// What we know about one interface, kept up to date from netlink messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceState {
+    pub name:    String,
+    pub index:   Option<u32>,
+    pub up:      Option<bool>,
+    /// The primary IPv4 address.
+    pub address: Option<Ipv4Addr>,
+}
+
+/// A `NETLINK_ROUTE` socket subscribed to link and IPv4 address changes,
+/// reporting those of a single interface.
+#[derive(Debug)]
+pub struct NetworkMonitor {
+    socket:    OwnedFd,
+    interface: InterfaceState,
+    buffer:    Vec<u8>,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+/// Route attributes as `(type, payload)` pairs.
+fn attributes(mut bytes: &[u8]) -> NetlinkResult<Vec<(u16, &[u8])>> {
+    let mut attributes = Vec::new();
+    while bytes.len() >= RTATTR_HEADER_LEN {
+        let length = u16_at(bytes, 0) as usize;
+        if length < RTATTR_HEADER_LEN || length > bytes.len() {
+            return Err(NetlinkError::Malformed(format!("attribute length {}", length)));
+        }
+        // the nested flag is of no interest here
+        attributes.push((u16_at(bytes, 2) & 0x3fff, &bytes[RTATTR_HEADER_LEN..length]));
+        bytes = &bytes[align(length).min(bytes.len())..];
+    }
+    Ok(attributes)
+}
+
+fn string_attribute(payload: &[u8]) -> Option<String> {
+    CStr::from_bytes_until_nul(payload)
+        .ok()
+        .and_then(|s| s.to_str().ok())
+        .map(str::to_string)
+}
+
+fn ipv4_attribute(payload: &[u8]) -> Option<Ipv4Addr> {
+    <[u8; 4]>::try_from(payload).ok().map(Ipv4Addr::from)
+}
+
+fn parse_link(body: &[u8]) -> NetlinkResult<LinkInfo> {
+    if body.len() < IFINFOMSG_LEN {
+        return Err(NetlinkError::Malformed(String::from("truncated ifinfomsg")));
+    }
+    let flags = u32_at(body, 8) as libc::c_int;
+    let name = attributes(&body[IFINFOMSG_LEN..])?
+        .into_iter()
+        .find(|(kind, _)| *kind == IFLA_IFNAME)
+        .and_then(|(_, payload)| string_attribute(payload));
+    Ok(LinkInfo {
+        index: u32_at(body, 4),
+        name,
+        up: flags & libc::IFF_UP != 0 && flags & libc::IFF_RUNNING != 0,
+    })
+}
+
+/// `None` for anything but IPv4.
+fn parse_address(body: &[u8]) -> NetlinkResult<Option<AddressInfo>> {
+    if body.len() < IFADDRMSG_LEN {
+        return Err(NetlinkError::Malformed(String::from("truncated ifaddrmsg")));
+    }
+    if body[0] as libc::c_int != libc::AF_INET {
+        return Ok(None);
+    }
+    let (mut local, mut address, mut label) = (None, None, None);
+    for (kind, payload) in attributes(&body[IFADDRMSG_LEN..])? {
+        match kind {
+            IFA_LOCAL => local = ipv4_attribute(payload),
+            IFA_ADDRESS => address = ipv4_attribute(payload),
+            IFA_LABEL => label = string_attribute(payload),
+            _ => {}
+        }
+    }
+    // IFA_ADDRESS is the peer on point-to-point links, IFA_LOCAL is always ours
+    Ok(local.or(address).map(|address| AddressInfo {
+        index: u32_at(body, 4),
+        address,
+        prefix_len: body[1],
+        secondary: body[2] & IFA_F_SECONDARY != 0,
+        label,
+    }))
+}
+
+/// Parse the messages of one netlink datagram, skipping the kinds we do not handle.
+pub fn parse_messages(mut bytes: &[u8]) -> NetlinkResult<Vec<NetlinkMessage>> {
+    let mut messages = Vec::new();
+    while bytes.len() >= NLMSG_HEADER_LEN {
+        let length = u32_at(bytes, 0) as usize;
+        if length < NLMSG_HEADER_LEN || length > bytes.len() {
+            return Err(NetlinkError::Malformed(format!("message length {}", length)));
+        }
+        let body = &bytes[NLMSG_HEADER_LEN..length];
+        let message = match u16_at(bytes, 4) {
+            RTM_NEWLINK => Some(NetlinkMessage::LinkChanged(parse_link(body)?)),
+            RTM_DELLINK => Some(NetlinkMessage::LinkRemoved(parse_link(body)?)),
+            RTM_NEWADDR => parse_address(body)?.map(NetlinkMessage::AddressAdded),
+            RTM_DELADDR => parse_address(body)?.map(NetlinkMessage::AddressRemoved),
+            // NLMSG_DONE, NLMSG_ERROR and whatever else we did not subscribe to
+            _ => None,
+        };
+        messages.extend(message);
+        bytes = &bytes[align(length).min(bytes.len())..];
+    }
+    Ok(messages)
+}
+
+impl InterfaceState {
+    /// Nothing known yet, as for an interface that does not exist.
+    pub fn new(name: &str) -> Self {
+        InterfaceState {
+            name:    name.to_string(),
+            index:   None,
+            up:      None,
+            address: None,
+        }
+    }
+
+    /// Look up the current state of `name` with `getifaddrs`.
+    pub fn query(name: &str) -> io::Result<Self> {
+        let mut state = InterfaceState::new(name);
+        let c_name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name with NUL"))?;
+        state.index = match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+            0 => None,
+            index => Some(index),
+        };
+
+        let mut addresses: *mut libc::ifaddrs = std::ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut addresses) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut cursor = addresses;
+        while let Some(entry) = unsafe { cursor.as_ref() } {
+            cursor = entry.ifa_next;
+            if unsafe { CStr::from_ptr(entry.ifa_name) } != c_name.as_c_str() {
+                continue;
+            }
+            let flags = entry.ifa_flags as libc::c_int;
+            state.up = Some(flags & libc::IFF_UP != 0 && flags & libc::IFF_RUNNING != 0);
+            let address = match unsafe { entry.ifa_addr.as_ref() } {
+                Some(address) if address.sa_family as libc::c_int == libc::AF_INET => {
+                    unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) }
+                }
+                _ => continue,
+            };
+            // the primary address comes first
+            if state.address.is_none() {
+                state.address = Some(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)));
+            }
+        }
+        unsafe { libc::freeifaddrs(addresses) };
+        Ok(state)
+    }
+
+    fn is_ours(&self, index: u32, name: Option<&str>) -> bool {
+        self.index == Some(index) || name == Some(self.name.as_str())
+    }
+
+    /// Update from `message`, returns what changed for this interface.
+    pub fn apply(&mut self, message: &NetlinkMessage) -> Option<NetworkChange> {
+        match message {
+            NetlinkMessage::LinkChanged(link) if self.is_ours(link.index, link.name.as_deref()) => {
+                self.index = Some(link.index);
+                let was_up = self.up.replace(link.up);
+                match (was_up, link.up) {
+                    (Some(true), true) | (Some(false), false) => None,
+                    (_, true) => Some(NetworkChange::LinkUp),
+                    (_, false) => Some(NetworkChange::LinkDown),
+                }
+            }
+            NetlinkMessage::LinkRemoved(link) if self.is_ours(link.index, link.name.as_deref()) => {
+                self.index = None;
+                self.address = None;
+                match self.up.replace(false) {
+                    Some(false) => None,
+                    _ => Some(NetworkChange::LinkDown),
+                }
+            }
+            NetlinkMessage::AddressAdded(address)
+                if !address.secondary && self.is_ours(address.index, address.label.as_deref()) =>
+            {
+                // a DHCP renew announces the same address again
+                let from = self.address.replace(address.address);
+                (from != Some(address.address)).then_some(NetworkChange::AddressChanged {
+                    from,
+                    to: address.address,
+                })
+            }
+            NetlinkMessage::AddressRemoved(address)
+                if self.address == Some(address.address)
+                    && self.is_ours(address.index, address.label.as_deref()) =>
+            {
+                self.address = None;
+                Some(NetworkChange::AddressRemoved(address.address))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl NetworkMonitor {
+    /// Subscribe to changes of `interface`, which does not need to exist yet.
+    pub fn new(interface: &str) -> NetlinkResult<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd == -1 {
+            return Err(NetlinkError::IOError(io::Error::last_os_error()));
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = RTMGRP_LINK | RTMGRP_IPV4_IFADDR;
+        let bound = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound == -1 {
+            return Err(NetlinkError::IOError(io::Error::last_os_error()));
+        }
+
+        // query after subscribing so that no change falls in between
+        let interface = InterfaceState::query(interface).map_err(NetlinkError::IOError)?;
+        Ok(NetworkMonitor {
+            socket,
+            interface,
+            buffer: vec![0u8; 64 * 1024],
+        })
+    }
+
+    pub fn interface(&self) -> &InterfaceState {
+        &self.interface
+    }
+
+    /// Read every pending notification, never blocks.
+    pub fn poll(&mut self) -> NetlinkResult<Vec<NetworkChange>> {
+        let mut changes = Vec::new();
+        loop {
+            let length = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    self.buffer.as_mut_ptr() as *mut libc::c_void,
+                    self.buffer.len(),
+                    0,
+                )
+            };
+            if length < 0 {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    io::ErrorKind::WouldBlock => Ok(changes),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => Err(NetlinkError::IOError(e)),
+                };
+            }
+            for message in parse_messages(&self.buffer[..length as usize])? {
+                changes.extend(self.interface.apply(&message));
+            }
+        }
+    }
+}
+
+impl AsRawFd for NetworkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl fmt::Display for NetworkChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkChange::LinkDown => f.write_str("link down"),
+            NetworkChange::LinkUp => f.write_str("link up"),
+            NetworkChange::AddressChanged { from: Some(from), to } => {
+                write!(f, "address changed from {} to {}", from, to)
+            }
+            NetworkChange::AddressChanged { from: None, to } => write!(f, "address {} added", to),
+            NetworkChange::AddressRemoved(address) => write!(f, "address {} removed", address),
+        }
+    }
+}
+
+impl fmt::Display for NetlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetlinkError::IOError(e) => write!(f, "{}", e),
+            NetlinkError::Malformed(reason) => write!(f, "malformed netlink message: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for NetlinkError {}
+
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+
+    /// Captured with `ip` on a veth pair drtest0 (index 6) and drtest1 (index 5),
+    /// link messages trimmed after IFLA_LINKMODE.
+    const DRTEST1_LINK_UP: &str = concat!(
+        "4c00000010000000000000000000000000000100050000004310010000000000",
+        "0c000300647274657374310008000d00e8030000050010000600000005001100",
+        "000000000500430000000000"
+    );
+    /// Administratively up but not running yet.
+    const DRTEST0_LINK_LOWER_UP: &str = concat!(
+        "4c00000010000000000000000000000000000100060000000310010001000000",
+        "0c000300647274657374300008000d00e8030000050010000300000005001100",
+        "000000000500430000000000"
+    );
+    const DRTEST0_LINK_UP: &str = concat!(
+        "4c00000010000000000000000000000000000100060000004310010000000000",
+        "0c000300647274657374300008000d00e8030000050010000600000005001100",
+        "000000000500430000000000"
+    );
+    const DRTEST0_LINK_DOWN: &str = concat!(
+        "4c00000010000000000000000000000000000100060000000210000001000000",
+        "0c000300647274657374300008000d00e8030000050010000200000005001100",
+        "000000000500430000000000"
+    );
+    /// 10.30.22.17/24 on drtest0.
+    const DRTEST0_NEWADDR_PRIMARY: &str = concat!(
+        "5000000014000000a2aad46a350600000218800006000000080001000a1e1611",
+        "080002000a1e16110c0003006472746573743000080008008000000014000600",
+        "ffffffffffffffff2482050024820500"
+    );
+    /// 10.30.22.18/24 on drtest0, secondary to the one above.
+    const DRTEST0_NEWADDR_SECONDARY: &str = concat!(
+        "5000000014000000a2aad46a370600000218810006000000080001000a1e1612",
+        "080002000a1e16120c0003006472746573743000080008008100000014000600",
+        "ffffffffffffffff4382050043820500"
+    );
+    const DRTEST0_DELADDR_SECONDARY: &str = concat!(
+        "5000000015000000a2aad46a390600000218810006000000080001000a1e1612",
+        "080002000a1e16120c0003006472746573743000080008008100000014000600",
+        "ffffffffffffffff4382050043820500"
+    );
+    const DRTEST0_DELADDR_PRIMARY: &str = concat!(
+        "5000000015000000a2aad46a390600000218800006000000080001000a1e1611",
+        "080002000a1e16110c0003006472746573743000080008008000000014000600",
+        "ffffffffffffffff2482050024820500"
+    );
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn message(hex: &str) -> NetlinkMessage {
+        let mut messages = parse_messages(&bytes(hex)).unwrap();
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    #[test]
+    fn test_netlink_parse() {
+        assert_eq!(
+            message(DRTEST0_LINK_LOWER_UP),
+            NetlinkMessage::LinkChanged(LinkInfo {
+                index: 6,
+                name:  Some(String::from("drtest0")),
+                up:    false,
+            })
+        );
+        assert_eq!(
+            message(DRTEST0_LINK_UP),
+            NetlinkMessage::LinkChanged(LinkInfo {
+                index: 6,
+                name:  Some(String::from("drtest0")),
+                up:    true,
+            })
+        );
+        assert_eq!(
+            message(DRTEST0_NEWADDR_SECONDARY),
+            NetlinkMessage::AddressAdded(AddressInfo {
+                index:      6,
+                address:    Ipv4Addr::new(10, 30, 22, 18),
+                prefix_len: 24,
+                secondary:  true,
+                label:      Some(String::from("drtest0")),
+            })
+        );
+        assert!(matches!(
+            message(DRTEST0_DELADDR_PRIMARY),
+            NetlinkMessage::AddressRemoved(AddressInfo { secondary: false, .. })
+        ));
+
+        // several messages in one datagram
+        let mut batch = bytes(DRTEST1_LINK_UP);
+        batch.extend(bytes(DRTEST0_NEWADDR_PRIMARY));
+        assert_eq!(parse_messages(&batch).unwrap().len(), 2);
+
+        let mut truncated = bytes(DRTEST0_NEWADDR_PRIMARY);
+        truncated.truncate(40);
+        assert!(matches!(
+            parse_messages(&truncated),
+            Err(NetlinkError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_interface_state() {
+        let mut interface = InterfaceState::new("drtest0");
+        let changes: Vec<Option<NetworkChange>> = [
+            DRTEST1_LINK_UP,
+            DRTEST0_LINK_LOWER_UP,
+            DRTEST0_LINK_UP,
+            DRTEST0_NEWADDR_PRIMARY,
+            DRTEST0_NEWADDR_SECONDARY,
+            // a renew announces the address again
+            DRTEST0_NEWADDR_PRIMARY,
+            DRTEST0_DELADDR_SECONDARY,
+            DRTEST0_DELADDR_PRIMARY,
+            DRTEST0_LINK_DOWN,
+        ]
+        .iter()
+        .map(|hex| interface.apply(&message(hex)))
+        .collect();
+
+        let address = Ipv4Addr::new(10, 30, 22, 17);
+        assert_eq!(
+            changes,
+            vec![
+                None,
+                Some(NetworkChange::LinkDown),
+                Some(NetworkChange::LinkUp),
+                Some(NetworkChange::AddressChanged {
+                    from: None,
+                    to:   address,
+                }),
+                None,
+                None,
+                None,
+                Some(NetworkChange::AddressRemoved(address)),
+                Some(NetworkChange::LinkDown),
+            ]
+        );
+        assert_eq!(interface.index, Some(6));
+        assert_eq!(interface.up, Some(false));
+    }
+
+    #[test]
+    fn test_interface_query() {
+        let loopback = InterfaceState::query("lo").unwrap();
+        assert!(loopback.index.is_some());
+        assert_eq!(loopback.address, Some(Ipv4Addr::LOCALHOST));
+        assert_eq!(
+            InterfaceState::query("drcom-missing").unwrap(),
+            InterfaceState::new("drcom-missing")
+        );
+    }
+}
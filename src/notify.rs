@@ -0,0 +1,169 @@
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+/// Client side of the systemd `sd_notify` protocol, newline separated
+/// `KEY=VALUE` assignments sent as datagrams to `$NOTIFY_SOCKET`.
+#[derive(Debug)]
+pub struct Notifier {
+    socket:            UnixDatagram,
+    watchdog_interval: Option<Duration>,
+    last_watchdog:     Option<Instant>,
+}
+
+impl Notifier {
+    /// Connect to `$NOTIFY_SOCKET`, `None` when not started by systemd with `Type=notify`.
+    ///
+    /// The watchdog is enabled when `$WATCHDOG_USEC` is set and `$WATCHDOG_PID`,
+    /// if present, names this process.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let path = match env::var_os(NOTIFY_SOCKET_ENV) {
+            Some(path) if !path.is_empty() => path,
+            _ => return Ok(None),
+        };
+        let mut notifier = Notifier::connect(path)?;
+
+        let for_us = env::var(WATCHDOG_PID_ENV)
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_none_or(|pid| pid == process::id());
+        let interval = env::var(WATCHDOG_USEC_ENV)
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|&usec| usec > 0 && for_us)
+            .map(Duration::from_micros);
+        notifier.watchdog_interval(interval);
+        Ok(Some(notifier))
+    }
+
+    /// Connect to a notify socket, a leading `@` names one in the abstract namespace.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::unbound()?;
+        match path.to_str().and_then(|p| p.strip_prefix('@')) {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+                socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?
+            }
+            _ => socket.connect(path)?,
+        }
+        Ok(Notifier {
+            socket,
+            watchdog_interval: None,
+            last_watchdog: None,
+        })
+    }
+
+    /// How often systemd expects `WATCHDOG=1`, `None` disables the watchdog.
+    pub fn watchdog_interval(&mut self, value: Option<Duration>) -> &mut Self {
+        self.watchdog_interval = value;
+        self
+    }
+
+    pub fn notify(&self, assignments: &[(&str, &str)]) -> io::Result<()> {
+        let message: Vec<String> = assignments
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        self.socket.send(message.join("\n").as_bytes()).map(|_| ())
+    }
+
+    /// Startup finished, only meaningful the first time and after a reload.
+    pub fn ready(&self) -> io::Result<()> {
+        self.notify(&[("READY", "1")])
+    }
+
+    pub fn status(&self, status: &str) -> io::Result<()> {
+        self.notify(&[("STATUS", status)])
+    }
+
+    pub fn reloading(&self) -> io::Result<()> {
+        self.notify(&[("RELOADING", "1")])
+    }
+
+    pub fn stopping(&self) -> io::Result<()> {
+        self.notify(&[("STOPPING", "1")])
+    }
+
+    /// Whether the watchdog wants a ping, which is at half its interval to leave some slack.
+    pub fn watchdog_due(&self, now: Instant) -> bool {
+        match (self.watchdog_interval, self.last_watchdog) {
+            (Some(_), None) => true,
+            (Some(interval), Some(last)) => now >= last + interval / 2,
+            (None, _) => false,
+        }
+    }
+
+    /// Send `WATCHDOG=1` if it is due, returns whether it was sent.
+    pub fn watchdog(&mut self, now: Instant) -> io::Result<bool> {
+        if !self.watchdog_due(now) {
+            return Ok(false);
+        }
+        self.last_watchdog = Some(now);
+        self.notify(&[("WATCHDOG", "1")]).map(|_| true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0u8; 256];
+        let length = socket.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..length].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_notifier() {
+        let path = env::temp_dir().join(format!("drcom-rs-notify-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        systemd.set_nonblocking(true).unwrap();
+
+        let mut notifier = Notifier::connect(&path).unwrap();
+        notifier.ready().unwrap();
+        assert_eq!(receive(&systemd), "READY=1");
+        notifier
+            .notify(&[("STATUS", "online, seq 0x2a"), ("READY", "1")])
+            .unwrap();
+        assert_eq!(receive(&systemd), "STATUS=online, seq 0x2a\nREADY=1");
+
+        // no watchdog unless systemd asked for one
+        let now = Instant::now();
+        assert!(!notifier.watchdog(now).unwrap());
+        notifier.watchdog_interval(Some(Duration::from_secs(10)));
+        assert!(notifier.watchdog(now).unwrap());
+        assert_eq!(receive(&systemd), "WATCHDOG=1");
+        assert!(!notifier.watchdog(now + Duration::from_secs(4)).unwrap());
+        assert!(notifier.watchdog_due(now + Duration::from_secs(5)));
+        assert!(notifier.watchdog(now + Duration::from_secs(5)).unwrap());
+        assert_eq!(receive(&systemd), "WATCHDOG=1");
+        assert!(systemd.recv(&mut [0u8; 16]).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_notifier_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("drcom-rs-notify-{}", process::id());
+        let systemd =
+            UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        Notifier::connect(format!("@{}", name)).unwrap().stopping().unwrap();
+        assert_eq!(receive(&systemd), "STOPPING=1");
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::http;
+
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_FAILURES: u32 = 2;
+/// What the usual connectivity check URLs answer when nothing is in the way.
+const NO_CONTENT: u16 = 204;
+
+/// Where to check that traffic actually gets through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeTarget {
+    /// `GET` a URL answering 204 No Content, e.g. `http://www.gstatic.com/generate_204`.
+    Http { authority: String, path: String },
+    /// Open a TCP connection to `host:port`.
+    Tcp(String),
+}
+
+#[derive(Debug)]
+pub enum ProbeOutcome {
+    Reachable,
+    /// Answered with something other than 204, most likely the captive portal.
+    Intercepted {
+        status:   u16,
+        location: Option<String>,
+    },
+    Unreachable(io::Error),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProbeError {
+    InvalidTarget(String),
+    UnsupportedScheme(String),
+}
+
+type ProbeParseResult<T> = Result<T, ProbeError>;
+
+/// What a finished check amounts to.
+#[derive(Debug)]
+pub struct ProbeResult {
+    pub outcome:  ProbeOutcome,
+    /// Failed checks in a row, including this one.
+    pub failures: u32,
+    /// Whether it is time to log in again.
+    pub lost:     bool,
+}
+
+/// Checks the target every `interval` on a background thread while the
+/// session is online, polled from the main loop.
+///
+/// An interception counts as lost right away, timeouts and refused
+/// connections only after `max_failures` of them in a row.
+#[derive(Debug)]
+pub struct Prober {
+    target:       ProbeTarget,
+    interval:     Duration,
+    timeout:      Duration,
+    max_failures: u32,
+    failures:     u32,
+    next_check:   Option<Instant>,
+    pending:      Option<Receiver<ProbeOutcome>>,
+}
+
+impl ProbeTarget {
+    /// An `http://` URL, `https://` would need a TLS stack for little gain.
+    pub fn http(url: &str) -> ProbeParseResult<Self> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) => return Err(ProbeError::UnsupportedScheme(scheme.to_string())),
+            None => return Err(ProbeError::InvalidTarget(url.to_string())),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(ProbeError::InvalidTarget(url.to_string()));
+        }
+        Ok(ProbeTarget::Http {
+            authority: authority.to_string(),
+            path:      path.to_string(),
+        })
+    }
+
+    /// A `host:port` to connect to.
+    pub fn tcp(address: &str) -> ProbeParseResult<Self> {
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(ProbeTarget::Tcp(address.to_string()))
+            }
+            _ => Err(ProbeError::InvalidTarget(address.to_string())),
+        }
+    }
+
+    /// Run one blocking check, each connect, write and read gets `timeout`.
+    pub fn check(&self, timeout: Duration) -> ProbeOutcome {
+        let result = match self {
+            ProbeTarget::Http { authority, path } => resolve(authority, 80).and_then(|address| {
+                http::get(&address, authority, path, timeout).map(|response| {
+                    match response.status {
+                        NO_CONTENT => ProbeOutcome::Reachable,
+                        status => ProbeOutcome::Intercepted {
+                            status,
+                            location: response.header("Location").map(str::to_string),
+                        },
+                    }
+                })
+            }),
+            ProbeTarget::Tcp(address) => resolve(address, 0)
+                .and_then(|address| TcpStream::connect_timeout(&address, timeout))
+                .map(|_| ProbeOutcome::Reachable),
+        };
+        result.unwrap_or_else(ProbeOutcome::Unreachable)
+    }
+}
+
+fn resolve(authority: &str, default_port: u16) -> io::Result<SocketAddr> {
+    authority
+        .to_socket_addrs()
+        .or_else(|_| (authority, default_port).to_socket_addrs())?
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", authority))
+        })
+}
+
+impl FromStr for ProbeTarget {
+    type Err = ProbeError;
+
+    /// A URL for the HTTP probe, `tcp://host:port` or a bare `host:port` for the TCP one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once("://") {
+            Some((scheme, address)) if scheme.eq_ignore_ascii_case("tcp") => {
+                ProbeTarget::tcp(address)
+            }
+            Some(_) => ProbeTarget::http(s),
+            None => ProbeTarget::tcp(s),
+        }
+    }
+}
+
+impl fmt::Display for ProbeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeTarget::Http { authority, path } => write!(f, "http://{}{}", authority, path),
+            ProbeTarget::Tcp(address) => write!(f, "tcp://{}", address),
+        }
+    }
+}
+
+impl ProbeOutcome {
+    pub fn is_reachable(&self) -> bool {
+        matches!(self, ProbeOutcome::Reachable)
+    }
+}
+
+impl fmt::Display for ProbeOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeOutcome::Reachable => f.write_str("reachable"),
+            ProbeOutcome::Intercepted {
+                status,
+                location: Some(location),
+            } => write!(f, "intercepted with {} to {}", status, location),
+            ProbeOutcome::Intercepted { status, .. } => write!(f, "intercepted with {}", status),
+            ProbeOutcome::Unreachable(e) => write!(f, "unreachable: {}", e),
+        }
+    }
+}
+
+impl Prober {
+    pub fn new(target: ProbeTarget) -> Self {
+        Prober {
+            target,
+            interval: DEFAULT_PROBE_INTERVAL,
+            timeout: DEFAULT_PROBE_TIMEOUT,
+            max_failures: DEFAULT_MAX_FAILURES,
+            failures: 0,
+            next_check: None,
+            pending: None,
+        }
+    }
+
+    pub fn interval(&mut self, value: Duration) -> &mut Self {
+        self.interval = value;
+        self
+    }
+
+    pub fn timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeout = value;
+        self
+    }
+
+    pub fn max_failures(&mut self, value: u32) -> &mut Self {
+        self.max_failures = value.max(1);
+        self
+    }
+
+    pub fn target(&self) -> &ProbeTarget {
+        &self.target
+    }
+
+    /// Start checking one interval from now, nothing changes if already started.
+    pub fn resume(&mut self, now: Instant) {
+        if self.next_check.is_none() {
+            self.next_check = Some(now + self.interval);
+        }
+    }
+
+    /// Stop checking, the outcome of a check still running is thrown away.
+    pub fn pause(&mut self) {
+        self.next_check = None;
+        self.pending = None;
+        self.failures = 0;
+    }
+
+    /// Start a check when one is due and report it once it finished, never blocks.
+    pub fn poll(&mut self, now: Instant) -> Option<ProbeResult> {
+        if let Some(ref pending) = self.pending {
+            match pending.try_recv() {
+                Ok(outcome) => {
+                    self.pending = None;
+                    return Some(self.judge(outcome));
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => self.pending = None,
+            }
+        }
+
+        match self.next_check {
+            Some(at) if now >= at => {
+                let (tx, rx) = mpsc::channel();
+                let (target, timeout) = (self.target.clone(), self.timeout);
+                thread::spawn(move || tx.send(target.check(timeout)));
+                self.pending = Some(rx);
+                self.next_check = Some(now + self.interval);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn judge(&mut self, outcome: ProbeOutcome) -> ProbeResult {
+        let lost = match outcome {
+            ProbeOutcome::Reachable => {
+                self.failures = 0;
+                false
+            }
+            ProbeOutcome::Intercepted { .. } => {
+                self.failures += 1;
+                true
+            }
+            ProbeOutcome::Unreachable(_) => {
+                self.failures += 1;
+                self.failures >= self.max_failures
+            }
+        };
+        let failures = self.failures;
+        if lost {
+            self.failures = 0;
+        }
+        ProbeResult {
+            outcome,
+            failures,
+            lost,
+        }
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeError::InvalidTarget(target) => write!(
+                f,
+                "invalid probe target `{}`, expected http://<host>/<path> or <host>:<port>",
+                target
+            ),
+            ProbeError::UnsupportedScheme(scheme) => {
+                write!(f, "unsupported probe scheme `{}`, only http and tcp work", scheme)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::http::tests::stand_in_server;
+    use std::net::TcpListener;
+
+    /// Answer one probe with the canned `response`.
+    fn answer_probe(response: &'static str) -> String {
+        let (address, _) = stand_in_server(1, move |request_line| {
+            assert_eq!(request_line, "GET /generate_204 HTTP/1.0");
+            response.to_string()
+        });
+        address
+    }
+
+    fn wait_for_result(prober: &mut Prober, now: Instant) -> ProbeResult {
+        assert!(prober.poll(now).is_none());
+        loop {
+            if let Some(result) = prober.poll(now) {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_probe_target_parse() {
+        assert_eq!(
+            "http://connect.rom.miui.com/generate_204".parse(),
+            Ok(ProbeTarget::Http {
+                authority: String::from("connect.rom.miui.com"),
+                path:      String::from("/generate_204"),
+            })
+        );
+        assert_eq!(
+            "http://10.0.0.1:8080".parse::<ProbeTarget>().unwrap().to_string(),
+            "http://10.0.0.1:8080/"
+        );
+        assert_eq!("1.1.1.1:53".parse(), Ok(ProbeTarget::Tcp(String::from("1.1.1.1:53"))));
+        assert_eq!(
+            "tcp://example.com:443".parse(),
+            Ok(ProbeTarget::Tcp(String::from("example.com:443")))
+        );
+        assert_eq!(
+            "https://www.gstatic.com/generate_204".parse::<ProbeTarget>(),
+            Err(ProbeError::UnsupportedScheme(String::from("https")))
+        );
+        assert!("example.com".parse::<ProbeTarget>().is_err());
+        assert!("http:///generate_204".parse::<ProbeTarget>().is_err());
+    }
+
+    #[test]
+    fn test_probe_check() {
+        let timeout = Duration::from_secs(2);
+        let address = answer_probe("HTTP/1.1 204 No Content\r\n\r\n");
+        let target = ProbeTarget::http(&format!("http://{}/generate_204", address)).unwrap();
+        assert!(target.check(timeout).is_reachable());
+
+        let address = answer_probe(
+            "HTTP/1.1 302 Found\r\nLocation: http://10.0.0.1/a70.htm\r\nContent-Length: 0\r\n\r\n",
+        );
+        let target = ProbeTarget::http(&format!("http://{}/generate_204", address)).unwrap();
+        match target.check(timeout) {
+            ProbeOutcome::Intercepted { status, location } => {
+                assert_eq!(status, 302);
+                assert_eq!(location.as_deref(), Some("http://10.0.0.1/a70.htm"));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // accepted but never answered
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = silent.local_addr().unwrap();
+        let target = ProbeTarget::http(&format!("http://{}/generate_204", address)).unwrap();
+        assert!(matches!(
+            target.check(Duration::from_millis(100)),
+            ProbeOutcome::Unreachable(_)
+        ));
+        assert!(ProbeTarget::tcp(&address.to_string())
+            .unwrap()
+            .check(timeout)
+            .is_reachable());
+        drop(silent);
+        assert!(!ProbeTarget::tcp(&address.to_string())
+            .unwrap()
+            .check(timeout)
+            .is_reachable());
+    }
+
+    #[test]
+    fn test_prober() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mut prober = Prober::new(ProbeTarget::tcp(&address).unwrap());
+        prober.interval(Duration::from_secs(30)).max_failures(2);
+
+        // nothing happens until resumed and due
+        let now = Instant::now();
+        assert!(prober.poll(now).is_none());
+        prober.resume(now);
+        assert!(prober.poll(now).is_none());
+
+        let now = now + Duration::from_secs(30);
+        let result = wait_for_result(&mut prober, now);
+        assert_eq!((result.failures, result.lost), (1, false));
+        let now = now + Duration::from_secs(30);
+        let result = wait_for_result(&mut prober, now);
+        assert_eq!((result.failures, result.lost), (2, true));
+
+        // one interception is enough
+        let address = answer_probe("HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let mut prober = Prober::new(
+            ProbeTarget::http(&format!("http://{}/generate_204", address)).unwrap(),
+        );
+        let now = Instant::now();
+        prober.resume(now);
+        let result = wait_for_result(&mut prober, now + Duration::from_secs(60));
+        assert!(result.lost);
+        assert!(matches!(result.outcome, ProbeOutcome::Intercepted { status: 200, .. }));
+
+        prober.pause();
+        assert!(prober.poll(now + Duration::from_secs(600)).is_none());
+    }
+}
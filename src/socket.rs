@@ -0,0 +1,192 @@
+use std::io;
+use std::mem;
+use std::net::{AddrParseError, Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+use log::warn;
+
+/// The port the official client sends from, some servers insist on it.
+pub const DEFAULT_PORT: u16 = 61440;
+
+/// How the UDP socket talking to the server is set up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOptions {
+    address:            SocketAddrV4,
+    device:             Option<String>,
+    ephemeral_fallback: bool,
+    reuse_address:      bool,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        SocketOptions {
+            address:            SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DEFAULT_PORT),
+            device:             None,
+            ephemeral_fallback: false,
+            reuse_address:      true,
+        }
+    }
+
+    /// Local address and port, port 0 picks an ephemeral one right away.
+    pub fn address(&mut self, address: SocketAddrV4) -> &mut Self {
+        self.address = address;
+        self
+    }
+
+    /// Send and receive only through this interface (SO_BINDTODEVICE), needs CAP_NET_RAW.
+    pub fn device(&mut self, device: Option<String>) -> &mut Self {
+        self.device = device;
+        self
+    }
+
+    /// Bind an ephemeral port when the configured one is taken, for servers that accept it.
+    pub fn ephemeral_fallback(&mut self, ephemeral_fallback: bool) -> &mut Self {
+        self.ephemeral_fallback = ephemeral_fallback;
+        self
+    }
+
+    /// Set SO_REUSEADDR, so that a restart does not fail while the previous socket lingers.
+    /// On by default, the catch is that a second instance may bind the port as well and
+    /// the two take each other's responses, `--pidfile` rules that out.
+    pub fn reuse_address(&mut self, reuse_address: bool) -> &mut Self {
+        self.reuse_address = reuse_address;
+        self
+    }
+
+    pub fn bind(&self) -> io::Result<UdpSocket> {
+        match self.bind_to(self.address) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && self.ephemeral_fallback => {
+                warn!(address:% = self.address, error:% = e; "falling back to an ephemeral port");
+                self.bind_to(SocketAddrV4::new(*self.address.ip(), 0))
+            }
+            result => result,
+        }
+    }
+
+    fn bind_to(&self, address: SocketAddrV4) -> io::Result<UdpSocket> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        if self.reuse_address {
+            let enabled: libc::c_int = 1;
+            setsockopt(
+                &socket,
+                libc::SO_REUSEADDR,
+                &enabled as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>(),
+            )?;
+        }
+        if let Some(ref device) = self.device {
+            let name = device.as_ptr().cast();
+            if let Err(e) = setsockopt(&socket, libc::SO_BINDTODEVICE, name, device.len()) {
+                return Err(io::Error::new(e.kind(), format!("cannot bind to {}: {}", device, e)));
+            }
+        }
+
+        let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+        sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+        sockaddr.sin_port = address.port().to_be();
+        sockaddr.sin_addr.s_addr = u32::from(*address.ip()).to_be();
+        let bound = unsafe {
+            libc::bind(
+                fd,
+                &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if bound == -1 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(e.kind(), format!("cannot bind {}: {}", address, e)));
+        }
+        Ok(UdpSocket::from(socket))
+    }
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions::new()
+    }
+}
+
+fn setsockopt(
+    socket: &OwnedFd,
+    name: libc::c_int,
+    value: *const libc::c_void,
+    length: usize,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            name,
+            value,
+            length as libc::socklen_t,
+        )
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// `address[:port]`, the port defaults to `DEFAULT_PORT`.
+pub fn parse_bind_address(s: &str) -> Result<SocketAddrV4, AddrParseError> {
+    s.parse::<SocketAddrV4>()
+        .or_else(|_| s.parse::<Ipv4Addr>().map(|ip| SocketAddrV4::new(ip, DEFAULT_PORT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bind_address() {
+        assert_eq!(
+            parse_bind_address("10.0.0.2"),
+            Ok(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_bind_address("10.0.0.2:0"),
+            Ok(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 0))
+        );
+        assert!(parse_bind_address("10.0.0.2:port").is_err());
+        assert!(parse_bind_address("[::1]:61440").is_err());
+    }
+
+    #[test]
+    fn test_socket_bind() {
+        let address = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        let first = SocketOptions::new().address(address).bind().unwrap();
+        let port = first.local_addr().unwrap().port();
+        assert_ne!(port, 0);
+
+        // both sockets set SO_REUSEADDR, like an old and a new instance
+        let mut options = SocketOptions::new();
+        options.address(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
+        let second = options.bind().unwrap();
+        assert_eq!(second.local_addr().unwrap().port(), port);
+
+        // without it the port stays taken
+        assert_eq!(
+            options.reuse_address(false).bind().unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // a plain socket does not share its port
+        let plain = UdpSocket::bind(address).unwrap();
+        let taken = plain.local_addr().unwrap().port();
+        options.address(SocketAddrV4::new(Ipv4Addr::LOCALHOST, taken));
+        assert_eq!(options.bind().unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        let fallback = options.ephemeral_fallback(true).bind().unwrap();
+        assert_ne!(fallback.local_addr().unwrap().port(), taken);
+
+        let missing = SocketOptions::new()
+            .address(address)
+            .device(Some(String::from("drcom-missing")))
+            .bind();
+        assert!(missing.is_err());
+    }
+}